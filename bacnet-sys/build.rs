@@ -14,14 +14,55 @@ impl bindgen::callbacks::ParseCallbacks for IgnoreMacros {
 }
 
 fn main() {
-    let mut dir = cmake::Config::new("bacnet-stack")
+    // Defines for both the library and the bindings
+    let mut defines = vec!["-DBBMD_CLIENT_ENABLED=1".to_string()]; // foreign device registration
+    if env::var("CARGO_FEATURE_BBMD").is_ok() {
+        // Acting as a BBMD: the stack keeps a BDT and FDT, and forwards broadcasts
+        defines.push("-DBBMD_ENABLED=1".into());
+    }
+    // The max-APDU-length-accepted of the local device, which sizes the stack's buffers and is
+    // what it announces in I-Am and reads back as the Device object's property
+    println!("cargo:rerun-if-env-changed=BACNET_MAX_APDU");
+    if let Ok(max_apdu) = env::var("BACNET_MAX_APDU") {
+        match max_apdu.parse::<u16>() {
+            Ok(max_apdu @ 50..=1476) => defines.push(format!("-DMAX_APDU={}", max_apdu)),
+            _ => panic!("BACNET_MAX_APDU must be 50 to 1476, not {}", max_apdu),
+        }
+    }
+    let bip6 = env::var("CARGO_FEATURE_BIP6").is_ok();
+    if bip6 {
+        defines.push("-DBACDL_BIP6=1".into());
+    }
+    let mstp = env::var("CARGO_FEATURE_MSTP").is_ok();
+    if mstp {
+        defines.push("-DBACDL_MSTP=1".into());
+    }
+    let ethernet = env::var("CARGO_FEATURE_ETHERNET").is_ok();
+    if ethernet {
+        defines.push("-DBACDL_ETHERNET=1".into());
+    }
+    if bip6 || mstp || ethernet {
+        // BACnet/IP is always built, with more datalinks the one to use is picked at runtime
+        // (datalink_set())
+        defines.extend(["-DBACDL_BIP=1".into(), "-DBACDL_MULTIPLE=1".into()]);
+    }
+
+    let mut config = cmake::Config::new("bacnet-stack");
+    config
         .define("BACNET_STACK_BUILD_APPS", "OFF")
-        .define("BAC_ROUTING", "OFF") // not sure what this implies
+        // Makes the stack a gateway to virtual devices on a virtual network, routes to remote
+        // networks are found without it (see bacnet/src/router.rs)
+        .define("BAC_ROUTING", "OFF")
         .define("BACNET_BUILD_PIFACE_APP", "OFF")
         .define("BACAPP_PRINT_ENABLED", "ON")
         .define("BACDL_BIP", "ON")
-        .define("BACDL_ETHERNET", "OFF")
-        .build();
+        .define("BACDL_BIP6", if bip6 { "ON" } else { "OFF" })
+        .define("BACDL_MSTP", if mstp { "ON" } else { "OFF" })
+        .define("BACDL_ETHERNET", if ethernet { "ON" } else { "OFF" });
+    for define in &defines {
+        config.cflag(define);
+    }
+    let mut dir = config.build();
 
     dir.push("build");
     // println!("cargo:warning={}", dir.display());
@@ -57,6 +98,7 @@ fn main() {
 
     let bindings = bindgen::Builder::default()
         .clang_arg("-Ibacnet-stack/src")
+        .clang_args(&defines)
         //.clang_arg("-I.")
         .header("wrapper.h")
         .parse_callbacks(Box::new(ignored_macros))
@@ -17,6 +17,17 @@
 #include "bacnet-stack/src/bacnet/datalink/datalink.h"
 #include "bacnet-stack/src/bacnet/version.h"
 #include "bacnet-stack/src/bacnet/datalink/dlenv.h"
+#include "bacnet-stack/src/bacnet/basic/bbmd/h_bbmd.h"
+#if defined(BACDL_BIP6)
+#include "bacnet-stack/src/bacnet/datalink/bip6.h"
+#include "bacnet-stack/src/bacnet/basic/bbmd6/vmac.h"
+#endif
+#if defined(BACDL_MSTP)
+#include "bacnet-stack/src/bacnet/datalink/dlmstp.h"
+#endif
+#if defined(BACDL_ETHERNET)
+#include "bacnet-stack/src/bacnet/datalink/ethernet.h"
+#endif
 #include "bacnet-stack/src/bacnet/bacenum.h"
 // #include "bacnet-stack/src/bacnet/bacport.h"
 
@@ -85,25 +96,19 @@
 // #include "bacnet-stack/src/bacnet/datalink/arcnet.h"
 // #include "bacnet-stack/src/bacnet/datalink/bacsec.h"
 // #include "bacnet-stack/src/bacnet/datalink/bip.h"
-// #include "bacnet-stack/src/bacnet/datalink/bip6.h"
 // #include "bacnet-stack/src/bacnet/datalink/bvlc.h"
 // #include "bacnet-stack/src/bacnet/datalink/bvlc6.h"
 // #include "bacnet-stack/src/bacnet/datalink/cobs.h"
 // #include "bacnet-stack/src/bacnet/datalink/crc.h"
 // #include "bacnet-stack/src/bacnet/datalink/datalink.h"
 // #include "bacnet-stack/src/bacnet/datalink/dlenv.h"
-// #include "bacnet-stack/src/bacnet/datalink/dlmstp.h"
-// #include "bacnet-stack/src/bacnet/datalink/ethernet.h"
 // #include "bacnet-stack/src/bacnet/datalink/mstp.h"
 // #include "bacnet-stack/src/bacnet/datalink/mstpdef.h"
 // #include "bacnet-stack/src/bacnet/datalink/mstptext.h"
 
 // #include "bacnet-stack/src/bacnet/basic/services.h"
 
-// #include "bacnet-stack/src/bacnet/basic/bbmd/h_bbmd.h"
-
 // #include "bacnet-stack/src/bacnet/basic/bbmd6/h_bbmd6.h"
-// #include "bacnet-stack/src/bacnet/basic/bbmd6/vmac.h"
 
 // #include "bacnet-stack/src/bacnet/basic/binding/address.h"
 
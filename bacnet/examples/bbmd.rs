@@ -0,0 +1,62 @@
+extern crate bacnet;
+extern crate structopt;
+
+use bacnet::{bbmd::BdtEntry, BACnetNetwork, DatalinkConfig};
+use std::{
+    net::{Ipv4Addr, SocketAddrV4},
+    thread,
+    time::Duration,
+};
+use structopt::StructOpt;
+
+/// Acts as a BBMD, printing the foreign devices registered with us every now and then
+#[derive(StructOpt, Debug)]
+#[structopt(name = "bbmd")]
+struct Opt {
+    /// The BBMDs of the network (including ourselves), as <ip>:<port>
+    bdt: Vec<SocketAddrV4>,
+    #[structopt(long)]
+    interface: Option<String>,
+    #[structopt(long, default_value = "47808")]
+    port: u16,
+}
+
+fn main() {
+    pretty_env_logger::init();
+    let opt = Opt::from_args();
+
+    let mut config = DatalinkConfig::new().port(opt.port);
+    if let Some(interface) = opt.interface {
+        config = config.interface(interface);
+    }
+    let network = BACnetNetwork::start(config).expect("failed to start the network");
+
+    // Broadcasts are sent straight to the other BBMDs
+    let bdt = opt
+        .bdt
+        .into_iter()
+        .map(|address| BdtEntry {
+            address,
+            mask: Ipv4Addr::BROADCAST,
+        })
+        .collect();
+    network.set_bdt(bdt).expect("failed to set the BDT");
+
+    loop {
+        match network.fdt() {
+            Ok(fdt) => {
+                println!("{} foreign device(s)", fdt.len());
+                for entry in fdt {
+                    println!(
+                        "  {:21}  ttl {:5}  remaining {:5}",
+                        entry.address,
+                        entry.ttl.as_secs(),
+                        entry.remaining.as_secs()
+                    );
+                }
+            }
+            Err(err) => eprintln!("failed to read the FDT... {}", err),
+        }
+        thread::sleep(Duration::from_secs(10));
+    }
+}
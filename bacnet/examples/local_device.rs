@@ -0,0 +1,124 @@
+use bacnet::{
+    device::{PropertyError, PropertyList, PropertyResult},
+    value::BACnetValue,
+    BACnetObject, LocalDevice, LocalObject, ObjectPropertyId, ObjectType,
+};
+use bacnet_sys::{
+    BACnetObjectType_OBJECT_ANALOG_INPUT, BACnetObjectType_OBJECT_BINARY_OUTPUT,
+    BACnetObjectType_OBJECT_MULTI_STATE_VALUE, BACnetObjectType_OBJECT_PROPRIETARY_MIN,
+    BACNET_PROPERTY_ID_PROP_PRESENT_VALUE,
+};
+use std::{thread, time::Duration};
+
+// degrees-Celsius
+const UNITS_DEGREES_CELSIUS: u32 = 62;
+
+// A proprietary object, counting the times it's been read
+struct ReadCounter {
+    reads: std::cell::Cell<u64>,
+}
+
+impl BACnetObject for ReadCounter {
+    fn object_type(&self) -> ObjectType {
+        BACnetObjectType_OBJECT_PROPRIETARY_MIN
+    }
+
+    fn object_instance(&self) -> u32 {
+        1
+    }
+
+    fn object_name(&self) -> String {
+        "Read counter".to_string()
+    }
+
+    fn property_list(&self) -> PropertyList {
+        PropertyList {
+            required: vec![BACNET_PROPERTY_ID_PROP_PRESENT_VALUE],
+            ..Default::default()
+        }
+    }
+
+    fn read_property(&self, property: ObjectPropertyId) -> PropertyResult<BACnetValue> {
+        match property {
+            BACNET_PROPERTY_ID_PROP_PRESENT_VALUE => {
+                self.reads.set(self.reads.get() + 1);
+                Ok(BACnetValue::Uint(self.reads.get()))
+            }
+            _ => Err(PropertyError::UnknownProperty),
+        }
+    }
+}
+
+/// Hosts a few objects, which other devices can read, write and subscribe to. The temperature goes
+/// up and down by itself, writes to the fan are printed.
+fn main() {
+    pretty_env_logger::init();
+    let device = match LocalDevice::builder()
+        .instance(1234)
+        .object_name("Rust gateway")
+        .model_name("local_device example")
+        .build()
+    {
+        Ok(device) => device,
+        Err(err) => {
+            eprintln!("failed to set up the device... {}", err);
+            return;
+        }
+    };
+
+    let objects = [
+        LocalObject::new(
+            BACnetObjectType_OBJECT_ANALOG_INPUT,
+            1,
+            "Outside temperature",
+        )
+        .map(|object| object.units(UNITS_DEGREES_CELSIUS).cov_increment(1.0)),
+        LocalObject::new(BACnetObjectType_OBJECT_BINARY_OUTPUT, 1, "Fan").map(|object| {
+            object.on_write(|value, priority| {
+                println!("fan commanded to {:?} at priority {:?}", value, priority);
+                Ok(())
+            })
+        }),
+        LocalObject::new(BACnetObjectType_OBJECT_MULTI_STATE_VALUE, 1, "Mode").map(|object| {
+            object
+                .number_of_states(3)
+                .description("Off, heating, cooling")
+        }),
+    ];
+    for object in objects {
+        if let Err(err) = object.and_then(|object| device.add_object(object)) {
+            eprintln!("failed to add object... {}", err);
+            return;
+        }
+    }
+
+    let counter = ReadCounter {
+        reads: Default::default(),
+    };
+    if let Err(err) = device.add_object(counter) {
+        eprintln!("failed to add object... {}", err);
+        return;
+    }
+
+    // Let workstations know about the temperature, rather than wait for them to look for it
+    if let Err(err) = device.announce_i_have(BACnetObjectType_OBJECT_ANALOG_INPUT, 1) {
+        eprintln!("failed to announce the temperature... {}", err);
+    }
+
+    let mut temperature = 12.0;
+    loop {
+        temperature = if temperature > 20.0 {
+            12.0
+        } else {
+            temperature + 0.5
+        };
+        if let Err(err) = device.set_present_value(
+            BACnetObjectType_OBJECT_ANALOG_INPUT,
+            1,
+            BACnetValue::Real(temperature),
+        ) {
+            eprintln!("failed to update temperature... {}", err);
+        }
+        thread::sleep(Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,47 @@
+extern crate bacnet;
+extern crate structopt;
+
+use bacnet::bbmd::{read_bdt, read_fdt};
+use std::{net::SocketAddrV4, time::Duration};
+use structopt::StructOpt;
+
+/// Reads the Broadcast Distribution Table and Foreign Device Table of a BBMD
+#[derive(StructOpt, Debug)]
+#[structopt(name = "readbdt")]
+struct Opt {
+    /// The BBMD, as <ip>:<port>
+    bbmd: SocketAddrV4,
+    #[structopt(short = "t", long, default_value = "3")]
+    timeout: u64,
+}
+
+fn main() {
+    pretty_env_logger::init();
+    let opt = Opt::from_args();
+    let timeout = Duration::from_secs(opt.timeout);
+
+    match read_bdt(opt.bbmd, timeout) {
+        Ok(bdt) => {
+            println!("Broadcast Distribution Table:");
+            for entry in bdt {
+                println!("  {:21}  mask {}", entry.address, entry.mask);
+            }
+        }
+        Err(err) => eprintln!("failed to read the BDT... {}", err),
+    }
+
+    match read_fdt(opt.bbmd, timeout) {
+        Ok(fdt) => {
+            println!("Foreign Device Table:");
+            for entry in fdt {
+                println!(
+                    "  {:21}  ttl {:5}  remaining {:5}",
+                    entry.address,
+                    entry.ttl.as_secs(),
+                    entry.remaining.as_secs()
+                );
+            }
+        }
+        Err(err) => eprintln!("failed to read the FDT... {}", err),
+    }
+}
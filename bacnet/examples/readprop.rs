@@ -20,6 +20,11 @@ struct Opt {
     dadr: u8,
     #[structopt(long, default_value = "47808")]
     port: u16,
+    /// How long to wait for a response (in millis) before trying again
+    #[structopt(long, default_value = "3000")]
+    apdu_timeout: u64,
+    #[structopt(long, default_value = "3")]
+    retries: u8,
 
     #[structopt(short = "t", long, default_value = "analog-value", parse(try_from_str = parse_object_type))]
     object_type: BACNET_OBJECT_TYPE,
@@ -79,6 +84,8 @@ fn main() {
         .dnet(opt.dnet)
         .dadr(opt.dadr)
         .port(opt.port)
+        .apdu_timeout(std::time::Duration::from_millis(opt.apdu_timeout))
+        .retries(opt.retries)
         .build();
 
     match server.connect() {
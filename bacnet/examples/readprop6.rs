@@ -0,0 +1,81 @@
+extern crate bacnet;
+extern crate structopt;
+
+use bacnet::{whois::WhoIs, BACnetNetwork, BACnetServer, DatalinkConfig};
+use bacnet_sys::{BACNET_OBJECT_TYPE, BACNET_PROPERTY_ID_PROP_PRESENT_VALUE};
+use std::{net::Ipv6Addr, time::Duration};
+use structopt::StructOpt;
+
+/// Discovers the devices on BACnet/IPv6, and reads the present value of an object of one of them
+#[derive(StructOpt, Debug)]
+#[structopt(name = "readprop6")]
+struct Opt {
+    /// Network interface to use
+    #[structopt(long)]
+    interface: Option<String>,
+    #[structopt(long, default_value = "0")]
+    device_id: u32,
+    /// The device's address, if it doesn't answer the Who-Is
+    #[structopt(long)]
+    ipv6: Option<Ipv6Addr>,
+    #[structopt(long, default_value = "47808")]
+    port: u16,
+    /// The device's VMAC, if it isn't its device id
+    #[structopt(long)]
+    vmac: Option<u32>,
+
+    #[structopt(short = "t", long, default_value = "2")]
+    object_type: BACNET_OBJECT_TYPE,
+    #[structopt(short = "i", long, default_value = "22")]
+    object_instance: u32,
+}
+
+fn main() {
+    pretty_env_logger::init();
+    let opt = Opt::from_args();
+
+    let mut config = DatalinkConfig::bip6();
+    if let Some(interface) = opt.interface {
+        config = config.interface(interface);
+    }
+    if let Err(err) = BACnetNetwork::start(config) {
+        eprintln!("failed to start BACnet/IPv6... {}", err);
+        return;
+    }
+
+    let mut vmac = opt.vmac;
+    match WhoIs::new().timeout(Duration::from_secs(1)).execute() {
+        Ok(devices) => {
+            for dev in devices {
+                println!("found device {:9}  VMAC {}", dev.device_id, dev.mac_addr);
+                // The stack knows where a device that answered is, its VMAC is enough
+                if let [a, b, c] = *dev.mac_addr.as_bytes() {
+                    if dev.device_id == opt.device_id && vmac.is_none() {
+                        vmac = Some(u32::from_be_bytes([0, a, b, c]));
+                    }
+                }
+            }
+        }
+        Err(err) => eprintln!("failed to discover devices... {}", err),
+    }
+
+    let mut builder = BACnetServer::builder()
+        .device_id(opt.device_id)
+        .port(opt.port);
+    if let Some(ipv6) = opt.ipv6 {
+        builder = builder.ipv6(ipv6);
+    }
+    let mut server = builder.vmac(vmac.unwrap_or(opt.device_id)).build();
+
+    match server.connect() {
+        Ok(()) => match server.read_prop(
+            opt.object_type,
+            opt.object_instance,
+            BACNET_PROPERTY_ID_PROP_PRESENT_VALUE,
+        ) {
+            Ok(value) => println!("present value {:?}", value),
+            Err(err) => eprintln!("failed to read property: {}", err),
+        },
+        Err(err) => eprintln!("failed to connect to device... {}", err),
+    }
+}
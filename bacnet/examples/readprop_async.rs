@@ -0,0 +1,119 @@
+extern crate bacnet;
+extern crate structopt;
+
+use bacnet::AsyncBACnetServer;
+use bacnet_sys::{
+    bactext_object_type_strtol, bactext_property_strtol, BACNET_OBJECT_TYPE, BACNET_PROPERTY_ID,
+};
+use std::sync::Arc;
+use structopt::StructOpt;
+
+/// Reads a property from a number of devices, with all the reads going on at once
+#[derive(StructOpt, Debug)]
+#[structopt(name = "readprop_async")]
+struct Opt {
+    /// The devices to read from, as <device-id>@<ip>
+    #[structopt(required = true, parse(try_from_str = parse_device))]
+    devices: Vec<(u32, std::net::Ipv4Addr)>,
+    #[structopt(long, default_value = "47808")]
+    port: u16,
+
+    #[structopt(short = "t", long, default_value = "analog-value", parse(try_from_str = parse_object_type))]
+    object_type: BACNET_OBJECT_TYPE,
+    #[structopt(short = "i", long, default_value = "22")]
+    object_instance: u32,
+    #[structopt(short = "p", long, default_value = "present-value", parse(try_from_str = parse_property))]
+    property: u32,
+
+    #[structopt(short = "n", long, default_value = "1")]
+    number_of_reads: usize,
+}
+
+fn parse_device(src: &str) -> Result<(u32, std::net::Ipv4Addr), String> {
+    let (device_id, ip) = src
+        .split_once('@')
+        .ok_or_else(|| format!("Expected <device-id>@<ip>, got '{}'", src))?;
+    Ok((
+        device_id.parse().map_err(|e| format!("{}", e))?,
+        ip.parse().map_err(|e| format!("{}", e))?,
+    ))
+}
+
+fn parse_object_type(src: &str) -> Result<BACNET_OBJECT_TYPE, String> {
+    if let Ok(t) = src.parse() {
+        Ok(t)
+    } else {
+        let mut found_index = 0;
+        if unsafe {
+            bactext_object_type_strtol(
+                src.as_ptr() as *const ::std::os::raw::c_char,
+                &mut found_index,
+            )
+        } {
+            Ok(found_index)
+        } else {
+            Err(format!("Couldn't parse input '{}' as object-type", src))
+        }
+    }
+}
+
+fn parse_property(src: &str) -> Result<BACNET_PROPERTY_ID, String> {
+    if let Ok(t) = src.parse() {
+        Ok(t)
+    } else {
+        let mut found_index = 0;
+        if unsafe {
+            bactext_property_strtol(
+                src.as_ptr() as *const ::std::os::raw::c_char,
+                &mut found_index,
+            )
+        } {
+            Ok(found_index)
+        } else {
+            Err(format!("Couldn't parse input '{}' as property", src))
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    pretty_env_logger::init();
+    let opt = Opt::from_args();
+
+    let mut servers = vec![];
+    for &(device_id, ip) in &opt.devices {
+        let mut server: AsyncBACnetServer = bacnet::BACnetServer::builder()
+            .device_id(device_id)
+            .ip(ip)
+            .port(opt.port)
+            .build_async();
+        match server.connect().await {
+            Ok(()) => servers.push(Arc::new(server)),
+            Err(err) => eprintln!("failed to connect to device {}... {}", device_id, err),
+        }
+    }
+
+    let mut reads = vec![];
+    for server in &servers {
+        for _ in 0..opt.number_of_reads {
+            let server = server.clone();
+            let (object_type, object_instance, property) =
+                (opt.object_type, opt.object_instance, opt.property);
+            reads.push(tokio::spawn(async move {
+                let r = server
+                    .read_prop(object_type, object_instance, property)
+                    .await;
+                (server.device_id, r)
+            }));
+        }
+    }
+
+    for read in reads {
+        match read.await.unwrap() {
+            (device_id, Ok(value)) => println!("device {}: result {:?}", device_id, value),
+            (device_id, Err(err)) => {
+                eprintln!("device {}: failed to read property: {}", device_id, err)
+            }
+        }
+    }
+}
@@ -0,0 +1,78 @@
+extern crate bacnet;
+extern crate structopt;
+
+use bacnet::{whois::WhoIs, BACnetNetwork, BACnetServer, DatalinkConfig};
+use bacnet_sys::{BACNET_OBJECT_TYPE, BACNET_PROPERTY_ID_PROP_PRESENT_VALUE};
+use std::time::Duration;
+use structopt::StructOpt;
+
+/// Discovers the devices on BACnet/Ethernet, and reads the present value of an object of one of
+/// them. Needs CAP_NET_RAW (or root)
+#[derive(StructOpt, Debug)]
+#[structopt(name = "readprop_ethernet")]
+struct Opt {
+    /// Network interface to use
+    #[structopt(long, default_value = "eth0")]
+    interface: String,
+
+    #[structopt(long, default_value = "0")]
+    device_id: u32,
+    /// The device's MAC address, as aa:bb:cc:dd:ee:ff
+    #[structopt(long, parse(try_from_str = parse_mac))]
+    mac: [u8; 6],
+    #[structopt(short = "t", long, default_value = "2")]
+    object_type: BACNET_OBJECT_TYPE,
+    #[structopt(short = "i", long, default_value = "22")]
+    object_instance: u32,
+}
+
+fn parse_mac(src: &str) -> Result<[u8; 6], String> {
+    let mut mac = [0u8; 6];
+    let mut octets = src.split(':');
+    for octet in mac.iter_mut() {
+        *octet = octets
+            .next()
+            .and_then(|octet| u8::from_str_radix(octet, 16).ok())
+            .ok_or_else(|| format!("Couldn't parse input '{}' as MAC address", src))?;
+    }
+    if octets.next().is_some() {
+        return Err(format!("Couldn't parse input '{}' as MAC address", src));
+    }
+    Ok(mac)
+}
+
+fn main() {
+    pretty_env_logger::init();
+    let opt = Opt::from_args();
+
+    if let Err(err) = BACnetNetwork::start(DatalinkConfig::ethernet(opt.interface)) {
+        eprintln!("failed to start BACnet/Ethernet... {}", err);
+        return;
+    }
+
+    match WhoIs::new().timeout(Duration::from_secs(1)).execute() {
+        Ok(devices) => {
+            for dev in devices {
+                println!("found device {:9}  MAC {}", dev.device_id, dev.mac_addr);
+            }
+        }
+        Err(err) => eprintln!("failed to discover devices... {}", err),
+    }
+
+    let mut server = BACnetServer::builder()
+        .device_id(opt.device_id)
+        .ethernet(opt.mac)
+        .build();
+
+    match server.connect() {
+        Ok(()) => match server.read_prop(
+            opt.object_type,
+            opt.object_instance,
+            BACNET_PROPERTY_ID_PROP_PRESENT_VALUE,
+        ) {
+            Ok(value) => println!("present value {:?}", value),
+            Err(err) => eprintln!("failed to read property: {}", err),
+        },
+        Err(err) => eprintln!("failed to connect to device... {}", err),
+    }
+}
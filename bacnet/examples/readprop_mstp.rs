@@ -0,0 +1,75 @@
+extern crate bacnet;
+extern crate structopt;
+
+use bacnet::{whois::WhoIs, BACnetNetwork, BACnetServer, DatalinkConfig};
+use bacnet_sys::{BACNET_OBJECT_TYPE, BACNET_PROPERTY_ID_PROP_PRESENT_VALUE};
+use std::time::Duration;
+use structopt::StructOpt;
+
+/// Discovers the devices on an MS/TP bus, and reads the present value of an object of one of
+/// them
+#[derive(StructOpt, Debug)]
+#[structopt(name = "readprop_mstp")]
+struct Opt {
+    /// Serial port the bus is on
+    #[structopt(long, default_value = "/dev/ttyUSB0")]
+    port: String,
+    #[structopt(long, default_value = "38400")]
+    baud_rate: u32,
+    /// Our own MAC address
+    #[structopt(long, default_value = "127")]
+    mac_address: u8,
+    #[structopt(long, default_value = "127")]
+    max_master: u8,
+
+    #[structopt(long, default_value = "0")]
+    device_id: u32,
+    /// The device's MAC address
+    #[structopt(long, default_value = "1")]
+    mac: u8,
+    #[structopt(short = "t", long, default_value = "2")]
+    object_type: BACNET_OBJECT_TYPE,
+    #[structopt(short = "i", long, default_value = "22")]
+    object_instance: u32,
+}
+
+fn main() {
+    pretty_env_logger::init();
+    let opt = Opt::from_args();
+
+    let config = DatalinkConfig::mstp(opt.port)
+        .baud_rate(opt.baud_rate)
+        .mac_address(opt.mac_address)
+        .max_master(opt.max_master);
+    if let Err(err) = BACnetNetwork::start(config) {
+        eprintln!("failed to start MS/TP... {}", err);
+        return;
+    }
+
+    // Joining the token ring takes a moment, and so does polling every master
+    match WhoIs::new().timeout(Duration::from_secs(5)).execute() {
+        Ok(devices) => {
+            for dev in devices {
+                println!("found device {:9}  MAC {}", dev.device_id, dev.mac_addr);
+            }
+        }
+        Err(err) => eprintln!("failed to discover devices... {}", err),
+    }
+
+    let mut server = BACnetServer::builder()
+        .device_id(opt.device_id)
+        .mstp(opt.mac)
+        .build();
+
+    match server.connect() {
+        Ok(()) => match server.read_prop(
+            opt.object_type,
+            opt.object_instance,
+            BACNET_PROPERTY_ID_PROP_PRESENT_VALUE,
+        ) {
+            Ok(value) => println!("present value {:?}", value),
+            Err(err) => eprintln!("failed to read property: {}", err),
+        },
+        Err(err) => eprintln!("failed to connect to device... {}", err),
+    }
+}
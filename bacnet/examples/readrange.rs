@@ -0,0 +1,119 @@
+extern crate bacnet;
+extern crate structopt;
+
+use bacnet::{read_range::ReadRange, BACnetServer};
+use bacnet_sys::{
+    bactext_object_type_strtol, bactext_property_strtol, BACNET_OBJECT_TYPE, BACNET_PROPERTY_ID,
+};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "readrange")]
+struct Opt {
+    #[structopt(long, default_value = "0")]
+    device_id: u32,
+    #[structopt(long, default_value = "192.168.10.96")]
+    ip: std::net::Ipv4Addr,
+    #[structopt(long, default_value = "0")]
+    dnet: u16,
+    #[structopt(long, default_value = "0")]
+    dadr: u8,
+    #[structopt(long, default_value = "47808")]
+    port: u16,
+
+    #[structopt(short = "t", long, default_value = "trend-log", parse(try_from_str = parse_object_type))]
+    object_type: BACNET_OBJECT_TYPE,
+    #[structopt(short = "i", long, default_value = "0")]
+    object_instance: u32,
+    #[structopt(short = "p", long, default_value = "log-buffer", parse(try_from_str = parse_property))]
+    property: u32,
+    /// Read by position, starting at this index
+    #[structopt(long)]
+    index: Option<u32>,
+    /// Read by sequence number, starting at this sequence number
+    #[structopt(long, conflicts_with = "index")]
+    sequence: Option<u32>,
+    /// The number of records to read (negative to read backwards)
+    #[structopt(short = "c", long, default_value = "10", allow_hyphen_values = true)]
+    count: i32,
+}
+
+fn parse_object_type(src: &str) -> Result<BACNET_OBJECT_TYPE, String> {
+    if let Ok(t) = src.parse() {
+        Ok(t)
+    } else {
+        let mut found_index = 0;
+        if unsafe {
+            bactext_object_type_strtol(
+                src.as_ptr() as *const ::std::os::raw::c_char,
+                &mut found_index,
+            )
+        } {
+            Ok(found_index)
+        } else {
+            Err(format!("Couldn't parse input '{}' as object-type", src))
+        }
+    }
+}
+
+fn parse_property(src: &str) -> Result<BACNET_PROPERTY_ID, String> {
+    if let Ok(t) = src.parse() {
+        Ok(t)
+    } else {
+        let mut found_index = 0;
+        if unsafe {
+            bactext_property_strtol(
+                src.as_ptr() as *const ::std::os::raw::c_char,
+                &mut found_index,
+            )
+        } {
+            Ok(found_index)
+        } else {
+            Err(format!("Couldn't parse input '{}' as property", src))
+        }
+    }
+}
+
+fn main() {
+    pretty_env_logger::init();
+    let opt = Opt::from_args();
+    let mut server = BACnetServer::builder()
+        .device_id(opt.device_id)
+        .ip(opt.ip)
+        .dnet(opt.dnet)
+        .dadr(opt.dadr)
+        .port(opt.port)
+        .build();
+
+    let range = match (opt.index, opt.sequence) {
+        (Some(index), _) => ReadRange::ByPosition {
+            index,
+            count: opt.count,
+        },
+        (None, Some(sequence)) => ReadRange::BySequence {
+            sequence,
+            count: opt.count,
+        },
+        (None, None) => ReadRange::All,
+    };
+
+    match server.connect() {
+        Ok(()) => {
+            match server.read_range(opt.object_type, opt.object_instance, opt.property, range) {
+                Ok(result) => {
+                    for record in &result.records {
+                        println!("{:?}", record);
+                    }
+                    println!(
+                        "{} records (first-item: {}, last-item: {}, more-items: {})",
+                        result.item_count, result.first_item, result.last_item, result.more_items
+                    );
+                }
+                Err(err) => eprintln!("failed to read range: {}", err),
+            }
+        }
+        Err(err) => {
+            eprintln!("failed to connect to device... {}", err);
+        }
+    }
+}
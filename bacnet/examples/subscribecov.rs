@@ -0,0 +1,120 @@
+extern crate bacnet;
+extern crate structopt;
+
+use bacnet::BACnetServer;
+use bacnet_sys::{
+    bactext_object_type_strtol, bactext_property_strtol, BACNET_OBJECT_TYPE, BACNET_PROPERTY_ID,
+};
+use std::time::Duration;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "subscribecov")]
+struct Opt {
+    #[structopt(long, default_value = "0")]
+    device_id: u32,
+    #[structopt(long, default_value = "192.168.10.96")]
+    ip: std::net::Ipv4Addr,
+    #[structopt(long, default_value = "0")]
+    dnet: u16,
+    #[structopt(long, default_value = "0")]
+    dadr: u8,
+    #[structopt(long, default_value = "47808")]
+    port: u16,
+
+    #[structopt(short = "t", long, default_value = "analog-value", parse(try_from_str = parse_object_type))]
+    object_type: BACNET_OBJECT_TYPE,
+    #[structopt(short = "i", long, default_value = "22")]
+    object_instance: u32,
+    /// Only monitor this property (SubscribeCOVProperty)
+    #[structopt(short = "p", long, parse(try_from_str = parse_property))]
+    property: Option<u32>,
+    /// The COV increment to use when monitoring a single property
+    #[structopt(long)]
+    increment: Option<f32>,
+    #[structopt(short = "c", long)]
+    confirmed: bool,
+    #[structopt(short = "l", long, default_value = "300")]
+    lifetime: u64,
+}
+
+fn parse_object_type(src: &str) -> Result<BACNET_OBJECT_TYPE, String> {
+    if let Ok(t) = src.parse() {
+        Ok(t)
+    } else {
+        let mut found_index = 0;
+        if unsafe {
+            bactext_object_type_strtol(
+                src.as_ptr() as *const ::std::os::raw::c_char,
+                &mut found_index,
+            )
+        } {
+            Ok(found_index)
+        } else {
+            Err(format!("Couldn't parse input '{}' as object-type", src))
+        }
+    }
+}
+
+fn parse_property(src: &str) -> Result<BACNET_PROPERTY_ID, String> {
+    if let Ok(t) = src.parse() {
+        Ok(t)
+    } else {
+        let mut found_index = 0;
+        if unsafe {
+            bactext_property_strtol(
+                src.as_ptr() as *const ::std::os::raw::c_char,
+                &mut found_index,
+            )
+        } {
+            Ok(found_index)
+        } else {
+            Err(format!("Couldn't parse input '{}' as property", src))
+        }
+    }
+}
+
+fn main() {
+    pretty_env_logger::init();
+    let opt = Opt::from_args();
+    let mut server = BACnetServer::builder()
+        .device_id(opt.device_id)
+        .ip(opt.ip)
+        .dnet(opt.dnet)
+        .dadr(opt.dadr)
+        .port(opt.port)
+        .build();
+
+    match server.connect() {
+        Ok(()) => {
+            let lifetime = Duration::from_secs(opt.lifetime);
+            let r = match opt.property {
+                Some(property) => server.subscribe_cov_property(
+                    opt.object_type,
+                    opt.object_instance,
+                    property,
+                    opt.increment,
+                    opt.confirmed,
+                    lifetime,
+                ),
+                None => server.subscribe_cov(
+                    opt.object_type,
+                    opt.object_instance,
+                    opt.confirmed,
+                    lifetime,
+                ),
+            };
+            match r {
+                Ok(subscription) => {
+                    for notification in subscription {
+                        println!("notification {:?}", notification);
+                    }
+                }
+                Err(err) => eprintln!("failed to subscribe: {}", err),
+            }
+        }
+        Err(err) => {
+            eprintln!("failed to connect to device... {}", err);
+        }
+    }
+}
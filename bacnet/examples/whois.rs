@@ -13,8 +13,12 @@ fn main() {
     println!("---------  ------------------------  ----  ------------------------  ----");
     for dev in devices {
         println!(
-            "{:9}  {:02X?}  {:4}  {:02X?}  {:4}",
-            dev.device_id, dev.mac_addr, dev.network_number, dev.addr, dev.max_apdu
+            "{:9}  {:24}  {:4}  {:24}  {:4}",
+            dev.device_id,
+            dev.mac_addr.to_string(),
+            dev.network_number,
+            dev.addr.to_string(),
+            dev.max_apdu
         );
     }
     println!(
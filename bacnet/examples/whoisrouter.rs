@@ -0,0 +1,21 @@
+use bacnet::router::WhoIsRouter;
+
+fn main() {
+    pretty_env_logger::init();
+    let routes = WhoIsRouter::new()
+        .timeout(std::time::Duration::from_secs(1))
+        .execute()
+        .unwrap();
+
+    let nroutes = routes.len();
+    println!("Network  Router");
+    println!("-------  --------------------");
+    for route in routes {
+        println!("{:7}  {}", route.network, route.router);
+    }
+    println!(
+        "Total: {} network{}",
+        nroutes,
+        if nroutes == 1 { "" } else { "s" }
+    );
+}
@@ -25,12 +25,19 @@ struct Opt {
     object_type: BACNET_OBJECT_TYPE,
     #[structopt(short = "i", long, default_value = "22")]
     object_instance: u32,
-    #[structopt(short = "v", long, default_value = "1", parse(from_str = parse_object_value))]
-    object_value: BACnetValue,
+    /// The value, a number, true/false, the name of an enumerated value or a string
+    #[structopt(short = "v", long, default_value = "1")]
+    object_value: String,
     #[structopt(short = "p", long, default_value = "present-value", parse(try_from_str = parse_property))]
     property: u32,
     #[structopt(short = "I", long, default_value = "4294967295")]
     index: u32,
+    /// Write priority (1-16), writes without a priority if not given
+    #[structopt(short = "P", long)]
+    priority: Option<u8>,
+    /// Relinquish the present value at the given priority instead of writing a value
+    #[structopt(short = "r", long)]
+    relinquish: bool,
 }
 
 fn parse_object_type(src: &str) -> Result<BACNET_OBJECT_TYPE, String> {
@@ -51,10 +58,6 @@ fn parse_object_type(src: &str) -> Result<BACNET_OBJECT_TYPE, String> {
     }
 }
 
-fn parse_object_value(src: &str) -> BACnetValue {
-    BACnetValue::from(src.to_string())
-}
-
 fn parse_property(src: &str) -> Result<BACNET_PROPERTY_ID, String> {
     if let Ok(t) = src.parse() {
         Ok(t)
@@ -86,22 +89,35 @@ fn main() {
 
     match server.connect() {
         Ok(()) => {
-            let object_value = if let BACnetValue::Bool(v) = opt.object_value {
+            // Names of enumerated values (like "active" or "degrees-celsius") are written as
+            // the value they name
+            let object_value = BACnetValue::from(opt.object_value);
+            let object_value = if let BACnetValue::Bool(v) = object_value {
                 BACnetValue::Enum(
                     if v { 1 } else { 0 },
                     Some((if v { "active" } else { "inactive" }).to_string()),
                 )
             } else {
-                opt.object_value
+                object_value
             };
 
-            let r = server.write_prop_at(
-                opt.object_type,
-                opt.object_instance,
-                object_value,
-                opt.property,
-                opt.index,
-            );
+            let r = match (opt.relinquish, opt.priority) {
+                (true, Some(priority)) => {
+                    server.relinquish(opt.object_type, opt.object_instance, priority)
+                }
+                (true, None) => {
+                    eprintln!("--relinquish requires a --priority");
+                    return;
+                }
+                (false, priority) => server.write_prop_at(
+                    opt.object_type,
+                    opt.object_instance,
+                    object_value,
+                    opt.property,
+                    opt.index,
+                    priority,
+                ),
+            };
             match r {
                 Ok(_) => println!("result {:?}", r),
                 Err(err) => eprintln!("failed to write property: {}", err),
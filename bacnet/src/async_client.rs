@@ -0,0 +1,258 @@
+//! An async client, for having many requests in flight at once
+//!
+//! Requests are handed to the `BACnetNetwork`, which sends them and completes their future once
+//! the response comes in. Up to MAX_TSM_TRANSACTIONS requests (to any number of devices) can be
+//! outstanding, further requests wait until one of those is done.
+
+use crate::{
+    disconnect, errors::Result, router, send_read_prop_multiple, send_read_range, send_write_prop,
+    send_write_prop_multiple, value::BACnetValue, BACnetErr, BACnetNetwork, Binding, DeviceId,
+    ObjectPropertyId, ObjectType, ReadAccessResult, ReadRange, ReadRangeResult, RequestInvokeId,
+    RequestOptions, Response,
+};
+use bacnet_sys::{
+    Send_Read_Property_Request, BACNET_ADDRESS, BACNET_ARRAY_ALL,
+    BACNET_PROPERTY_ID_PROP_PRESENT_VALUE,
+};
+use log::debug;
+use std::sync::Arc;
+
+/// A BACnet server that is talked to asynchronously
+///
+/// Every method can be called concurrently, e.g. from several tasks sharing the server in an
+/// `Arc`, each call being a separate request.
+#[derive(Debug, Clone)]
+pub struct AsyncBACnetServer {
+    pub device_id: u32,
+    addr: BACNET_ADDRESS,
+    options: RequestOptions,
+    binding: Arc<Binding>,
+}
+
+impl AsyncBACnetServer {
+    pub(crate) fn new(
+        device_id: u32,
+        addr: BACNET_ADDRESS,
+        options: RequestOptions,
+        binding: Arc<Binding>,
+    ) -> Self {
+        Self {
+            device_id,
+            addr,
+            options,
+            binding,
+        }
+    }
+
+    /// Binds the server, like `BACnetServer::connect()`. A server on a remote network is reached
+    /// through a router from the routing table, which isn't looked for here: run `WhoIsRouter`
+    /// first if the route may not be known yet.
+    pub async fn connect(&mut self) -> Result<()> {
+        if let Some(network) = self.binding.network {
+            router::lookup(network)?.set_mac(&mut self.addr);
+        }
+        let device_id = self.device_id;
+        let addr = self.addr;
+        let binding = Arc::clone(&self.binding);
+        let (found, _) = BACnetNetwork::get()?
+            .run_async(move || binding.bind_request(addr))
+            .await?;
+        debug!("found = {}", found);
+        if found {
+            Ok(())
+        } else {
+            Err(BACnetErr::NotConnected { device_id })
+        }
+    }
+
+    /// Reads the present value (property 85)
+    pub async fn read_prop_present_value(
+        &self,
+        object_type: ObjectType,
+        object_instance: u32,
+    ) -> Result<BACnetValue> {
+        self.read_prop(
+            object_type,
+            object_instance,
+            BACNET_PROPERTY_ID_PROP_PRESENT_VALUE,
+        )
+        .await
+    }
+
+    /// Reads a property
+    pub async fn read_prop(
+        &self,
+        object_type: ObjectType,
+        object_instance: u32,
+        property_id: ObjectPropertyId,
+    ) -> Result<BACnetValue> {
+        self.read_prop_at(object_type, object_instance, property_id, BACNET_ARRAY_ALL)
+            .await
+    }
+
+    /// Reads a property at a specific index
+    pub async fn read_prop_at(
+        &self,
+        object_type: ObjectType,
+        object_instance: u32,
+        property_id: ObjectPropertyId,
+        index: u32,
+    ) -> Result<BACnetValue> {
+        let response = self
+            .request(move |device_id| {
+                Ok(unsafe {
+                    Send_Read_Property_Request(
+                        device_id,
+                        object_type,
+                        object_instance,
+                        property_id,
+                        index,
+                    )
+                })
+            })
+            .await?;
+        match response {
+            Response::Value(value) => value,
+            _ => Err(BACnetErr::NoValue),
+        }
+    }
+
+    /// Reads several properties from several objects in a single request, like
+    /// `BACnetServer::read_prop_multiple()`
+    pub async fn read_prop_multiple(
+        &self,
+        objects: &[(ObjectType, u32, Vec<ObjectPropertyId>)],
+    ) -> Result<Vec<ReadAccessResult>> {
+        let objects = objects.to_vec();
+        match self
+            .request(move |device_id| send_read_prop_multiple(device_id, &objects))
+            .await?
+        {
+            Response::ReadAccess(results) => results,
+            _ => Err(BACnetErr::NoValue),
+        }
+    }
+
+    /// Reads a range of items from a list property, like `BACnetServer::read_range()`
+    pub async fn read_range(
+        &self,
+        object_type: ObjectType,
+        object_instance: u32,
+        property_id: ObjectPropertyId,
+        range: ReadRange,
+    ) -> Result<ReadRangeResult> {
+        let response = self
+            .request(move |device_id| {
+                Ok(send_read_range(
+                    device_id,
+                    object_type,
+                    object_instance,
+                    property_id,
+                    &range,
+                ))
+            })
+            .await?;
+        match response {
+            Response::ReadRange(result) => result,
+            _ => Err(BACnetErr::NoValue),
+        }
+    }
+
+    /// Writes the present value (property 85)
+    pub async fn write_prop_present_value(
+        &self,
+        object_type: ObjectType,
+        object_instance: u32,
+        value: BACnetValue,
+    ) -> Result<()> {
+        self.write_prop(
+            object_type,
+            object_instance,
+            value,
+            BACNET_PROPERTY_ID_PROP_PRESENT_VALUE,
+        )
+        .await
+    }
+
+    /// Writes a property
+    pub async fn write_prop(
+        &self,
+        object_type: ObjectType,
+        object_instance: u32,
+        value: BACnetValue,
+        property_id: ObjectPropertyId,
+    ) -> Result<()> {
+        self.write_prop_at(
+            object_type,
+            object_instance,
+            value,
+            property_id,
+            BACNET_ARRAY_ALL,
+            None,
+        )
+        .await
+    }
+
+    /// Writes a property at a specific index
+    ///
+    /// The priority must be between 1 and 16, `None` writes without a priority.
+    pub async fn write_prop_at(
+        &self,
+        object_type: ObjectType,
+        object_instance: u32,
+        value: BACnetValue,
+        property_id: ObjectPropertyId,
+        index: u32,
+        priority: Option<u8>,
+    ) -> Result<()> {
+        self.request(move |device_id| {
+            send_write_prop(
+                device_id,
+                object_type,
+                object_instance,
+                &value,
+                property_id,
+                index,
+                priority,
+            )
+        })
+        .await
+        .map(|_| ())
+    }
+
+    /// Writes several properties on several objects in a single request, like
+    /// `BACnetServer::write_prop_multiple()`
+    pub async fn write_prop_multiple(
+        &self,
+        writes: &[(ObjectType, u32, ObjectPropertyId, BACnetValue, Option<u8>)],
+    ) -> Result<()> {
+        let writes = writes.to_vec();
+        self.request(move |device_id| send_write_prop_multiple(device_id, &writes))
+            .await
+            .map(|_| ())
+    }
+
+    /// A handle on the same server, sending its requests with different timeouts and retries,
+    /// like `BACnetServer::with_options()`
+    pub fn with_options(&self, options: RequestOptions) -> Self {
+        Self {
+            options: options.or(self.options),
+            ..self.clone()
+        }
+    }
+
+    // Have the driver send a request, and wait for the response
+    async fn request(
+        &self,
+        send: impl FnMut(DeviceId) -> Result<RequestInvokeId> + Send + 'static,
+    ) -> Result<Response> {
+        BACnetNetwork::get()?
+            .request_async(self.device_id, self.options, send)
+            .await
+    }
+
+    /// Remove the server from the address cache, like `BACnetServer::disconnect()`
+    pub fn disconnect(&self) {
+        disconnect(self.device_id);
+    }
+}
@@ -0,0 +1,432 @@
+//! Working with BACnet Broadcast Management Devices (BBMDs)
+//!
+//! To reach devices on other IP subnets, we register with a BBMD as a foreign device. The BBMD
+//! then forwards the broadcasts on its network to us, and our broadcasts (like Who-Is) are sent to
+//! the BBMD for it to distribute. The registration has to be renewed before its time-to-live runs
+//! out, which the network does by itself.
+//!
+//! The Broadcast Distribution Table (BDT) and Foreign Device Table (FDT) of a BBMD can be read
+//! with `read_bdt()` and `read_fdt()`.
+//!
+//! With the `bbmd` feature, we can act as a BBMD ourselves: once the network has a BDT (see
+//! `BACnetNetwork::set_bdt()`) the stack forwards broadcasts to the other BBMDs in it, accepts
+//! foreign device registrations and answers requests for its tables.
+
+// Registration goes through the stack, as the BBMD has to know the address of the stack's socket.
+// The stack doesn't pass on the BVLC-Result to us though, it only remembers the last one it got.
+// So before sending, we have it forget the last one, and then wait for a new one to show up.
+//
+// Reading the tables doesn't need the stack at all, the BBMD answers on whatever socket asked. The
+// stack can't decode the answers for us either, so we do it all here.
+
+use crate::{errors::Result, BACnetErr, BACnetNetwork};
+#[cfg(feature = "bbmd")]
+use bacnet_sys::{
+    bvlc_bdt_list, bvlc_fdt_list, bvlc_maintenance_timer,
+    BACNET_IP_BROADCAST_DISTRIBUTION_TABLE_ENTRY, BACNET_IP_FOREIGN_DEVICE_TABLE_ENTRY,
+};
+use bacnet_sys::{
+    bvlc_get_function_code, bvlc_get_last_result, bvlc_handler, bvlc_register_with_bbmd,
+    BACNET_ADDRESS, BACNET_IP_ADDRESS,
+};
+use log::{debug, error, trace};
+use once_cell::sync::Lazy;
+use std::{
+    net::{Ipv4Addr, SocketAddrV4, UdpSocket},
+    sync::{mpsc, Mutex},
+    time::{Duration, Instant},
+};
+
+// BVLL message type and functions, see Annex J of the standard (and bvlc.h)
+const BVLL_TYPE_BACNET_IP: u8 = 0x81;
+const BVLC_RESULT: u8 = 0x00;
+const BVLC_READ_BROADCAST_DIST_TABLE: u8 = 0x02;
+const BVLC_READ_BROADCAST_DIST_TABLE_ACK: u8 = 0x03;
+const BVLC_READ_FOREIGN_DEVICE_TABLE: u8 = 0x06;
+const BVLC_READ_FOREIGN_DEVICE_TABLE_ACK: u8 = 0x07;
+
+// BVLC-Result codes
+const BVLC_RESULT_SUCCESSFUL_COMPLETION: u16 = 0x0000;
+const BVLC_RESULT_REGISTER_FOREIGN_DEVICE_NAK: u16 = 0x0030;
+// Not a result any BBMD sends, what the stack is left with when it forgets the last result
+const BVLC_RESULT_NONE: u16 = 0xFFFF;
+
+// How long to wait for a BBMD to answer
+const RESULT_TIMEOUT: Duration = Duration::from_secs(3);
+
+static REGISTRATION: Lazy<Mutex<Option<Registration>>> = Lazy::new(|| Mutex::new(None));
+
+// When the FDT was last aged
+#[cfg(feature = "bbmd")]
+static LAST_TICK: Lazy<Mutex<Instant>> = Lazy::new(|| Mutex::new(Instant::now()));
+
+type ReplyFn = Box<dyn FnOnce(Result<()>) + Send>;
+
+// Our registration as a foreign device
+struct Registration {
+    bbmd: SocketAddrV4,
+    ttl: u16,
+    renew_at: Instant,
+    waiting: Option<Waiting>,
+}
+
+// A registration request waiting for the BBMD to answer
+struct Waiting {
+    deadline: Instant,
+    reply: ReplyFn,
+}
+
+/// An entry of a Broadcast Distribution Table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BdtEntry {
+    /// The BBMD
+    pub address: SocketAddrV4,
+    /// The broadcast distribution mask, all ones for a BBMD that forwards broadcasts to us
+    /// directly instead of broadcasting them on its network
+    pub mask: Ipv4Addr,
+}
+
+/// An entry of a Foreign Device Table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FdtEntry {
+    /// The foreign device
+    pub address: SocketAddrV4,
+    /// The time-to-live the foreign device registered with
+    pub ttl: Duration,
+    /// How long until the entry is removed, unless the foreign device registers again
+    pub remaining: Duration,
+}
+
+impl BACnetNetwork {
+    /// Register with a BBMD as a foreign device
+    ///
+    /// The registration lasts for `ttl` (in seconds, up to 65535) and is renewed automatically.
+    /// Registering with another BBMD replaces the registration. Returns `BACnetErr::BvlcNak` if the
+    /// BBMD refuses us, like when it doesn't accept foreign devices.
+    pub fn register_foreign_device(&self, bbmd: SocketAddrV4, ttl: Duration) -> Result<()> {
+        let (tx, rx) = mpsc::channel();
+        self.run(move || {
+            register(
+                bbmd,
+                ttl,
+                Box::new(move |result| {
+                    let _ = tx.send(result);
+                }),
+            )
+        })?;
+        rx.recv().map_err(|_| BACnetErr::DriverStopped)?
+    }
+
+    /// The BBMD we're registered with as a foreign device, if any
+    pub fn foreign_device_bbmd(&self) -> Option<SocketAddrV4> {
+        REGISTRATION
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|registration| registration.bbmd)
+    }
+
+    /// Act as a BBMD with the given Broadcast Distribution Table
+    ///
+    /// The table holds every BBMD of the BACnet/IP network, including ourselves. An empty table
+    /// stops forwarding broadcasts to other BBMDs. Other BBMDs can change the table as well, with
+    /// Write-Broadcast-Distribution-Table.
+    #[cfg(feature = "bbmd")]
+    pub fn set_bdt(&self, bdt: Vec<BdtEntry>) -> Result<()> {
+        self.run(move || {
+            let entries = bdt_entries();
+            if bdt.len() > entries.len() {
+                return Err(BACnetErr::TableFull { max: entries.len() });
+            }
+            for (i, entry) in entries.into_iter().enumerate() {
+                let entry = unsafe { &mut *entry };
+                entry.valid = i < bdt.len();
+                if let Some(bdt_entry) = bdt.get(i) {
+                    entry.dest_address = ip_address(bdt_entry.address);
+                    entry.broadcast_mask.address = bdt_entry.mask.octets();
+                }
+            }
+            Ok(())
+        })?
+    }
+
+    /// Our own Broadcast Distribution Table, when acting as a BBMD
+    #[cfg(feature = "bbmd")]
+    pub fn bdt(&self) -> Result<Vec<BdtEntry>> {
+        self.run(|| {
+            bdt_entries()
+                .into_iter()
+                .map(|entry| unsafe { &*entry })
+                .filter(|entry| entry.valid)
+                .map(|entry| BdtEntry {
+                    address: socket_addr_of(&entry.dest_address),
+                    mask: entry.broadcast_mask.address.into(),
+                })
+                .collect()
+        })
+    }
+
+    /// Our own Foreign Device Table, when acting as a BBMD
+    #[cfg(feature = "bbmd")]
+    pub fn fdt(&self) -> Result<Vec<FdtEntry>> {
+        self.run(|| {
+            let mut fdt = vec![];
+            let mut entry = unsafe { bvlc_fdt_list() };
+            while !entry.is_null() {
+                let BACNET_IP_FOREIGN_DEVICE_TABLE_ENTRY {
+                    valid,
+                    dest_address,
+                    ttl_seconds,
+                    ttl_seconds_remaining,
+                    next,
+                } = unsafe { *entry };
+                if valid {
+                    fdt.push(FdtEntry {
+                        address: socket_addr_of(&dest_address),
+                        ttl: Duration::from_secs(ttl_seconds as u64),
+                        remaining: Duration::from_secs(ttl_seconds_remaining as u64),
+                    });
+                }
+                entry = next;
+            }
+            fdt
+        })
+    }
+}
+
+// The entries of the stack's BDT, valid or not. Runs on the network thread.
+#[cfg(feature = "bbmd")]
+fn bdt_entries() -> Vec<*mut BACNET_IP_BROADCAST_DISTRIBUTION_TABLE_ENTRY> {
+    let mut entries = vec![];
+    let mut entry = unsafe { bvlc_bdt_list() };
+    while !entry.is_null() {
+        entries.push(entry);
+        entry = unsafe { (*entry).next };
+    }
+    entries
+}
+
+/// Read the Broadcast Distribution Table of a BBMD
+pub fn read_bdt(bbmd: SocketAddrV4, timeout: Duration) -> Result<Vec<BdtEntry>> {
+    let data = read_table(
+        bbmd,
+        timeout,
+        BVLC_READ_BROADCAST_DIST_TABLE,
+        BVLC_READ_BROADCAST_DIST_TABLE_ACK,
+    )?;
+    Ok(data
+        .chunks_exact(10)
+        .map(|entry| BdtEntry {
+            address: socket_addr(&entry[..6]),
+            mask: Ipv4Addr::new(entry[6], entry[7], entry[8], entry[9]),
+        })
+        .collect())
+}
+
+/// Read the Foreign Device Table of a BBMD
+pub fn read_fdt(bbmd: SocketAddrV4, timeout: Duration) -> Result<Vec<FdtEntry>> {
+    let data = read_table(
+        bbmd,
+        timeout,
+        BVLC_READ_FOREIGN_DEVICE_TABLE,
+        BVLC_READ_FOREIGN_DEVICE_TABLE_ACK,
+    )?;
+    Ok(data
+        .chunks_exact(10)
+        .map(|entry| FdtEntry {
+            address: socket_addr(&entry[..6]),
+            ttl: Duration::from_secs(u16::from_be_bytes([entry[6], entry[7]]) as u64),
+            remaining: Duration::from_secs(u16::from_be_bytes([entry[8], entry[9]]) as u64),
+        })
+        .collect())
+}
+
+// Send a read request for a table, and return the table data from the ack
+fn read_table(bbmd: SocketAddrV4, timeout: Duration, function: u8, ack: u8) -> Result<Vec<u8>> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).map_err(|_| BACnetErr::SendFailed)?;
+    socket
+        .send_to(&[BVLL_TYPE_BACNET_IP, function, 0, 4], bbmd)
+        .map_err(|_| BACnetErr::SendFailed)?;
+
+    let start = Instant::now();
+    let mut buf = [0u8; 1500];
+    while let Some(time_left) = timeout.checked_sub(start.elapsed()) {
+        // A zero timeout would mean blocking forever
+        if time_left.is_zero() {
+            break;
+        }
+        socket
+            .set_read_timeout(Some(time_left))
+            .map_err(|_| BACnetErr::SendFailed)?;
+        let (len, src) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(_) => break,
+        };
+        if src != bbmd.into() || len < 4 || buf[0] != BVLL_TYPE_BACNET_IP {
+            trace!("ignoring {} bytes from {}", len, src);
+            continue;
+        }
+
+        let len = len
+            .min(u16::from_be_bytes([buf[2], buf[3]]) as usize)
+            .max(4);
+        match buf[1] {
+            f if f == ack => return Ok(buf[4..len].to_vec()),
+            BVLC_RESULT if len >= 6 => {
+                return Err(bvlc_nak(u16::from_be_bytes([buf[4], buf[5]])));
+            }
+            f => trace!("ignoring BVLC function {} from {}", f, src),
+        }
+    }
+    Err(BACnetErr::NoResponse { retries: 0 })
+}
+
+// Send a registration request, `reply` is called once the BBMD answered (or didn't). Runs on the
+// network thread.
+pub(crate) fn register(bbmd: SocketAddrV4, ttl: Duration, reply: ReplyFn) {
+    let ttl = ttl.as_secs().min(u16::MAX as u64) as u16;
+    debug!("registering with BBMD {} for {} seconds", bbmd, ttl);
+    forget_result(bbmd);
+    let sent = unsafe { bvlc_register_with_bbmd(&ip_address(bbmd), ttl) };
+    if sent <= 0 {
+        reply(Err(BACnetErr::SendFailed));
+        return;
+    }
+
+    let now = Instant::now();
+    *REGISTRATION.lock().unwrap() = Some(Registration {
+        bbmd,
+        ttl,
+        // Renew once 80% of the time-to-live has passed
+        renew_at: now + Duration::from_secs(ttl as u64).mul_f32(0.8),
+        waiting: Some(Waiting {
+            deadline: now + RESULT_TIMEOUT,
+            reply,
+        }),
+    });
+}
+
+// Check on the answer to a registration request, and renew the registration when it's time. As a
+// BBMD, age the entries of the FDT as well. Runs on the network thread, after every PDU.
+pub(crate) fn maintain() {
+    #[cfg(feature = "bbmd")]
+    {
+        let mut last_tick = LAST_TICK.lock().unwrap();
+        let seconds = last_tick.elapsed().as_secs().min(u16::MAX as u64);
+        if seconds > 0 {
+            *last_tick += Duration::from_secs(seconds);
+            unsafe { bvlc_maintenance_timer(seconds as u16) };
+        }
+    }
+
+    let mut registration = REGISTRATION.lock().unwrap();
+    let Some(current) = registration.as_mut() else {
+        return;
+    };
+
+    if let Some(waiting) = current.waiting.take() {
+        // We're called after every PDU, so a result that doesn't answer a registration (like a
+        // Distribute-Broadcast-To-Network NAK) can't hide the answer.
+        let result = match last_result() {
+            (
+                BVLC_RESULT,
+                code
+                @ (BVLC_RESULT_SUCCESSFUL_COMPLETION | BVLC_RESULT_REGISTER_FOREIGN_DEVICE_NAK),
+            ) => check_result(code),
+            _ if Instant::now() >= waiting.deadline => Err(BACnetErr::NoResponse { retries: 0 }),
+            _ => {
+                current.waiting = Some(waiting);
+                return;
+            }
+        };
+
+        // There's no use in renewing a registration the BBMD refused
+        if let Err(BACnetErr::BvlcNak { .. }) = result {
+            *registration = None;
+        }
+        (waiting.reply)(result);
+        return;
+    }
+
+    if Instant::now() >= current.renew_at {
+        let (bbmd, ttl) = (current.bbmd, current.ttl);
+        drop(registration);
+        register(
+            bbmd,
+            Duration::from_secs(ttl as u64),
+            Box::new(move |result| {
+                if let Err(err) = result {
+                    error!(
+                        "renewing the registration with BBMD {} failed: {}",
+                        bbmd, err
+                    );
+                }
+            }),
+        );
+    }
+}
+
+fn check_result(code: u16) -> Result<()> {
+    match code {
+        BVLC_RESULT_SUCCESSFUL_COMPLETION => Ok(()),
+        code => Err(bvlc_nak(code)),
+    }
+}
+
+// The last BVLC function the stack got, and the last BVLC-Result code
+fn last_result() -> (u8, u16) {
+    unsafe { (bvlc_get_function_code(), bvlc_get_last_result()) }
+}
+
+// Have the stack forget the last BVLC-Result, so the BBMD's answer can't be mistaken for one that
+// came in earlier (like the answer to the previous registration). There's no way to reset it, so
+// the stack is handed a BVLC-Result from the BBMD that no BBMD sends.
+fn forget_result(bbmd: SocketAddrV4) {
+    let [high, low] = BVLC_RESULT_NONE.to_be_bytes();
+    let mut message = [BVLL_TYPE_BACNET_IP, BVLC_RESULT, 0, 6, high, low];
+    let mut src = BACNET_ADDRESS::default();
+    unsafe {
+        bvlc_handler(
+            &mut ip_address(bbmd),
+            &mut src,
+            message.as_mut_ptr(),
+            message.len() as u16,
+        )
+    };
+}
+
+fn bvlc_nak(code: u16) -> BACnetErr {
+    let text = match code {
+        0x0010 => "Write-Broadcast-Distribution-Table NAK",
+        0x0020 => "Read-Broadcast-Distribution-Table NAK",
+        BVLC_RESULT_REGISTER_FOREIGN_DEVICE_NAK => "Register-Foreign-Device NAK",
+        0x0040 => "Read-Foreign-Device-Table NAK",
+        0x0050 => "Delete-Foreign-Device-Table-Entry NAK",
+        0x0060 => "Distribute-Broadcast-To-Network NAK",
+        _ => "Unknown result",
+    };
+    BACnetErr::BvlcNak {
+        text: text.to_string(),
+        code,
+    }
+}
+
+#[cfg(feature = "bbmd")]
+fn socket_addr_of(address: &BACNET_IP_ADDRESS) -> SocketAddrV4 {
+    SocketAddrV4::new(address.address.into(), address.port)
+}
+
+// A B/IP address is the IPv4 address followed by the port
+fn socket_addr(data: &[u8]) -> SocketAddrV4 {
+    SocketAddrV4::new(
+        Ipv4Addr::new(data[0], data[1], data[2], data[3]),
+        u16::from_be_bytes([data[4], data[5]]),
+    )
+}
+
+fn ip_address(address: SocketAddrV4) -> BACNET_IP_ADDRESS {
+    BACNET_IP_ADDRESS {
+        address: address.ip().octets(),
+        port: address.port(),
+    }
+}
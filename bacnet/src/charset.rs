@@ -0,0 +1,231 @@
+// Character string encodings (ASHRAE 135 clause 20.2.9)
+//
+// A character string starts with a byte telling its character set, and for IBM/Microsoft DBCS
+// the code page follows as two more bytes. Everything that isn't UTF-8 is decoded to a
+// `BACnetValue::EncodedString`, so it's written back in the same character set.
+
+use crate::{
+    errors::Result,
+    value::{BACnetValue, CharacterSet},
+    BACnetErr,
+};
+use encoding_rs::{Encoding, BIG5, EUC_JP, EUC_KR, GBK, SHIFT_JIS};
+
+// The character set bytes
+const CHARSET_UTF8: u8 = 0; // Formerly ANSI X3.4
+const CHARSET_DBCS: u8 = 1;
+const CHARSET_JIS: u8 = 2;
+const CHARSET_UCS4: u8 = 3;
+const CHARSET_UCS2: u8 = 4;
+const CHARSET_LATIN1: u8 = 5;
+
+/// Decode a character string from its character set byte and the bytes following it
+// `is_multiple_of()` is newer than the Rust version we build with
+#[allow(unknown_lints, clippy::manual_is_multiple_of)]
+pub(crate) fn decode_character_string(charset: u8, bytes: &[u8]) -> Result<BACnetValue> {
+    let (charset, value) = match charset {
+        CHARSET_UTF8 => {
+            return Ok(BACnetValue::String(
+                String::from_utf8_lossy(bytes).into_owned(),
+            ))
+        }
+        CHARSET_DBCS => {
+            let (code_page, bytes) = match bytes {
+                [hi, lo, rest @ ..] => (u16::from_be_bytes([*hi, *lo]), rest),
+                _ => return Err(BACnetErr::DecodeFailed),
+            };
+            let (value, had_errors) = dbcs_encoding(code_page)?.decode_without_bom_handling(bytes);
+            if had_errors {
+                return Err(BACnetErr::DecodeFailed);
+            }
+            (CharacterSet::Dbcs { code_page }, value.into_owned())
+        }
+        CHARSET_JIS => {
+            // JIS X 0208 is EUC-JP without the high bits set
+            let euc = bytes.iter().map(|b| b | 0x80).collect::<Vec<_>>();
+            let (value, had_errors) = EUC_JP.decode_without_bom_handling(&euc);
+            if had_errors || bytes.iter().any(|b| !(0x21..=0x7E).contains(b)) {
+                return Err(BACnetErr::DecodeFailed);
+            }
+            (CharacterSet::Jis, value.into_owned())
+        }
+        CHARSET_UCS4 => {
+            if bytes.len() % 4 != 0 {
+                return Err(BACnetErr::DecodeFailed);
+            }
+            let value = bytes
+                .chunks(4)
+                .map(|c| char::from_u32(u32::from_be_bytes([c[0], c[1], c[2], c[3]])))
+                .collect::<Option<String>>()
+                .ok_or(BACnetErr::DecodeFailed)?;
+            (CharacterSet::Ucs4, value)
+        }
+        CHARSET_UCS2 => {
+            if bytes.len() % 2 != 0 {
+                return Err(BACnetErr::DecodeFailed);
+            }
+            let value = bytes
+                .chunks(2)
+                .map(|c| char::from_u32(u16::from_be_bytes([c[0], c[1]]) as u32))
+                .collect::<Option<String>>()
+                .ok_or(BACnetErr::DecodeFailed)?;
+            (CharacterSet::Ucs2, value)
+        }
+        // The first 256 code points of Unicode are ISO 8859-1
+        CHARSET_LATIN1 => (
+            CharacterSet::Latin1,
+            bytes.iter().map(|&b| b as char).collect(),
+        ),
+        _ => return Err(BACnetErr::DecodeFailed),
+    };
+
+    Ok(BACnetValue::EncodedString { value, charset })
+}
+
+/// Encode a string in the given character set, returning the character set byte and the bytes
+/// following it
+pub(crate) fn encode_character_string(value: &str, charset: CharacterSet) -> Result<(u8, Vec<u8>)> {
+    Ok(match charset {
+        CharacterSet::Utf8 => (CHARSET_UTF8, value.as_bytes().to_vec()),
+        CharacterSet::Dbcs { code_page } => {
+            let (bytes, _, had_errors) = dbcs_encoding(code_page)?.encode(value);
+            if had_errors {
+                return Err(BACnetErr::EncodeFailed);
+            }
+            let mut ret = code_page.to_be_bytes().to_vec();
+            ret.extend_from_slice(&bytes);
+            (CHARSET_DBCS, ret)
+        }
+        CharacterSet::Jis => {
+            let (bytes, _, had_errors) = EUC_JP.encode(value);
+            // Only the two byte JIS X 0208 characters can be represented
+            if had_errors || bytes.iter().any(|&b| b < 0xA1 || b == 0xFF) {
+                return Err(BACnetErr::EncodeFailed);
+            }
+            (CHARSET_JIS, bytes.iter().map(|b| b & 0x7F).collect())
+        }
+        CharacterSet::Ucs4 => (
+            CHARSET_UCS4,
+            value
+                .chars()
+                .flat_map(|c| (c as u32).to_be_bytes())
+                .collect(),
+        ),
+        CharacterSet::Ucs2 => {
+            let mut ret = vec![];
+            for c in value.chars() {
+                let c = u16::try_from(c as u32).map_err(|_| BACnetErr::EncodeFailed)?;
+                ret.extend_from_slice(&c.to_be_bytes());
+            }
+            (CHARSET_UCS2, ret)
+        }
+        CharacterSet::Latin1 => (
+            CHARSET_LATIN1,
+            value
+                .chars()
+                .map(|c| u8::try_from(c as u32).map_err(|_| BACnetErr::EncodeFailed))
+                .collect::<Result<_>>()?,
+        ),
+    })
+}
+
+// The double byte character sets we know of, by their (Windows) code page
+fn dbcs_encoding(code_page: u16) -> Result<&'static Encoding> {
+    match code_page {
+        932 => Ok(SHIFT_JIS),
+        936 => Ok(GBK),
+        949 => Ok(EUC_KR),
+        950 => Ok(BIG5),
+        _ => Err(BACnetErr::UnsupportedCodePage { code_page }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Decode the character set byte and bytes, check the value, and encode it back to the same
+    fn round_trip(encoded: &[u8], value: &str, charset: CharacterSet) {
+        let decoded = decode_character_string(encoded[0], &encoded[1..]).unwrap();
+        match &decoded {
+            BACnetValue::String(s) => {
+                assert_eq!(charset, CharacterSet::Utf8);
+                assert_eq!(s, value);
+            }
+            BACnetValue::EncodedString {
+                value: s,
+                charset: c,
+            } => {
+                assert_eq!(*c, charset);
+                assert_eq!(s, value);
+            }
+            value => panic!("not a string: {:?}", value),
+        }
+        let (charset, bytes) = encode_character_string(value, charset).unwrap();
+        assert_eq!(charset, encoded[0]);
+        assert_eq!(bytes, &encoded[1..]);
+    }
+
+    #[test]
+    fn character_sets() {
+        round_trip(b"\x00Fan 1", "Fan 1", CharacterSet::Utf8);
+        round_trip(
+            &[CHARSET_UCS2, 0x00, 0x46, 0x00, 0xE9, 0x65, 0xE5],
+            "F\u{e9}\u{65e5}",
+            CharacterSet::Ucs2,
+        );
+        round_trip(
+            &[CHARSET_UCS4, 0x00, 0x00, 0x00, 0x46, 0x00, 0x01, 0xF3, 0x21],
+            "F\u{1f321}",
+            CharacterSet::Ucs4,
+        );
+        round_trip(
+            &[CHARSET_LATIN1, 0x46, 0xE9, 0xFF],
+            "F\u{e9}\u{ff}",
+            CharacterSet::Latin1,
+        );
+        // 日本 in Shift_JIS and in JIS X 0208
+        round_trip(
+            &[CHARSET_DBCS, 0x03, 0xA4, 0x93, 0xFA, 0x96, 0x7B],
+            "\u{65e5}\u{672c}",
+            CharacterSet::Dbcs { code_page: 932 },
+        );
+        round_trip(
+            &[CHARSET_JIS, 0x46, 0x7C, 0x4B, 0x5C],
+            "\u{65e5}\u{672c}",
+            CharacterSet::Jis,
+        );
+    }
+
+    #[test]
+    fn invalid_strings() {
+        assert!(decode_character_string(CHARSET_UCS2, &[0x00]).is_err());
+        assert!(decode_character_string(CHARSET_UCS4, &[0x00, 0x11, 0x00, 0x00]).is_err());
+        assert!(decode_character_string(CHARSET_DBCS, &[0x03]).is_err());
+        assert!(matches!(
+            decode_character_string(CHARSET_DBCS, &[0x04, 0xE4, 0x41]),
+            Err(BACnetErr::UnsupportedCodePage { code_page: 1252 })
+        ));
+        assert!(decode_character_string(CHARSET_JIS, b"A").is_err());
+        assert!(decode_character_string(6, b"A").is_err());
+        assert!(encode_character_string("\u{1f321}", CharacterSet::Ucs2).is_err());
+        assert!(encode_character_string("\u{100}", CharacterSet::Latin1).is_err());
+        assert!(encode_character_string("A", CharacterSet::Jis).is_err());
+    }
+
+    // A string that's read, changed and written back keeps its character set
+    #[test]
+    fn read_modify_write() {
+        let (value, charset) = match decode_character_string(CHARSET_LATIN1, b"Caf\xE9") {
+            Ok(BACnetValue::EncodedString { value, charset }) => (value.replace('C', "K"), charset),
+            value => panic!("not an encoded string: {:?}", value),
+        };
+        let (charset_byte, bytes) = encode_character_string(&value, charset).unwrap();
+        assert_eq!(charset_byte, CHARSET_LATIN1);
+        assert_eq!(bytes, b"Kaf\xE9");
+        assert_eq!(
+            decode_character_string(charset_byte, &bytes).unwrap(),
+            BACnetValue::EncodedString { value, charset }
+        );
+    }
+}
@@ -0,0 +1,570 @@
+//! Change-of-Value (COV) subscriptions
+//!
+//! A subscription is made with `BACnetServer::subscribe_cov()`, and the server then notifies us
+//! whenever the monitored object changes. With `BACnetServer::subscribe_cov_property()` only a
+//! single property is monitored, optionally with our own COV increment.
+
+// Notifications are decoded here rather than by the stack, which keeps a single value per property
+// and can't decode constructed values (like a weekly-schedule). So we acknowledge confirmed
+// notifications ourselves as well. The subscriber process identifier tells which subscription a
+// notification belongs to, and it's pushed on that subscription's channel.
+//
+// The stack has no Send_* function for SubscribeCOVProperty, so we put that request together
+// ourselves, the same way Send_COV_Subscribe does for SubscribeCOV.
+//
+// Notifications come in and subscriptions are renewed on the network thread, whether or not anyone
+// is reading from them. A renewal that fails is passed on to the reader, and tried again.
+
+use crate::{
+    datalink,
+    encoding::{decode_application_data, decode_object_id, decode_unsigned, TagKind, TagReader},
+    errors::Result,
+    network::{publish, RequestOptions, UnsolicitedMessage},
+    value::BACnetValue,
+    BACnetErr, BACnetNetwork, BACnetServer, DeviceId, ObjectPropertyId, ObjectType,
+    RequestInvokeId, Response,
+};
+use bacnet_sys::{
+    address_get_by_device, cov_subscribe_property_encode_apdu, npdu_encode_npdu_data,
+    npdu_encode_pdu, tsm_free_invoke_id, tsm_next_free_invokeID,
+    tsm_set_confirmed_unsegmented_transaction, Send_COV_Subscribe, BACNET_ADDRESS,
+    BACNET_ARRAY_ALL, BACNET_CONFIRMED_SERVICE_DATA,
+    BACNET_MESSAGE_PRIORITY_MESSAGE_PRIORITY_NORMAL, BACNET_NPDU_DATA, BACNET_SUBSCRIBE_COV_DATA,
+    MAX_MPDU,
+};
+use log::{debug, error, warn};
+use once_cell::sync::Lazy;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        mpsc::{channel, Receiver, Sender},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+// The subscriptions we know of, indexed by subscriber process identifier. Next to notifications
+// they're told about failed renewals.
+static SUBSCRIBERS: Lazy<Mutex<HashMap<u32, Sender<Result<CovNotification>>>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+// The subscriptions to renew, indexed by subscriber process identifier
+static RENEWALS: Lazy<Mutex<HashMap<u32, Renewal>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+static NEXT_PROCESS_ID: AtomicU32 = AtomicU32::new(1);
+
+// The APDUs answering a confirmed notification (see clause 20.1 of ASHRAE 135)
+const PDU_TYPE_SIMPLE_ACK: u8 = 0x20;
+const PDU_TYPE_REJECT: u8 = 0x60;
+const SERVICE_COV_NOTIFICATION: u8 = 1;
+const REJECT_REASON_INVALID_TAG: u8 = 4;
+
+/// A COV notification, telling us about the new values of the monitored object
+#[derive(Debug, Clone, PartialEq)]
+pub struct CovNotification {
+    pub device_id: u32,
+    pub object_type: ObjectType,
+    pub object_instance: u32,
+    /// The time left on the subscription according to the server (in seconds)
+    pub time_remaining: u32,
+    pub values: HashMap<ObjectPropertyId, BACnetValue>,
+}
+
+/// An active COV subscription
+///
+/// The subscription is renewed in the background before its lifetime runs out, and cancelled
+/// when dropped. Notifications are read through `recv_timeout()` or as an iterator.
+pub struct CovSubscription<'a> {
+    server: &'a BACnetServer,
+    request: CovRequest,
+    receiver: Receiver<Result<CovNotification>>,
+    active: bool,
+}
+
+// A subscription the network thread renews
+struct Renewal {
+    device_id: DeviceId,
+    options: RequestOptions,
+    request: CovRequest,
+    renew_at: Instant,
+    // Whether a renewal has been sent and not answered yet
+    sent: bool,
+}
+
+// A SubscribeCOV (or SubscribeCOVProperty) request. Unlike BACNET_SUBSCRIBE_COV_DATA, it can be
+// sent off to the network thread.
+#[derive(Debug, Clone, Copy)]
+struct CovRequest {
+    process_id: u32,
+    object_type: ObjectType,
+    object_instance: u32,
+    monitored_property: Option<(ObjectPropertyId, Option<f32>)>,
+    confirmed: bool,
+    lifetime: u32,
+    cancel: bool,
+}
+
+impl CovRequest {
+    fn data(&self) -> BACNET_SUBSCRIBE_COV_DATA {
+        let mut data = BACNET_SUBSCRIBE_COV_DATA {
+            subscriberProcessIdentifier: self.process_id,
+            cancellationRequest: self.cancel,
+            issueConfirmedNotifications: self.confirmed,
+            lifetime: self.lifetime,
+            ..Default::default()
+        };
+        data.monitoredObjectIdentifier.type_ = self.object_type;
+        data.monitoredObjectIdentifier.instance = self.object_instance;
+        if let Some((property, cov_increment)) = self.monitored_property {
+            data.monitoredProperty.propertyIdentifier = property;
+            data.monitoredProperty.propertyArrayIndex = BACNET_ARRAY_ALL;
+            if let Some(cov_increment) = cov_increment {
+                data.covIncrementPresent = true;
+                data.covIncrement = cov_increment;
+            }
+        }
+        data
+    }
+
+    fn send(&self, device_id: DeviceId) -> RequestInvokeId {
+        let mut data = self.data();
+        unsafe {
+            if self.monitored_property.is_some() {
+                send_cov_subscribe_property(device_id, &mut data)
+            } else {
+                Send_COV_Subscribe(device_id, &mut data)
+            }
+        }
+    }
+}
+
+impl<'a> CovSubscription<'a> {
+    pub(crate) fn new(
+        server: &'a BACnetServer,
+        object_type: ObjectType,
+        object_instance: u32,
+        monitored_property: Option<(ObjectPropertyId, Option<f32>)>,
+        confirmed: bool,
+        lifetime: Duration,
+    ) -> Result<Self> {
+        // Less than a second would be taken for an indefinite lifetime (0)
+        let lifetime = match u32::try_from(lifetime.as_secs()) {
+            Ok(0) if !lifetime.is_zero() => return Err(BACnetErr::InvalidValue),
+            Ok(lifetime) => lifetime,
+            Err(_) => return Err(BACnetErr::InvalidValue),
+        };
+        let process_id = NEXT_PROCESS_ID.fetch_add(1, Ordering::Relaxed);
+        let request = CovRequest {
+            process_id,
+            object_type,
+            object_instance,
+            monitored_property,
+            confirmed,
+            lifetime,
+            cancel: false,
+        };
+
+        let (sender, receiver) = channel();
+        SUBSCRIBERS.lock().unwrap().insert(process_id, sender);
+
+        let mut ret = CovSubscription {
+            server,
+            request,
+            receiver,
+            active: false,
+        };
+        // If this fails, dropping `ret` removes it from the list of subscribers
+        ret.send(false)?;
+        ret.active = true;
+        if lifetime != 0 {
+            RENEWALS.lock().unwrap().insert(
+                process_id,
+                Renewal {
+                    device_id: server.device_id,
+                    options: server.options,
+                    request,
+                    renew_at: renew_at(&request),
+                    sent: false,
+                },
+            );
+        }
+        Ok(ret)
+    }
+
+    /// The subscriber process identifier used for this subscription
+    pub fn process_id(&self) -> u32 {
+        self.request.process_id
+    }
+
+    /// Wait up to `timeout` for the next notification
+    ///
+    /// Returns `Ok(None)` if no notification came in, and an error if renewing the subscription
+    /// failed. The renewal is tried again until the subscription is dropped.
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<Option<CovNotification>> {
+        match self.receiver.recv_timeout(timeout) {
+            Ok(notification) => notification.map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    // Send the request (or its cancellation), and wait for the server to acknowledge it
+    fn send(&self, cancel: bool) -> Result<()> {
+        let request = CovRequest {
+            cancel,
+            ..self.request
+        };
+        self.server
+            .request(move |device_id| Ok(request.send(device_id)))?;
+        Ok(())
+    }
+}
+
+impl Iterator for CovSubscription<'_> {
+    type Item = CovNotification;
+
+    /// Blocks until the next notification comes in. The iterator ends if the subscription can't
+    /// be renewed.
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.receiver.recv() {
+            Ok(Ok(notification)) => Some(notification),
+            Ok(Err(err)) => {
+                error!("COV subscription {} failed: {}", self.process_id(), err);
+                None
+            }
+            Err(_) => None,
+        }
+    }
+}
+
+impl Drop for CovSubscription<'_> {
+    fn drop(&mut self) {
+        SUBSCRIBERS.lock().unwrap().remove(&self.process_id());
+        RENEWALS.lock().unwrap().remove(&self.process_id());
+
+        // Only cancel what the server has actually accepted
+        if self.active {
+            if let Err(err) = self.send(true) {
+                warn!(
+                    "failed to cancel COV subscription {}: {}",
+                    self.process_id(),
+                    err
+                );
+            }
+        }
+    }
+}
+
+/// # Safety
+///
+/// This dereferences the raw pointers handed to us by the stack, so it's unsafe.
+#[no_mangle]
+pub unsafe extern "C" fn my_ucov_notification_handler(
+    service_request: *mut u8,
+    service_len: u16,
+    _src: *mut BACNET_ADDRESS,
+) {
+    let apdu = unsafe { std::slice::from_raw_parts(service_request, service_len as usize) };
+    match decode_cov_notification(apdu) {
+        Ok((process_id, notification)) => notify(process_id, notification),
+        Err(err) => warn!("unable to decode COV notification: {}", err),
+    }
+}
+
+/// # Safety
+///
+/// This dereferences the raw pointers handed to us by the stack, so it's unsafe.
+#[no_mangle]
+pub unsafe extern "C" fn my_ccov_notification_handler(
+    service_request: *mut u8,
+    service_len: u16,
+    src: *mut BACNET_ADDRESS,
+    service_data: *mut BACNET_CONFIRMED_SERVICE_DATA,
+) {
+    let apdu = unsafe { std::slice::from_raw_parts(service_request, service_len as usize) };
+    let (src, invoke_id) = unsafe { (&mut *src, (*service_data).invoke_id) };
+    match decode_cov_notification(apdu) {
+        Ok((process_id, notification)) => {
+            send_reply(
+                src,
+                [PDU_TYPE_SIMPLE_ACK, invoke_id, SERVICE_COV_NOTIFICATION],
+            );
+            notify(process_id, notification);
+        }
+        Err(err) => {
+            warn!("unable to decode COV notification: {}", err);
+            send_reply(src, [PDU_TYPE_REJECT, invoke_id, REJECT_REASON_INVALID_TAG]);
+        }
+    }
+}
+
+// When to renew a subscription, once 80% of its lifetime has passed
+fn renew_at(request: &CovRequest) -> Instant {
+    Instant::now() + Duration::from_secs(request.lifetime as u64).mul_f32(0.8)
+}
+
+// Send the renewals that are due. Runs on the network thread, after every PDU.
+pub(crate) fn maintain() {
+    let now = Instant::now();
+    let mut failed = vec![];
+    for renewal in RENEWALS
+        .lock()
+        .unwrap()
+        .values_mut()
+        .filter(|renewal| !renewal.sent && now >= renewal.renew_at)
+    {
+        let request = renewal.request;
+        debug!("renewing COV subscription {}", request.process_id);
+        let sent = BACnetNetwork::get().and_then(|network| {
+            network.request_detached(
+                renewal.device_id,
+                renewal.options,
+                move |device_id| Ok(request.send(device_id)),
+                move |response| renewed(&request, response),
+            )
+        });
+        match sent {
+            Ok(()) => renewal.sent = true,
+            Err(err) => failed.push((request.process_id, err)),
+        }
+    }
+    for (process_id, err) in failed {
+        renewal_failed(process_id, err);
+    }
+}
+
+// Schedule the next renewal once the server acknowledged this one. Runs on the network thread.
+fn renewed(request: &CovRequest, response: Response) {
+    let mut renewals = RENEWALS.lock().unwrap();
+    // The subscription may have been dropped in the meantime
+    let Some(renewal) = renewals.get_mut(&request.process_id) else {
+        return;
+    };
+    renewal.sent = false;
+    match response {
+        Response::Error(err) => {
+            drop(renewals);
+            renewal_failed(request.process_id, err);
+        }
+        _ => renewal.renew_at = renew_at(request),
+    }
+}
+
+// Tell the subscription its renewal failed, it's tried again after the next PDU
+fn renewal_failed(process_id: u32, err: BACnetErr) {
+    warn!("renewing COV subscription {} failed: {}", process_id, err);
+    if let Some(sender) = SUBSCRIBERS.lock().unwrap().get(&process_id) {
+        let _ = sender.send(Err(err));
+    }
+}
+
+// Hand a notification to its subscription, or to anyone listening for unsolicited messages
+fn notify(process_id: u32, notification: CovNotification) {
+    debug!("COV notification {:?}", notification);
+    if let Some(sender) = SUBSCRIBERS.lock().unwrap().get(&process_id) {
+        let _ = sender.send(Ok(notification));
+    } else {
+        debug!("no subscriber for COV process identifier {}", process_id);
+        publish(UnsolicitedMessage::CovNotification(notification));
+    }
+}
+
+// COVNotification-Request ::= SEQUENCE {
+//     subscriberProcessIdentifier [0] Unsigned32,
+//     initiatingDeviceIdentifier  [1] BACnetObjectIdentifier,
+//     monitoredObjectIdentifier   [2] BACnetObjectIdentifier,
+//     timeRemaining               [3] Unsigned,
+//     listOfValues                [4] SEQUENCE OF BACnetPropertyValue
+// }
+//
+// BACnetPropertyValue ::= SEQUENCE {
+//     propertyIdentifier [0] BACnetPropertyIdentifier,
+//     propertyArrayIndex [1] Unsigned OPTIONAL,
+//     value              [2] ABSTRACT-SYNTAX.&Type,
+//     priority           [3] Unsigned (1..16) OPTIONAL
+// }
+//
+// A value we can't decode is left out (and logged), rather than dropping the whole notification.
+fn decode_cov_notification(data: &[u8]) -> Result<(u32, CovNotification)> {
+    let mut reader = TagReader::new(data);
+    let process_id = decode_unsigned(reader.read_context(0)?) as u32;
+    let (_, device_id) = decode_object_id(reader.read_context(1)?);
+    let (object_type, object_instance) = decode_object_id(reader.read_context(2)?);
+    let time_remaining = decode_unsigned(reader.read_context(3)?) as u32;
+
+    let mut values = HashMap::new();
+    reader.expect_opening(4)?;
+    while reader.next_context(4) != Some(TagKind::Closing) {
+        let property = decode_unsigned(reader.read_context(0)?) as ObjectPropertyId;
+        if matches!(reader.next_context(1), Some(TagKind::Length(_))) {
+            reader.read_context(1)?;
+        }
+        reader.expect_opening(2)?;
+        match decode_application_data(reader.read_constructed(2)?, object_type, property) {
+            Ok(value) => {
+                values.insert(property, value);
+            }
+            Err(err) => warn!(
+                "unable to decode property {} in COV notification: {}",
+                property, err
+            ),
+        }
+        if matches!(reader.next_context(3), Some(TagKind::Length(_))) {
+            reader.read_context(3)?;
+        }
+    }
+    reader.expect_closing(4)?;
+
+    Ok((
+        process_id,
+        CovNotification {
+            device_id,
+            object_type,
+            object_instance,
+            time_remaining,
+            values,
+        },
+    ))
+}
+
+// Answer a confirmed notification with the given APDU, on the network thread
+fn send_reply(dest: &mut BACNET_ADDRESS, apdu: [u8; 3]) {
+    let mut my_address = BACNET_ADDRESS::default();
+    let mut npdu_data = BACNET_NPDU_DATA::default();
+    let mut pdu = [0u8; MAX_MPDU as usize];
+
+    datalink::get_my_address(&mut my_address);
+    let pdu_len = unsafe {
+        npdu_encode_npdu_data(
+            &mut npdu_data,
+            false,
+            BACNET_MESSAGE_PRIORITY_MESSAGE_PRIORITY_NORMAL,
+        );
+        npdu_encode_pdu(pdu.as_mut_ptr(), dest, &mut my_address, &mut npdu_data)
+    } as usize;
+    pdu[pdu_len..pdu_len + apdu.len()].copy_from_slice(&apdu);
+    if datalink::send_pdu(dest, &mut npdu_data, &mut pdu[..pdu_len + apdu.len()]) <= 0 {
+        warn!("failed to answer COV notification");
+    }
+}
+
+/// Send a SubscribeCOVProperty request, returning the invoke id (0 if it couldn't be sent)
+///
+/// # Safety
+///
+/// Calls into the stack, which must be initialized.
+pub(crate) unsafe fn send_cov_subscribe_property(
+    device_id: u32,
+    cov_data: &mut BACNET_SUBSCRIBE_COV_DATA,
+) -> u8 {
+    let mut dest = BACNET_ADDRESS::default();
+    let mut my_address = BACNET_ADDRESS::default();
+    let mut npdu_data = BACNET_NPDU_DATA::default();
+    let mut max_apdu = 0;
+    let mut pdu = [0u8; MAX_MPDU as usize];
+
+    if !address_get_by_device(device_id, &mut max_apdu, &mut dest) {
+        return 0;
+    }
+    let invoke_id = tsm_next_free_invokeID();
+    if invoke_id == 0 {
+        return 0;
+    }
+
+    datalink::get_my_address(&mut my_address);
+    npdu_encode_npdu_data(
+        &mut npdu_data,
+        true,
+        BACNET_MESSAGE_PRIORITY_MESSAGE_PRIORITY_NORMAL,
+    );
+    let mut pdu_len = npdu_encode_pdu(pdu.as_mut_ptr(), &mut dest, &mut my_address, &mut npdu_data);
+    pdu_len += cov_subscribe_property_encode_apdu(
+        pdu.as_mut_ptr().add(pdu_len as usize),
+        pdu.len() as u32 - pdu_len as u32,
+        invoke_id,
+        cov_data,
+    );
+    if pdu_len as u32 >= max_apdu {
+        error!(
+            "SubscribeCOVProperty request doesn't fit in the device's APDU ({} bytes)",
+            max_apdu
+        );
+        tsm_free_invoke_id(invoke_id);
+        return 0;
+    }
+
+    tsm_set_confirmed_unsegmented_transaction(
+        invoke_id,
+        &mut dest,
+        &mut npdu_data,
+        pdu.as_mut_ptr(),
+        pdu_len as u16,
+    );
+    if datalink::send_pdu(&mut dest, &mut npdu_data, &mut pdu[..pdu_len as usize]) <= 0 {
+        warn!("failed to send SubscribeCOVProperty request");
+    }
+    invoke_id
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notification() {
+        let mut data = vec![
+            0x09, 18, // subscriber process identifier
+            0x1C, 0x02, 0x00, 0x00, 0x64, // initiating device 100
+            0x2C, 0x00, 0x00, 0x00, 0x03, // analog-input 3
+            0x39, 60, // time remaining
+            0x4E,
+        ];
+        // present-value: 21.5
+        data.extend_from_slice(&[0x09, 85, 0x2E, 0x44]);
+        data.extend_from_slice(&21.5f32.to_be_bytes());
+        data.push(0x2F);
+        // status-flags, with a priority
+        data.extend_from_slice(&[0x09, 111, 0x2E, 0x82, 0x04, 0x80, 0x2F, 0x39, 8]);
+        // A constructed value, like a day of a weekly-schedule
+        data.extend_from_slice(&[
+            0x09, 123, 0x1A, 0x00, 0x03, 0x2E, 0x0E, 0xB4, 7, 0, 0, 0, 0x00,
+        ]);
+        data.extend_from_slice(&[0x0F, 0x2F]);
+        // A value that can't be decoded
+        data.extend_from_slice(&[0x09, 28, 0x2E, 0xD1, 0x00, 0x2F]);
+        data.push(0x4F);
+
+        let (process_id, notification) = decode_cov_notification(&data).unwrap();
+        assert_eq!(process_id, 18);
+        assert_eq!(notification.device_id, 100);
+        assert_eq!(
+            (notification.object_type, notification.object_instance),
+            (0, 3)
+        );
+        assert_eq!(notification.time_remaining, 60);
+        assert_eq!(notification.values.len(), 3);
+        assert_eq!(notification.values[&85], BACnetValue::Real(21.5));
+        assert_eq!(
+            notification.values[&111],
+            BACnetValue::BitString(vec![true, false, false, false])
+        );
+        assert_eq!(
+            notification.values[&123],
+            BACnetValue::Constructed {
+                tag: 0,
+                values: vec![
+                    BACnetValue::Time {
+                        hour: 7,
+                        minute: 0,
+                        second: 0,
+                        hundredths: 0,
+                    },
+                    BACnetValue::Null,
+                ],
+            }
+        );
+
+        // Without the end of the list of values
+        assert!(decode_cov_notification(&data[..data.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,480 @@
+//! Configuration of the datalink the network runs on
+//!
+//! Normally the stack configures itself from `BACNET_*` environment variables (see `dlenv.c` in
+//! bacnet-stack). A `DatalinkConfig` passed to `BACnetNetwork::start()` does the same thing
+//! without touching the environment, anything that isn't set keeps the stack's default.
+//!
+//! BACnet/IP is always available, other datalinks are behind a feature:
+//! - `bip6`: BACnet/IPv6, see `DatalinkConfig::bip6()`
+//! - `mstp`: MS/TP over a serial port, see `DatalinkConfig::mstp()`
+//! - `ethernet`: BACnet/Ethernet (ISO 8802-3), see `DatalinkConfig::ethernet()`
+
+// The stack's services send through datalink_send_pdu(), which is the one datalink that was built
+// or (with several) the one picked by datalink_set(). We receive on our own, so the functions here
+// pass on to the datalink the network was started on.
+
+use crate::{bbmd, errors::Result, BACnetErr};
+#[cfg(any(feature = "bip6", feature = "mstp", feature = "ethernet"))]
+use bacnet_sys::datalink_set;
+use bacnet_sys::{
+    apdu_retries_set, apdu_timeout_set, bip_get_broadcast_address, bip_get_my_address,
+    bip_get_port, bip_init, bip_receive, bip_send_pdu, bip_set_addr, bip_set_broadcast_addr,
+    bip_set_port, BACNET_ADDRESS, BACNET_IP_ADDRESS, BACNET_NPDU_DATA,
+};
+#[cfg(feature = "bip6")]
+use bacnet_sys::{
+    bip6_get_broadcast_address, bip6_get_my_address, bip6_get_port, bip6_init, bip6_receive,
+    bip6_send_pdu, bip6_set_broadcast_addr, bip6_set_port, vmac_data, VMAC_Add, BACNET_IP6_ADDRESS,
+};
+#[cfg(feature = "mstp")]
+use bacnet_sys::{
+    dlmstp_get_broadcast_address, dlmstp_get_my_address, dlmstp_init, dlmstp_receive,
+    dlmstp_send_pdu, dlmstp_set_baud_rate, dlmstp_set_mac_address, dlmstp_set_max_master,
+};
+#[cfg(feature = "ethernet")]
+use bacnet_sys::{
+    ethernet_get_broadcast_address, ethernet_get_my_address, ethernet_init, ethernet_receive,
+    ethernet_send_pdu,
+};
+use log::error;
+use once_cell::sync::OnceCell;
+#[cfg(feature = "bip6")]
+use std::net::{Ipv6Addr, SocketAddrV6};
+use std::{
+    ffi::CString,
+    net::{Ipv4Addr, SocketAddrV4},
+    os::raw::c_char,
+    time::Duration,
+};
+
+static DATALINK: OnceCell<Datalink> = OnceCell::new();
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum Datalink {
+    #[default]
+    Bip,
+    #[cfg(feature = "bip6")]
+    Bip6,
+    #[cfg(feature = "mstp")]
+    Mstp,
+    #[cfg(feature = "ethernet")]
+    Ethernet,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DatalinkConfig {
+    datalink: Datalink,
+
+    /// Network interface to use, like "eth0", or the serial port for MS/TP
+    interface: Option<String>,
+
+    /// Our own address, as the stack announces it and recognizes its own broadcasts by
+    address: Option<Ipv4Addr>,
+
+    /// UDP port to listen on and send from
+    port: Option<u16>,
+
+    /// Address broadcasts are sent to
+    broadcast_address: Option<Ipv4Addr>,
+
+    /// Multicast address broadcasts are sent to, on BACnet/IPv6
+    #[cfg(feature = "bip6")]
+    multicast_address: Option<Ipv6Addr>,
+
+    /// Baud rate of the serial port, on MS/TP
+    #[cfg(feature = "mstp")]
+    baud_rate: Option<u32>,
+
+    /// Our MAC address (station address) on MS/TP
+    #[cfg(feature = "mstp")]
+    mac_address: Option<u8>,
+
+    /// Highest MAC address polled for other masters, on MS/TP
+    #[cfg(feature = "mstp")]
+    max_master: Option<u8>,
+
+    /// BBMD to register with as a foreign device, and the time-to-live of the registration
+    bbmd: Option<(SocketAddrV4, Duration)>,
+
+    /// How long to wait for the response to a request before sending it again
+    apdu_timeout: Option<Duration>,
+
+    /// How many times a request is sent again if no response comes in
+    apdu_retries: Option<u8>,
+}
+
+// DatalinkConfig::new().interface("eth0").port(47809)
+impl DatalinkConfig {
+    /// A configuration for BACnet/IP
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A configuration for BACnet/IPv6
+    ///
+    /// Only the interface, port, multicast address and APDU settings apply.
+    #[cfg(feature = "bip6")]
+    pub fn bip6() -> Self {
+        Self {
+            datalink: Datalink::Bip6,
+            ..Self::default()
+        }
+    }
+
+    /// A configuration for MS/TP on a serial port, like "/dev/ttyUSB0"
+    ///
+    /// Only the baud rate, MAC address, max-master and APDU settings apply.
+    #[cfg(feature = "mstp")]
+    pub fn mstp(port: impl Into<String>) -> Self {
+        Self {
+            datalink: Datalink::Mstp,
+            interface: Some(port.into()),
+            ..Self::default()
+        }
+    }
+
+    /// A configuration for BACnet/Ethernet on a network interface, like "eth0"
+    ///
+    /// Only the APDU settings apply. Opening the interface needs CAP_NET_RAW (or root).
+    #[cfg(feature = "ethernet")]
+    pub fn ethernet(interface: impl Into<String>) -> Self {
+        Self {
+            datalink: Datalink::Ethernet,
+            interface: Some(interface.into()),
+            ..Self::default()
+        }
+    }
+
+    /// Set the network interface to use. Default: the stack picks one
+    pub fn interface(mut self, interface: impl Into<String>) -> Self {
+        self.interface = Some(interface.into());
+        self
+    }
+
+    /// Set our own address. Default: the address of the interface
+    pub fn address(mut self, address: Ipv4Addr) -> Self {
+        self.address = Some(address);
+        self
+    }
+
+    /// Set the UDP port. Default: 47808 (0xBAC0)
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        self
+    }
+
+    /// Set the address broadcasts are sent to. Default: the broadcast address of the interface
+    pub fn broadcast_address(mut self, broadcast_address: Ipv4Addr) -> Self {
+        self.broadcast_address = Some(broadcast_address);
+        self
+    }
+
+    /// Set the multicast address broadcasts are sent to on BACnet/IPv6. Default: FF02::BAC0
+    /// (link-local)
+    #[cfg(feature = "bip6")]
+    pub fn multicast_address(mut self, multicast_address: Ipv6Addr) -> Self {
+        self.multicast_address = Some(multicast_address);
+        self
+    }
+
+    /// Set the baud rate of the serial port on MS/TP. Default: 38400
+    #[cfg(feature = "mstp")]
+    pub fn baud_rate(mut self, baud_rate: u32) -> Self {
+        self.baud_rate = Some(baud_rate);
+        self
+    }
+
+    /// Set our MAC address on MS/TP, 0 to 127. Every master on the wire needs its own
+    #[cfg(feature = "mstp")]
+    pub fn mac_address(mut self, mac_address: u8) -> Self {
+        self.mac_address = Some(mac_address);
+        self
+    }
+
+    /// Set the highest MAC address that is polled for other masters on MS/TP, up to 127.
+    /// Default: 127
+    #[cfg(feature = "mstp")]
+    pub fn max_master(mut self, max_master: u8) -> Self {
+        self.max_master = Some(max_master);
+        self
+    }
+
+    /// Register with a BBMD as a foreign device, see `BACnetNetwork::register_foreign_device()`.
+    /// Default: no registration
+    pub fn bbmd(mut self, bbmd: SocketAddrV4, ttl: Duration) -> Self {
+        self.bbmd = Some((bbmd, ttl));
+        self
+    }
+
+    /// Set the default APDU timeout of requests. Default: 3 seconds, see `RequestOptions`
+    pub fn apdu_timeout(mut self, timeout: Duration) -> Self {
+        self.apdu_timeout = Some(timeout);
+        self
+    }
+
+    /// Set the default number of retries of requests. Default: 3, see `RequestOptions`
+    pub fn apdu_retries(mut self, retries: u8) -> Self {
+        self.apdu_retries = Some(retries);
+        self
+    }
+
+    // Initialize the datalink, in place of dlenv_init()
+    pub(crate) fn init(&self) -> Result<()> {
+        if let Some(timeout) = self.apdu_timeout {
+            unsafe { apdu_timeout_set(timeout.as_millis().min(u16::MAX as u128) as u16) };
+        }
+        if let Some(retries) = self.apdu_retries {
+            unsafe { apdu_retries_set(retries) };
+        }
+
+        let interface = self
+            .interface
+            .as_deref()
+            .map(CString::new)
+            .transpose()
+            .map_err(|_| BACnetErr::DatalinkInitFailed {
+                reason: "interface name contains a NUL byte".to_string(),
+            })?;
+        let interface = interface
+            .as_ref()
+            .map_or(std::ptr::null_mut(), |name| name.as_ptr() as *mut c_char);
+
+        match self.datalink {
+            Datalink::Bip => self.init_bip(interface)?,
+            #[cfg(feature = "bip6")]
+            Datalink::Bip6 => self.init_bip6(interface)?,
+            #[cfg(feature = "mstp")]
+            Datalink::Mstp => self.init_mstp(interface)?,
+            #[cfg(feature = "ethernet")]
+            Datalink::Ethernet => self.init_ethernet(interface)?,
+        }
+        let _ = DATALINK.set(self.datalink);
+        Ok(())
+    }
+
+    fn init_bip(&self, interface: *mut c_char) -> Result<()> {
+        #[cfg(any(feature = "bip6", feature = "mstp", feature = "ethernet"))]
+        unsafe {
+            datalink_set(c"bip".as_ptr() as *mut _)
+        };
+        if let Some(port) = self.port {
+            unsafe { bip_set_port(port) };
+        }
+        if !unsafe { bip_init(interface) } {
+            return Err(self.open_failed("BACnet/IP"));
+        }
+
+        let port = unsafe { bip_get_port() };
+        if let Some(address) = self.address {
+            unsafe { bip_set_addr(&ip_address(address, port)) };
+        }
+        if let Some(broadcast_address) = self.broadcast_address {
+            unsafe { bip_set_broadcast_addr(&ip_address(broadcast_address, port)) };
+        }
+
+        // The network isn't running yet, so there's nobody to wait for the BBMD's answer
+        if let Some((address, ttl)) = self.bbmd {
+            bbmd::register(
+                address,
+                ttl,
+                Box::new(move |result| {
+                    if let Err(err) = result {
+                        error!("registering with BBMD {} failed: {}", address, err);
+                    }
+                }),
+            );
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "bip6")]
+    fn init_bip6(&self, interface: *mut c_char) -> Result<()> {
+        if self.address.is_some() || self.broadcast_address.is_some() || self.bbmd.is_some() {
+            return Err(BACnetErr::DatalinkInitFailed {
+                reason: "IPv4 addresses and BBMDs don't apply to BACnet/IPv6".to_string(),
+            });
+        }
+
+        unsafe { datalink_set(c"bip6".as_ptr() as *mut _) };
+        if let Some(port) = self.port {
+            unsafe { bip6_set_port(port) };
+        }
+        if !unsafe { bip6_init(interface) } {
+            return Err(self.open_failed("BACnet/IPv6"));
+        }
+        if let Some(multicast_address) = self.multicast_address {
+            let address = BACNET_IP6_ADDRESS {
+                address: multicast_address.octets(),
+                port: unsafe { bip6_get_port() },
+            };
+            unsafe { bip6_set_broadcast_addr(&address) };
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "mstp")]
+    fn init_mstp(&self, port: *mut c_char) -> Result<()> {
+        if self.has_ip_settings() {
+            return Err(BACnetErr::DatalinkInitFailed {
+                reason: "IP addresses, ports and BBMDs don't apply to MS/TP".to_string(),
+            });
+        }
+        if port.is_null() {
+            return Err(BACnetErr::DatalinkInitFailed {
+                reason: "no serial port to run MS/TP on".to_string(),
+            });
+        }
+        // Slave nodes (128 and up) can't send requests
+        if self.mac_address.unwrap_or(0) > 127 || self.max_master.unwrap_or(0) > 127 {
+            return Err(BACnetErr::DatalinkInitFailed {
+                reason: "MS/TP MAC address and max-master must be 127 or lower".to_string(),
+            });
+        }
+
+        unsafe { datalink_set(c"mstp".as_ptr() as *mut _) };
+        // The settings are picked up by dlmstp_init()
+        if let Some(baud_rate) = self.baud_rate {
+            unsafe { dlmstp_set_baud_rate(baud_rate) };
+        }
+        if let Some(mac_address) = self.mac_address {
+            unsafe { dlmstp_set_mac_address(mac_address) };
+        }
+        if let Some(max_master) = self.max_master {
+            unsafe { dlmstp_set_max_master(max_master) };
+        }
+        if !unsafe { dlmstp_init(port) } {
+            return Err(self.open_failed("MS/TP"));
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "ethernet")]
+    fn init_ethernet(&self, interface: *mut c_char) -> Result<()> {
+        if self.has_ip_settings() {
+            return Err(BACnetErr::DatalinkInitFailed {
+                reason: "IP addresses, ports and BBMDs don't apply to BACnet/Ethernet".to_string(),
+            });
+        }
+        #[cfg(feature = "mstp")]
+        if self.baud_rate.is_some() || self.mac_address.is_some() || self.max_master.is_some() {
+            return Err(BACnetErr::DatalinkInitFailed {
+                reason: "MS/TP settings don't apply to BACnet/Ethernet".to_string(),
+            });
+        }
+        if interface.is_null() {
+            return Err(BACnetErr::DatalinkInitFailed {
+                reason: "no interface to run BACnet/Ethernet on".to_string(),
+            });
+        }
+
+        unsafe { datalink_set(c"ethernet".as_ptr() as *mut _) };
+        if !unsafe { ethernet_init(interface) } {
+            return Err(self.open_failed("BACnet/Ethernet"));
+        }
+        Ok(())
+    }
+
+    // Whether anything that only applies to BACnet/IP or BACnet/IPv6 is set
+    #[cfg(any(feature = "mstp", feature = "ethernet"))]
+    fn has_ip_settings(&self) -> bool {
+        #[cfg(feature = "bip6")]
+        if self.multicast_address.is_some() {
+            return true;
+        }
+        self.address.is_some()
+            || self.port.is_some()
+            || self.broadcast_address.is_some()
+            || self.bbmd.is_some()
+    }
+
+    fn open_failed(&self, datalink: &str) -> BACnetErr {
+        BACnetErr::DatalinkInitFailed {
+            reason: format!(
+                "couldn't open {} on interface {}",
+                datalink,
+                self.interface.as_deref().unwrap_or("(default)")
+            ),
+        }
+    }
+}
+
+// The datalink the network was started on
+fn datalink() -> Datalink {
+    DATALINK.get().copied().unwrap_or_default()
+}
+
+// Wait up to `timeout` milliseconds for a PDU
+pub(crate) fn receive(src: &mut BACNET_ADDRESS, pdu: &mut [u8], timeout: u32) -> u16 {
+    let max_pdu = pdu.len().min(u16::MAX as usize) as u16;
+    match datalink() {
+        Datalink::Bip => unsafe { bip_receive(src, pdu.as_mut_ptr(), max_pdu, timeout) },
+        #[cfg(feature = "bip6")]
+        Datalink::Bip6 => unsafe { bip6_receive(src, pdu.as_mut_ptr(), max_pdu, timeout) },
+        #[cfg(feature = "mstp")]
+        Datalink::Mstp => unsafe { dlmstp_receive(src, pdu.as_mut_ptr(), max_pdu, timeout) },
+        #[cfg(feature = "ethernet")]
+        Datalink::Ethernet => unsafe { ethernet_receive(src, pdu.as_mut_ptr(), max_pdu, timeout) },
+    }
+}
+
+pub(crate) fn send_pdu(
+    dest: &mut BACNET_ADDRESS,
+    npdu_data: &mut BACNET_NPDU_DATA,
+    pdu: &mut [u8],
+) -> i32 {
+    let len = pdu.len() as u32;
+    match datalink() {
+        Datalink::Bip => unsafe { bip_send_pdu(dest, npdu_data, pdu.as_mut_ptr(), len) },
+        #[cfg(feature = "bip6")]
+        Datalink::Bip6 => unsafe { bip6_send_pdu(dest, npdu_data, pdu.as_mut_ptr(), len) },
+        #[cfg(feature = "mstp")]
+        Datalink::Mstp => unsafe { dlmstp_send_pdu(dest, npdu_data, pdu.as_mut_ptr(), len) },
+        #[cfg(feature = "ethernet")]
+        Datalink::Ethernet => unsafe { ethernet_send_pdu(dest, npdu_data, pdu.as_mut_ptr(), len) },
+    }
+}
+
+pub(crate) fn get_my_address(my_address: &mut BACNET_ADDRESS) {
+    match datalink() {
+        Datalink::Bip => unsafe { bip_get_my_address(my_address) },
+        #[cfg(feature = "bip6")]
+        Datalink::Bip6 => unsafe { bip6_get_my_address(my_address) },
+        #[cfg(feature = "mstp")]
+        Datalink::Mstp => unsafe { dlmstp_get_my_address(my_address) },
+        #[cfg(feature = "ethernet")]
+        Datalink::Ethernet => unsafe { ethernet_get_my_address(my_address) },
+    }
+}
+
+pub(crate) fn get_broadcast_address(dest: &mut BACNET_ADDRESS) {
+    match datalink() {
+        Datalink::Bip => unsafe { bip_get_broadcast_address(dest) },
+        #[cfg(feature = "bip6")]
+        Datalink::Bip6 => unsafe { bip6_get_broadcast_address(dest) },
+        #[cfg(feature = "mstp")]
+        Datalink::Mstp => unsafe { dlmstp_get_broadcast_address(dest) },
+        #[cfg(feature = "ethernet")]
+        Datalink::Ethernet => unsafe { ethernet_get_broadcast_address(dest) },
+    }
+}
+
+// Tell the stack where to find the device with the VMAC in `addr`, as it only learns of VMACs
+// through the devices talking to it
+#[cfg(feature = "bip6")]
+pub(crate) fn add_vmac(addr: &BACNET_ADDRESS, ipv6: SocketAddrV6) {
+    let vmac = u32::from_be_bytes([0, addr.mac[0], addr.mac[1], addr.mac[2]]);
+    let mut data = vmac_data {
+        mac_len: 18,
+        ..Default::default()
+    };
+    data.mac[..16].copy_from_slice(&ipv6.ip().octets());
+    data.mac[16..].copy_from_slice(&ipv6.port().to_be_bytes());
+    unsafe { VMAC_Add(vmac, &mut data) };
+}
+
+fn ip_address(address: Ipv4Addr, port: u16) -> BACNET_IP_ADDRESS {
+    BACNET_IP_ADDRESS {
+        address: address.octets(),
+        port,
+    }
+}
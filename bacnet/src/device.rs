@@ -0,0 +1,1294 @@
+//! The local device: BACnet objects hosted by this process
+//!
+//! Objects are registered with `LocalDevice::add_object()`, after which other devices can read
+//! and write them (ReadProperty, ReadPropertyMultiple, WriteProperty and WritePropertyMultiple).
+//! Their values live on the Rust side: the application updates them with
+//! `LocalDevice::set_present_value()`, and hears of writes through the object's `on_write`
+//! callback.
+//!
+//! Other devices can subscribe to changes of value (SubscribeCOV) of the objects that report them,
+//! and are notified when their values change, by more than the cov-increment for a real present
+//! value. The subscriptions are kept by the stack, and listed in the Device object's
+//! active-cov-subscriptions. Confirmed notifications are sent once, with the default APDU timeout:
+//! they aren't retried when the subscriber doesn't acknowledge them.
+//!
+//! Other devices can find the objects by name or identifier with Who-Has, which is answered with
+//! an I-Have. `LocalDevice::announce_i_have()` sends one unasked.
+//!
+//! Other object types, proprietary ones included, are hosted by implementing `BACnetObject`.
+//!
+//! The Device object itself is the stack's. Its identity (instance, name, vendor and so on) is set
+//! with `LocalDevice::builder()`, before the network is started.
+//!
+//! ```ignore
+//! let device = LocalDevice::get()?;
+//! device.add_object(
+//!     LocalObject::new(OBJECT_ANALOG_VALUE, 1, "Supply temperature")?.units(UNITS_DEGREES_CELSIUS),
+//! )?;
+//! device.set_present_value(OBJECT_ANALOG_VALUE, 1, BACnetValue::Real(18.5))?;
+//! ```
+
+// The stack finds objects through a table of object_functions_t, with an entry (a set of
+// callbacks) per object type. We hand it our own table: the stack's Device object, and a slot for
+// every object type that has objects registered. Some callbacks don't say which object type
+// they're called for, so every slot has its own set of callbacks, told apart by a const generic.
+// A slot is taken by setting its Object_Type, the first entry without one ends the table. The
+// stack takes an object type with a value list callback to report changes of value, the callbacks
+// for that are dropped from the slot when its objects don't.
+//
+// The table is only touched on the network thread, where the stack reads it.
+
+use crate::{
+    encoding::{decode_application_data, encode_application_data, encode_data},
+    errors::Result,
+    value::BACnetValue,
+    BACnetErr, BACnetNetwork, ObjectPropertyId, ObjectType,
+};
+use bacnet_sys::{
+    characterstring_init_ansi_safe, handler_cov_task, handler_cov_timer_seconds,
+    object_functions_t, BACnetObjectType_MAX_BACNET_OBJECT_TYPE as MAX_BACNET_OBJECT_TYPE,
+    BACnetObjectType_OBJECT_ANALOG_INPUT as OBJECT_ANALOG_INPUT,
+    BACnetObjectType_OBJECT_ANALOG_OUTPUT as OBJECT_ANALOG_OUTPUT,
+    BACnetObjectType_OBJECT_ANALOG_VALUE as OBJECT_ANALOG_VALUE,
+    BACnetObjectType_OBJECT_BINARY_INPUT as OBJECT_BINARY_INPUT,
+    BACnetObjectType_OBJECT_BINARY_OUTPUT as OBJECT_BINARY_OUTPUT,
+    BACnetObjectType_OBJECT_BINARY_VALUE as OBJECT_BINARY_VALUE,
+    BACnetObjectType_OBJECT_DEVICE as OBJECT_DEVICE,
+    BACnetObjectType_OBJECT_MULTI_STATE_INPUT as OBJECT_MULTI_STATE_INPUT,
+    BACnetObjectType_OBJECT_MULTI_STATE_OUTPUT as OBJECT_MULTI_STATE_OUTPUT,
+    BACnetObjectType_OBJECT_MULTI_STATE_VALUE as OBJECT_MULTI_STATE_VALUE,
+    BACnet_Error_Class_ERROR_CLASS_OBJECT as ERROR_CLASS_OBJECT,
+    BACnet_Error_Class_ERROR_CLASS_PROPERTY as ERROR_CLASS_PROPERTY,
+    BACnet_Error_Code_ERROR_CODE_ABORT_SEGMENTATION_NOT_SUPPORTED,
+    BACnet_Error_Code_ERROR_CODE_INVALID_ARRAY_INDEX as ERROR_CODE_INVALID_ARRAY_INDEX,
+    BACnet_Error_Code_ERROR_CODE_INVALID_DATA_TYPE as ERROR_CODE_INVALID_DATA_TYPE,
+    BACnet_Error_Code_ERROR_CODE_PROPERTY_IS_NOT_AN_ARRAY as ERROR_CODE_PROPERTY_IS_NOT_AN_ARRAY,
+    BACnet_Error_Code_ERROR_CODE_UNKNOWN_OBJECT as ERROR_CODE_UNKNOWN_OBJECT,
+    BACnet_Error_Code_ERROR_CODE_UNKNOWN_PROPERTY as ERROR_CODE_UNKNOWN_PROPERTY,
+    BACnet_Error_Code_ERROR_CODE_VALUE_OUT_OF_RANGE as ERROR_CODE_VALUE_OUT_OF_RANGE,
+    BACnet_Error_Code_ERROR_CODE_WRITE_ACCESS_DENIED as ERROR_CODE_WRITE_ACCESS_DENIED,
+    DeviceGetRRInfo, Device_Count, Device_Index_To_Instance, Device_Init,
+    Device_Object_Instance_Number, Device_Object_Name, Device_Object_Name_ANSI_Init,
+    Device_Property_Lists, Device_Read_Property_Local, Device_Set_Application_Software_Version,
+    Device_Set_Description, Device_Set_Location, Device_Set_Model_Name,
+    Device_Set_Object_Instance_Number, Device_Set_Vendor_Identifier, Device_Set_Vendor_Name,
+    Device_Valid_Object_Instance_Number, Device_Write_Property_Local, Send_I_Have,
+    BACNET_ARRAY_ALL, BACNET_CHARACTER_STRING, BACNET_MAX_INSTANCE,
+    BACNET_PROPERTY_ID_PROP_COV_INCREMENT as PROP_COV_INCREMENT,
+    BACNET_PROPERTY_ID_PROP_DESCRIPTION as PROP_DESCRIPTION,
+    BACNET_PROPERTY_ID_PROP_EVENT_STATE as PROP_EVENT_STATE,
+    BACNET_PROPERTY_ID_PROP_NUMBER_OF_STATES as PROP_NUMBER_OF_STATES,
+    BACNET_PROPERTY_ID_PROP_OBJECT_IDENTIFIER as PROP_OBJECT_IDENTIFIER,
+    BACNET_PROPERTY_ID_PROP_OBJECT_NAME as PROP_OBJECT_NAME,
+    BACNET_PROPERTY_ID_PROP_OBJECT_TYPE as PROP_OBJECT_TYPE,
+    BACNET_PROPERTY_ID_PROP_OUT_OF_SERVICE as PROP_OUT_OF_SERVICE,
+    BACNET_PROPERTY_ID_PROP_POLARITY as PROP_POLARITY,
+    BACNET_PROPERTY_ID_PROP_PRESENT_VALUE as PROP_PRESENT_VALUE,
+    BACNET_PROPERTY_ID_PROP_PRIORITY_ARRAY as PROP_PRIORITY_ARRAY,
+    BACNET_PROPERTY_ID_PROP_RELINQUISH_DEFAULT as PROP_RELINQUISH_DEFAULT,
+    BACNET_PROPERTY_ID_PROP_STATUS_FLAGS as PROP_STATUS_FLAGS,
+    BACNET_PROPERTY_ID_PROP_UNITS as PROP_UNITS, BACNET_PROPERTY_VALUE, BACNET_READ_PROPERTY_DATA,
+    BACNET_STATUS_ABORT, BACNET_STATUS_ERROR, BACNET_WRITE_PROPERTY_DATA,
+    MAX_CHARACTER_STRING_BYTES,
+};
+use log::{debug, warn};
+use once_cell::sync::Lazy;
+use std::{
+    any::Any,
+    cell::UnsafeCell,
+    collections::{BTreeMap, HashMap},
+    ffi::CString,
+    fmt,
+    os::raw::{c_char, c_int, c_uint},
+    ptr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// How many different object types the local device can host
+pub const MAX_OBJECT_TYPES: usize = 32;
+
+// "no-units", the units of an analog object that doesn't say
+const NO_UNITS: u32 = 95;
+
+// The longest text the stack keeps for the Device object's text properties, the sizes of its
+// buffers in device.c (MAX_DEV_*_LEN)
+const MAX_DEV_NAME_LEN: usize = 32;
+const MAX_DEV_MOD_LEN: usize = 32;
+const MAX_DEV_VER_LEN: usize = 16;
+const MAX_DEV_DESC_LEN: usize = 64;
+const MAX_DEV_LOC_LEN: usize = 64;
+
+// The identity the Device object is given when the network starts
+static IDENTITY: Mutex<Identity> = Mutex::new(Identity::Pending(None));
+
+type Objects = BTreeMap<(ObjectType, u32), Arc<Mutex<dyn AnyObject>>>;
+
+static OBJECTS: Lazy<Mutex<Objects>> = Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+// The object type of every slot that's taken, in order
+static SLOTS: Lazy<Mutex<Vec<ObjectType>>> = Lazy::new(|| Mutex::new(vec![]));
+
+// The property lists (ended by -1) of every slot, which the stack keeps pointers to
+static PROPERTY_LISTS: Lazy<Mutex<Vec<PropertyLists>>> = Lazy::new(|| Mutex::new(vec![]));
+
+static OBJECT_TABLE: Lazy<ObjectTable> = Lazy::new(ObjectTable::new);
+
+// The values of the COV properties of every object, as they were last reported
+static REPORTED: Lazy<Mutex<HashMap<(ObjectType, u32), CovValues>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+static COV_TICK: Lazy<Mutex<Instant>> = Lazy::new(|| Mutex::new(Instant::now()));
+
+type CovValues = Vec<(ObjectPropertyId, BACnetValue)>;
+
+struct PropertyLists {
+    required: Box<[c_int]>,
+    optional: Box<[c_int]>,
+    proprietary: Box<[c_int]>,
+}
+
+// The Device object, a slot per object type, and the end of the table
+struct ObjectTable(UnsafeCell<[object_functions_t; MAX_OBJECT_TYPES + 2]>);
+
+// Only the network thread reads or writes the table
+unsafe impl Sync for ObjectTable {}
+
+macro_rules! slot_entries {
+    ($($slot:literal)*) => {
+        [$(slot_entry::<$slot>()),*]
+    };
+}
+
+impl ObjectTable {
+    fn new() -> Self {
+        let slots: [object_functions_t; MAX_OBJECT_TYPES] = slot_entries!(
+            0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15
+            16 17 18 19 20 21 22 23 24 25 26 27 28 29 30 31
+        );
+        let mut table = [object_functions_t {
+            Object_Type: MAX_BACNET_OBJECT_TYPE,
+            ..Default::default()
+        }; MAX_OBJECT_TYPES + 2];
+        table[0] = object_functions_t {
+            Object_Type: OBJECT_DEVICE,
+            // The stack's Device_Init() is what walks this table, so it isn't called again
+            Object_Init: None,
+            Object_Count: Some(Device_Count),
+            Object_Index_To_Instance: Some(Device_Index_To_Instance),
+            Object_Valid_Instance: Some(Device_Valid_Object_Instance_Number),
+            Object_Name: Some(Device_Object_Name),
+            Object_Read_Property: Some(Device_Read_Property_Local),
+            Object_Write_Property: Some(Device_Write_Property_Local),
+            Object_RPM_List: Some(Device_Property_Lists),
+            Object_RR_Info: Some(DeviceGetRRInfo),
+            ..Default::default()
+        };
+        table[1..=MAX_OBJECT_TYPES].copy_from_slice(&slots);
+        Self(UnsafeCell::new(table))
+    }
+
+    fn as_mut_ptr(&self) -> *mut object_functions_t {
+        self.0.get() as *mut object_functions_t
+    }
+}
+
+// The callbacks of a slot, before it's taken
+fn slot_entry<const SLOT: usize>() -> object_functions_t {
+    object_functions_t {
+        Object_Type: MAX_BACNET_OBJECT_TYPE,
+        Object_Count: Some(object_count::<SLOT>),
+        Object_Index_To_Instance: Some(object_index_to_instance::<SLOT>),
+        Object_Valid_Instance: Some(object_valid_instance::<SLOT>),
+        Object_Name: Some(object_name::<SLOT>),
+        Object_Read_Property: Some(read_property),
+        Object_Write_Property: Some(write_property),
+        Object_RPM_List: Some(property_lists::<SLOT>),
+        Object_Value_List: Some(object_value_list::<SLOT>),
+        Object_COV: Some(object_cov::<SLOT>),
+        Object_COV_Clear: Some(object_cov_clear::<SLOT>),
+        ..Default::default()
+    }
+}
+
+// Hand our object table to the stack. Must run on the network thread, before anything is
+// received.
+pub(crate) fn init() {
+    unsafe { Device_Init(OBJECT_TABLE.as_mut_ptr()) };
+}
+
+// Take a slot for a new object type, which reports changes of value or not. Must run on the
+// network thread.
+fn take_slot(slot: usize, object_type: ObjectType, reports_cov: bool) {
+    let entry = unsafe { &mut *OBJECT_TABLE.as_mut_ptr().add(1 + slot) };
+    if !reports_cov {
+        entry.Object_Value_List = None;
+        entry.Object_COV = None;
+        entry.Object_COV_Clear = None;
+    }
+    entry.Object_Type = object_type;
+}
+
+// Send the COV notifications that are due, and age the subscriptions. Runs on the network thread,
+// after every PDU.
+pub(crate) fn maintain() {
+    let mut last_tick = COV_TICK.lock().unwrap();
+    let seconds = last_tick.elapsed().as_secs().min(u32::MAX as u64);
+    if seconds > 0 {
+        *last_tick += Duration::from_secs(seconds);
+        unsafe { handler_cov_timer_seconds(seconds as u32) };
+    }
+    unsafe { handler_cov_task() };
+}
+
+/// Why a property couldn't be read or written, as it's reported to the device asking
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PropertyError {
+    UnknownObject,
+    UnknownProperty,
+    NotAnArray,
+    InvalidArrayIndex,
+    WriteAccessDenied,
+    InvalidDataType,
+    ValueOutOfRange,
+    /// Any other error class and code
+    Other {
+        class: u32,
+        code: u32,
+    },
+}
+
+impl PropertyError {
+    fn class_and_code(self) -> (u32, u32) {
+        let code = match self {
+            PropertyError::UnknownObject => return (ERROR_CLASS_OBJECT, ERROR_CODE_UNKNOWN_OBJECT),
+            PropertyError::UnknownProperty => ERROR_CODE_UNKNOWN_PROPERTY,
+            PropertyError::NotAnArray => ERROR_CODE_PROPERTY_IS_NOT_AN_ARRAY,
+            PropertyError::InvalidArrayIndex => ERROR_CODE_INVALID_ARRAY_INDEX,
+            PropertyError::WriteAccessDenied => ERROR_CODE_WRITE_ACCESS_DENIED,
+            PropertyError::InvalidDataType => ERROR_CODE_INVALID_DATA_TYPE,
+            PropertyError::ValueOutOfRange => ERROR_CODE_VALUE_OUT_OF_RANGE,
+            PropertyError::Other { class, code } => return (class, code),
+        };
+        (ERROR_CLASS_PROPERTY, code)
+    }
+}
+
+pub type PropertyResult<T> = std::result::Result<T, PropertyError>;
+
+/// The properties of an object type, apart from object-identifier, object-name and object-type
+/// which every object has
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PropertyList {
+    pub required: Vec<ObjectPropertyId>,
+    pub optional: Vec<ObjectPropertyId>,
+    pub proprietary: Vec<ObjectPropertyId>,
+}
+
+/// An object hosted by the local device, of any object type but the Device
+///
+/// The device answers for object-identifier, object-name and object-type itself, the object for
+/// the rest of its properties. An array property is read whole, as a `BACnetValue::Array`, and the
+/// device picks out the element that was asked for.
+///
+/// The methods are called on the network thread, so they should be quick.
+pub trait BACnetObject: Send + 'static {
+    fn object_type(&self) -> ObjectType;
+
+    fn object_instance(&self) -> u32;
+
+    fn object_name(&self) -> String;
+
+    /// The properties of the object. The stack keeps one list per object type, taken from the
+    /// first object of the type that's added.
+    fn property_list(&self) -> PropertyList;
+
+    fn read_property(&self, property: ObjectPropertyId) -> PropertyResult<BACnetValue>;
+
+    /// Write a property, with the priority given by the writer (for a commandable property).
+    /// Default: nothing can be written
+    fn write_property(
+        &mut self,
+        property: ObjectPropertyId,
+        _value: BACnetValue,
+        _priority: Option<u8>,
+    ) -> PropertyResult<()> {
+        self.read_property(property)
+            .and(Err(PropertyError::WriteAccessDenied))
+    }
+
+    /// The properties reported in a COV notification. Default: none, for an object that doesn't
+    /// report changes of value
+    fn cov_properties(&self) -> Vec<ObjectPropertyId> {
+        vec![]
+    }
+
+    /// How much a real present value has to change to be reported. Default: `None`, any change
+    /// is reported
+    fn cov_increment(&self) -> Option<f32> {
+        None
+    }
+}
+
+// A hosted object, which can be had back as its own type
+trait AnyObject: BACnetObject {
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: BACnetObject> AnyObject for T {
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+type WriteCallback = Box<dyn FnMut(&BACnetValue, Option<u8>) -> PropertyResult<()> + Send>;
+
+/// An analog, binary or multi-state input, output or value object, hosted by the local device
+///
+/// Outputs are commandable: writes go into the priority array, and the present value is the one
+/// with the highest priority (or the relinquish default). Inputs only take writes to their present
+/// value while they're out of service.
+pub struct LocalObject {
+    object_type: ObjectType,
+    object_instance: u32,
+    object_name: String,
+    description: String,
+    present_value: BACnetValue,
+    out_of_service: bool,
+    units: u32,
+    number_of_states: u32,
+    cov_increment: f32,
+    priority_array: [BACnetValue; 16],
+    on_write: Option<WriteCallback>,
+}
+
+// LocalObject::new(OBJECT_ANALOG_INPUT, 1, "Outside temperature")?.units(62)
+impl LocalObject {
+    /// A new object of one of the analog, binary or multi-state object types
+    pub fn new(
+        object_type: ObjectType,
+        object_instance: u32,
+        name: impl Into<String>,
+    ) -> Result<Self> {
+        let present_value = match Kind::of(object_type) {
+            Some(Kind::Analog) => BACnetValue::Real(0.0),
+            Some(Kind::Binary) => BACnetValue::Enum(0, None), // inactive
+            Some(Kind::MultiState) => BACnetValue::Uint(1),
+            None => return Err(BACnetErr::UnsupportedObjectType { object_type }),
+        };
+        Ok(Self {
+            object_type,
+            object_instance,
+            object_name: name.into(),
+            description: String::new(),
+            present_value,
+            out_of_service: false,
+            units: NO_UNITS,
+            number_of_states: 2,
+            cov_increment: 0.0,
+            priority_array: std::array::from_fn(|_| BACnetValue::Null),
+            on_write: None,
+        })
+    }
+
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.description = description.into();
+        self
+    }
+
+    /// Set the engineering units of an analog object. Default: no-units
+    pub fn units(mut self, units: u32) -> Self {
+        self.units = units;
+        self
+    }
+
+    /// Set how much the present value of an analog object has to change to be reported to COV
+    /// subscribers. Default: 0, any change
+    pub fn cov_increment(mut self, cov_increment: f32) -> Self {
+        self.cov_increment = cov_increment.max(0.0);
+        self
+    }
+
+    /// Set the number of states of a multi-state object. Default: 2
+    pub fn number_of_states(mut self, number_of_states: u32) -> Self {
+        self.number_of_states = number_of_states.max(1);
+        self
+    }
+
+    /// Set the initial present value (the relinquish default of an output)
+    pub fn present_value(mut self, value: BACnetValue) -> Result<Self> {
+        self.set_present_value(value)
+            .map_err(|_| BACnetErr::InvalidValue)?;
+        Ok(self)
+    }
+
+    /// Call `callback` when another device writes the present value, with the value and the
+    /// priority it was written with. An error is what the writer gets back, and the value isn't
+    /// taken.
+    ///
+    /// The callback runs on the network thread, so it should be quick. It must not go through
+    /// `LocalDevice` for this object.
+    pub fn on_write(
+        mut self,
+        callback: impl FnMut(&BACnetValue, Option<u8>) -> PropertyResult<()> + Send + 'static,
+    ) -> Self {
+        self.on_write = Some(Box::new(callback));
+        self
+    }
+
+    fn kind(&self) -> Kind {
+        Kind::of(self.object_type).unwrap()
+    }
+
+    fn is_commandable(&self) -> bool {
+        matches!(
+            self.object_type,
+            OBJECT_ANALOG_OUTPUT | OBJECT_BINARY_OUTPUT | OBJECT_MULTI_STATE_OUTPUT
+        )
+    }
+
+    fn is_input(&self) -> bool {
+        matches!(
+            self.object_type,
+            OBJECT_ANALOG_INPUT | OBJECT_BINARY_INPUT | OBJECT_MULTI_STATE_INPUT
+        )
+    }
+
+    // The present value, which for an output is the value with the highest priority
+    fn effective_value(&self) -> BACnetValue {
+        if self.is_commandable() {
+            if let Some(value) = self
+                .priority_array
+                .iter()
+                .find(|value| **value != BACnetValue::Null)
+            {
+                return value.clone();
+            }
+        }
+        self.present_value.clone()
+    }
+
+    // Set the present value (or the relinquish default of an output)
+    fn set_present_value(&mut self, value: BACnetValue) -> PropertyResult<()> {
+        self.present_value = self.check_value(value)?;
+        Ok(())
+    }
+
+    // Check that a value fits the object, and put it the way it's kept
+    fn check_value(&self, value: BACnetValue) -> PropertyResult<BACnetValue> {
+        match (self.kind(), value) {
+            (Kind::Analog, BACnetValue::Real(f)) => Ok(BACnetValue::Real(f)),
+            (Kind::Binary, BACnetValue::Enum(state @ (0 | 1), _)) => {
+                Ok(BACnetValue::Enum(state, None))
+            }
+            (Kind::Binary, BACnetValue::Enum(..)) => Err(PropertyError::ValueOutOfRange),
+            (Kind::MultiState, BACnetValue::Uint(state)) => {
+                if state >= 1 && state <= self.number_of_states as u64 {
+                    Ok(BACnetValue::Uint(state))
+                } else {
+                    Err(PropertyError::ValueOutOfRange)
+                }
+            }
+            _ => Err(PropertyError::InvalidDataType),
+        }
+    }
+}
+
+impl BACnetObject for LocalObject {
+    fn object_type(&self) -> ObjectType {
+        self.object_type
+    }
+
+    fn object_instance(&self) -> u32 {
+        self.object_instance
+    }
+
+    fn object_name(&self) -> String {
+        self.object_name.clone()
+    }
+
+    fn property_list(&self) -> PropertyList {
+        let mut required = vec![
+            PROP_PRESENT_VALUE,
+            PROP_STATUS_FLAGS,
+            PROP_EVENT_STATE,
+            PROP_OUT_OF_SERVICE,
+        ];
+        match self.kind() {
+            Kind::Analog => required.push(PROP_UNITS),
+            Kind::Binary if self.object_type != OBJECT_BINARY_VALUE => required.push(PROP_POLARITY),
+            Kind::Binary => {}
+            Kind::MultiState => required.push(PROP_NUMBER_OF_STATES),
+        }
+        if self.is_commandable() {
+            required.extend([PROP_PRIORITY_ARRAY, PROP_RELINQUISH_DEFAULT]);
+        }
+        let mut optional = vec![PROP_DESCRIPTION];
+        if self.kind() == Kind::Analog {
+            optional.push(PROP_COV_INCREMENT);
+        }
+        PropertyList {
+            required,
+            optional,
+            proprietary: vec![],
+        }
+    }
+
+    fn read_property(&self, property: ObjectPropertyId) -> PropertyResult<BACnetValue> {
+        let kind = self.kind();
+        Ok(match property {
+            PROP_PRESENT_VALUE => self.effective_value(),
+            PROP_DESCRIPTION => BACnetValue::String(self.description.clone()),
+            // in-alarm, fault, overridden, out-of-service
+            PROP_STATUS_FLAGS => {
+                BACnetValue::BitString(vec![false, false, false, self.out_of_service])
+            }
+            PROP_EVENT_STATE => BACnetValue::Enum(0, None), // normal
+            PROP_OUT_OF_SERVICE => BACnetValue::Bool(self.out_of_service),
+            PROP_UNITS if kind == Kind::Analog => BACnetValue::Enum(self.units, None),
+            PROP_COV_INCREMENT if kind == Kind::Analog => BACnetValue::Real(self.cov_increment),
+            PROP_POLARITY if kind == Kind::Binary && self.object_type != OBJECT_BINARY_VALUE => {
+                BACnetValue::Enum(0, None) // normal
+            }
+            PROP_NUMBER_OF_STATES if kind == Kind::MultiState => {
+                BACnetValue::Uint(self.number_of_states as u64)
+            }
+            PROP_PRIORITY_ARRAY if self.is_commandable() => {
+                BACnetValue::Array(self.priority_array.to_vec())
+            }
+            PROP_RELINQUISH_DEFAULT if self.is_commandable() => self.present_value.clone(),
+            _ => return Err(PropertyError::UnknownProperty),
+        })
+    }
+
+    fn write_property(
+        &mut self,
+        property: ObjectPropertyId,
+        value: BACnetValue,
+        priority: Option<u8>,
+    ) -> PropertyResult<()> {
+        match property {
+            PROP_PRESENT_VALUE => {
+                if self.is_input() && !self.out_of_service {
+                    return Err(PropertyError::WriteAccessDenied);
+                }
+                // Writing NULL relinquishes a priority
+                let value = match value {
+                    BACnetValue::Null if self.is_commandable() => BACnetValue::Null,
+                    value => self.check_value(value)?,
+                };
+                if let Some(on_write) = &mut self.on_write {
+                    on_write(&value, priority)?;
+                }
+                if self.is_commandable() {
+                    self.priority_array[priority.unwrap_or(16) as usize - 1] = value;
+                } else {
+                    self.present_value = value;
+                }
+                Ok(())
+            }
+            PROP_OUT_OF_SERVICE => match value {
+                BACnetValue::Bool(out_of_service) => {
+                    self.out_of_service = out_of_service;
+                    Ok(())
+                }
+                _ => Err(PropertyError::InvalidDataType),
+            },
+            PROP_COV_INCREMENT if self.kind() == Kind::Analog => match value {
+                BACnetValue::Real(cov_increment) if cov_increment >= 0.0 => {
+                    self.cov_increment = cov_increment;
+                    Ok(())
+                }
+                BACnetValue::Real(_) => Err(PropertyError::ValueOutOfRange),
+                _ => Err(PropertyError::InvalidDataType),
+            },
+            _ => self
+                .read_property(property)
+                .and(Err(PropertyError::WriteAccessDenied)),
+        }
+    }
+
+    fn cov_properties(&self) -> Vec<ObjectPropertyId> {
+        vec![PROP_PRESENT_VALUE, PROP_STATUS_FLAGS]
+    }
+
+    fn cov_increment(&self) -> Option<f32> {
+        (self.kind() == Kind::Analog).then_some(self.cov_increment)
+    }
+}
+
+impl fmt::Debug for LocalObject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LocalObject")
+            .field("object_type", &self.object_type)
+            .field("object_instance", &self.object_instance)
+            .field("object_name", &self.object_name)
+            .field("present_value", &self.effective_value())
+            .field("out_of_service", &self.out_of_service)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Analog,
+    Binary,
+    MultiState,
+}
+
+impl Kind {
+    fn of(object_type: ObjectType) -> Option<Kind> {
+        match object_type {
+            OBJECT_ANALOG_INPUT | OBJECT_ANALOG_OUTPUT | OBJECT_ANALOG_VALUE => Some(Kind::Analog),
+            OBJECT_BINARY_INPUT | OBJECT_BINARY_OUTPUT | OBJECT_BINARY_VALUE => Some(Kind::Binary),
+            OBJECT_MULTI_STATE_INPUT | OBJECT_MULTI_STATE_OUTPUT | OBJECT_MULTI_STATE_VALUE => {
+                Some(Kind::MultiState)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The device of this process, and the objects it hosts
+#[derive(Debug)]
+pub struct LocalDevice {
+    _private: (),
+}
+
+static LOCAL_DEVICE: LocalDevice = LocalDevice { _private: () };
+
+impl LocalDevice {
+    /// The local device, which starts the network if it isn't running yet (see
+    /// `BACnetNetwork::get()`)
+    pub fn get() -> Result<&'static LocalDevice> {
+        BACnetNetwork::get()?;
+        Ok(&LOCAL_DEVICE)
+    }
+
+    /// Set the identity of the local device, before the network is started
+    pub fn builder() -> LocalDeviceBuilder {
+        LocalDeviceBuilder::default()
+    }
+
+    /// Host an object, so other devices can read and write it
+    pub fn add_object<O: BACnetObject>(&self, object: O) -> Result<()> {
+        let object_type = object.object_type();
+        let key = (object_type, object.object_instance());
+        if object_type == OBJECT_DEVICE || object_type >= MAX_BACNET_OBJECT_TYPE {
+            return Err(BACnetErr::UnsupportedObjectType { object_type });
+        }
+        let properties = object.property_list();
+        let reports_cov = !object.cov_properties().is_empty();
+        {
+            let mut objects = OBJECTS.lock().unwrap();
+            if objects.contains_key(&key) {
+                return Err(BACnetErr::ObjectExists {
+                    object_type: key.0,
+                    object_instance: key.1,
+                });
+            }
+            objects.insert(key, Arc::new(Mutex::new(object)));
+        }
+
+        let mut slots = SLOTS.lock().unwrap();
+        if slots.contains(&object_type) {
+            return Ok(());
+        }
+        if slots.len() == MAX_OBJECT_TYPES {
+            OBJECTS.lock().unwrap().remove(&key);
+            return Err(BACnetErr::TableFull {
+                max: MAX_OBJECT_TYPES,
+            });
+        }
+        let required: Vec<_> = [PROP_OBJECT_IDENTIFIER, PROP_OBJECT_NAME, PROP_OBJECT_TYPE]
+            .into_iter()
+            .chain(properties.required)
+            .collect();
+        PROPERTY_LISTS.lock().unwrap().push(PropertyLists {
+            required: property_list(&required),
+            optional: property_list(&properties.optional),
+            proprietary: property_list(&properties.proprietary),
+        });
+        let slot = slots.len();
+        slots.push(object_type);
+        drop(slots);
+        debug!("object type {} takes slot {}", object_type, slot);
+        BACnetNetwork::get()?.run(move || take_slot(slot, object_type, reports_cov))
+    }
+
+    /// Stop hosting an object
+    pub fn remove_object(&self, object_type: ObjectType, object_instance: u32) -> Result<()> {
+        REPORTED
+            .lock()
+            .unwrap()
+            .remove(&(object_type, object_instance));
+        OBJECTS
+            .lock()
+            .unwrap()
+            .remove(&(object_type, object_instance))
+            .map(|_| ())
+            .ok_or(BACnetErr::UnknownObject {
+                object_type,
+                object_instance,
+            })
+    }
+
+    /// Broadcast an I-Have for a hosted object, telling the network it's here without being asked
+    /// by a Who-Has. For a newly added object, say.
+    pub fn announce_i_have(&self, object_type: ObjectType, object_instance: u32) -> Result<()> {
+        let name = find(object_type, object_instance)?
+            .lock()
+            .unwrap()
+            .object_name();
+        BACnetNetwork::get()?.run(move || unsafe {
+            let mut object_name = BACNET_CHARACTER_STRING::default();
+            if !characterstring_init_ansi_safe(
+                &mut object_name,
+                name.as_ptr() as *const c_char,
+                name.len(),
+            ) {
+                return Err(BACnetErr::InvalidValue);
+            }
+            Send_I_Have(
+                Device_Object_Instance_Number(),
+                object_type,
+                object_instance,
+                &mut object_name,
+            );
+            Ok(())
+        })?
+    }
+
+    /// The present value of a hosted object
+    pub fn present_value(
+        &self,
+        object_type: ObjectType,
+        object_instance: u32,
+    ) -> Result<BACnetValue> {
+        let object = find(object_type, object_instance)?;
+        let value = object.lock().unwrap().read_property(PROP_PRESENT_VALUE);
+        value.map_err(|_| BACnetErr::NoValue)
+    }
+
+    /// Call `f` with a hosted object of type `O`, to look at or change it. Fails with
+    /// `UnknownObject` if there's no such object, or it isn't an `O`.
+    ///
+    /// The network thread waits for `f` if it needs the object meanwhile.
+    pub fn update_object<O: BACnetObject, R>(
+        &self,
+        object_type: ObjectType,
+        object_instance: u32,
+        f: impl FnOnce(&mut O) -> R,
+    ) -> Result<R> {
+        let object = find(object_type, object_instance)?;
+        let mut object = object.lock().unwrap();
+        match object.as_any_mut().downcast_mut::<O>() {
+            Some(object) => Ok(f(object)),
+            None => Err(BACnetErr::UnknownObject {
+                object_type,
+                object_instance,
+            }),
+        }
+    }
+
+    /// Update the present value of a hosted object. For an output, this sets the relinquish
+    /// default, which is the present value while nothing is commanded.
+    pub fn set_present_value(
+        &self,
+        object_type: ObjectType,
+        object_instance: u32,
+        value: BACnetValue,
+    ) -> Result<()> {
+        self.update_object(object_type, object_instance, |object: &mut LocalObject| {
+            object.set_present_value(value)
+        })?
+        .map_err(|_| BACnetErr::InvalidValue)
+    }
+}
+
+enum Identity {
+    Pending(Option<LocalDeviceBuilder>),
+    Applied,
+}
+
+/// The identity of the local device, which has to be set before the network is started
+///
+/// Whatever isn't set keeps the stack's default. Text is limited by the stack, to 16 bytes for
+/// the application-software-version, 32 for the vendor and model names, 64 for the description
+/// and location, and to a character string for the object-name.
+///
+/// The max-APDU-length-accepted sizes the stack's buffers, so it's set when building: with the
+/// `BACNET_MAX_APDU` environment variable (50 to 1476, by default 1476 on BACnet/IP). The stack
+/// is built without segmentation, which makes segmentation-supported always no-segmentation.
+#[derive(Debug, Clone, Default)]
+pub struct LocalDeviceBuilder {
+    instance: Option<u32>,
+    object_name: Option<String>,
+    vendor_identifier: Option<u16>,
+    vendor_name: Option<String>,
+    model_name: Option<String>,
+    application_software_version: Option<String>,
+    description: Option<String>,
+    location: Option<String>,
+}
+
+// LocalDevice::builder().instance(1234).object_name("Gateway 1").build()
+impl LocalDeviceBuilder {
+    pub fn instance(mut self, instance: u32) -> Self {
+        self.instance = Some(instance);
+        self
+    }
+
+    pub fn object_name(mut self, object_name: impl Into<String>) -> Self {
+        self.object_name = Some(object_name.into());
+        self
+    }
+
+    pub fn vendor_identifier(mut self, vendor_identifier: u16) -> Self {
+        self.vendor_identifier = Some(vendor_identifier);
+        self
+    }
+
+    pub fn vendor_name(mut self, vendor_name: impl Into<String>) -> Self {
+        self.vendor_name = Some(vendor_name.into());
+        self
+    }
+
+    pub fn model_name(mut self, model_name: impl Into<String>) -> Self {
+        self.model_name = Some(model_name.into());
+        self
+    }
+
+    pub fn application_software_version(mut self, version: impl Into<String>) -> Self {
+        self.application_software_version = Some(version.into());
+        self
+    }
+
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    pub fn location(mut self, location: impl Into<String>) -> Self {
+        self.location = Some(location.into());
+        self
+    }
+
+    /// Keep the identity for when the network starts. Fails with `AlreadyStarted` if it has
+    /// started already, so this has to come before `BACnetNetwork::start()`, or anything else
+    /// that uses the network. Text the stack can't keep fails with `IdentityRejected`.
+    pub fn build(self) -> Result<&'static LocalDevice> {
+        if self
+            .instance
+            .is_some_and(|instance| instance > BACNET_MAX_INSTANCE)
+        {
+            return Err(BACnetErr::InvalidValue);
+        }
+        // Refused here rather than when the network starts, where it can't be told apart from the
+        // network failing
+        let texts = [
+            (
+                "object-name",
+                &self.object_name,
+                MAX_CHARACTER_STRING_BYTES as usize,
+            ),
+            ("vendor-name", &self.vendor_name, MAX_DEV_NAME_LEN),
+            ("model-name", &self.model_name, MAX_DEV_MOD_LEN),
+            (
+                "application-software-version",
+                &self.application_software_version,
+                MAX_DEV_VER_LEN,
+            ),
+            ("description", &self.description, MAX_DEV_DESC_LEN),
+            ("location", &self.location, MAX_DEV_LOC_LEN),
+        ];
+        for (property, value, max_len) in texts {
+            if value
+                .as_ref()
+                .is_some_and(|value| value.len() > max_len || value.contains('\0'))
+            {
+                return Err(BACnetErr::IdentityRejected {
+                    property: property.to_string(),
+                });
+            }
+        }
+        match &mut *IDENTITY.lock().unwrap() {
+            Identity::Pending(identity) => *identity = Some(self),
+            Identity::Applied => return Err(BACnetErr::AlreadyStarted),
+        }
+        Ok(&LOCAL_DEVICE)
+    }
+
+    // Give the Device object this identity. Must run on the network thread.
+    fn apply(&self) -> Result<()> {
+        if let Some(instance) = self.instance {
+            unsafe { Device_Set_Object_Instance_Number(instance) };
+        }
+        if let Some(object_name) = &self.object_name {
+            let rejected = || BACnetErr::IdentityRejected {
+                property: "object-name".to_string(),
+            };
+            let object_name = CString::new(object_name.as_str()).map_err(|_| rejected())?;
+            // The name is copied, it needn't outlive the call
+            if !unsafe { Device_Object_Name_ANSI_Init(object_name.as_ptr()) } {
+                return Err(rejected());
+            }
+        }
+        if let Some(vendor_identifier) = self.vendor_identifier {
+            unsafe { Device_Set_Vendor_Identifier(vendor_identifier) };
+        }
+        set_text("vendor-name", &self.vendor_name, Device_Set_Vendor_Name)?;
+        set_text("model-name", &self.model_name, Device_Set_Model_Name)?;
+        set_text(
+            "application-software-version",
+            &self.application_software_version,
+            Device_Set_Application_Software_Version,
+        )?;
+        set_text("description", &self.description, Device_Set_Description)?;
+        set_text("location", &self.location, Device_Set_Location)?;
+        Ok(())
+    }
+}
+
+// Set a text property of the Device object, with one of the stack's setters (which copy it)
+fn set_text(
+    property: &str,
+    value: &Option<String>,
+    set: unsafe extern "C" fn(*const c_char, usize) -> bool,
+) -> Result<()> {
+    match value {
+        Some(value) if !unsafe { set(value.as_ptr() as *const c_char, value.len()) } => {
+            Err(BACnetErr::IdentityRejected {
+                property: property.to_string(),
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+// Give the Device object the identity from `LocalDevice::builder()`, if there's one. Must run on
+// the network thread, before the datalink is up.
+pub(crate) fn apply_identity() -> Result<()> {
+    let identity = std::mem::replace(&mut *IDENTITY.lock().unwrap(), Identity::Applied);
+    match identity {
+        Identity::Pending(Some(identity)) => identity.apply(),
+        _ => Ok(()),
+    }
+}
+
+fn find(object_type: ObjectType, object_instance: u32) -> Result<Arc<Mutex<dyn AnyObject>>> {
+    OBJECTS
+        .lock()
+        .unwrap()
+        .get(&(object_type, object_instance))
+        .cloned()
+        .ok_or(BACnetErr::UnknownObject {
+            object_type,
+            object_instance,
+        })
+}
+
+fn property_list(properties: &[ObjectPropertyId]) -> Box<[c_int]> {
+    properties
+        .iter()
+        .map(|&property| property as c_int)
+        .chain([-1])
+        .collect()
+}
+
+fn slot_type(slot: usize) -> ObjectType {
+    SLOTS
+        .lock()
+        .unwrap()
+        .get(slot)
+        .copied()
+        .unwrap_or(MAX_BACNET_OBJECT_TYPE)
+}
+
+// The instances of an object type, in order
+fn instances(object_type: ObjectType) -> Vec<u32> {
+    OBJECTS
+        .lock()
+        .unwrap()
+        .range((object_type, 0)..=(object_type, u32::MAX))
+        .map(|(&(_, instance), _)| instance)
+        .collect()
+}
+
+unsafe extern "C" fn object_count<const SLOT: usize>() -> c_uint {
+    instances(slot_type(SLOT)).len() as c_uint
+}
+
+unsafe extern "C" fn object_index_to_instance<const SLOT: usize>(index: c_uint) -> u32 {
+    instances(slot_type(SLOT))
+        .get(index as usize)
+        .copied()
+        .unwrap_or(u32::MAX)
+}
+
+unsafe extern "C" fn object_valid_instance<const SLOT: usize>(object_instance: u32) -> bool {
+    find(slot_type(SLOT), object_instance).is_ok()
+}
+
+unsafe extern "C" fn object_name<const SLOT: usize>(
+    object_instance: u32,
+    object_name: *mut BACNET_CHARACTER_STRING,
+) -> bool {
+    let Ok(object) = find(slot_type(SLOT), object_instance) else {
+        return false;
+    };
+    let name = object.lock().unwrap().object_name();
+    characterstring_init_ansi_safe(object_name, name.as_ptr() as *const c_char, name.len())
+}
+
+unsafe extern "C" fn property_lists<const SLOT: usize>(
+    required: *mut *const c_int,
+    optional: *mut *const c_int,
+    proprietary: *mut *const c_int,
+) {
+    let lists = PROPERTY_LISTS.lock().unwrap();
+    let Some(lists) = lists.get(SLOT) else {
+        return;
+    };
+    // The lists never move or go away, so the pointers stay good
+    if !required.is_null() {
+        *required = lists.required.as_ptr();
+    }
+    if !optional.is_null() {
+        *optional = lists.optional.as_ptr();
+    }
+    if !proprietary.is_null() {
+        *proprietary = lists.proprietary.as_ptr();
+    }
+}
+
+// The current values of an object's COV properties, and its cov-increment
+fn cov_values(object_type: ObjectType, object_instance: u32) -> (CovValues, Option<f32>) {
+    let Ok(object) = find(object_type, object_instance) else {
+        return (vec![], None);
+    };
+    let object = object.lock().unwrap();
+    let values = object
+        .cov_properties()
+        .into_iter()
+        .filter_map(|property| Some((property, object.read_property(property).ok()?)))
+        .collect();
+    (values, object.cov_increment())
+}
+
+// Whether the values have changed enough since they were reported to be reported again
+fn cov_changed(reported: &CovValues, current: &CovValues, cov_increment: Option<f32>) -> bool {
+    reported.len() != current.len()
+        || reported.iter().zip(current).any(|(reported, current)| {
+            match (reported, current, cov_increment) {
+                (
+                    (PROP_PRESENT_VALUE, BACnetValue::Real(reported)),
+                    (PROP_PRESENT_VALUE, BACnetValue::Real(current)),
+                    Some(cov_increment),
+                ) => current != reported && (current - reported).abs() >= cov_increment,
+                _ => reported != current,
+            }
+        })
+}
+
+unsafe extern "C" fn object_cov<const SLOT: usize>(object_instance: u32) -> bool {
+    let object_type = slot_type(SLOT);
+    let (current, cov_increment) = cov_values(object_type, object_instance);
+    if current.is_empty() {
+        return false;
+    }
+    match REPORTED
+        .lock()
+        .unwrap()
+        .get(&(object_type, object_instance))
+    {
+        Some(reported) => cov_changed(reported, &current, cov_increment),
+        None => true,
+    }
+}
+
+// The stack has notified the subscribers of the current values
+unsafe extern "C" fn object_cov_clear<const SLOT: usize>(object_instance: u32) {
+    let object_type = slot_type(SLOT);
+    let (current, _) = cov_values(object_type, object_instance);
+    REPORTED
+        .lock()
+        .unwrap()
+        .insert((object_type, object_instance), current);
+}
+
+// Fill in the values of a COV notification. The stack hands over a linked list with room for a
+// few values, the unused end of it is cut off.
+unsafe extern "C" fn object_value_list<const SLOT: usize>(
+    object_instance: u32,
+    value_list: *mut BACNET_PROPERTY_VALUE,
+) -> bool {
+    let (values, _) = cov_values(slot_type(SLOT), object_instance);
+    let mut entry = value_list;
+    let mut last = ptr::null_mut();
+    for (property, value) in values {
+        if entry.is_null() {
+            warn!("no room for property {} in a COV notification", property);
+            break;
+        }
+        let Ok(value) = encode_data(value) else {
+            warn!(
+                "couldn't encode property {} for a COV notification",
+                property
+            );
+            return false;
+        };
+        (*entry).propertyIdentifier = property;
+        (*entry).propertyArrayIndex = BACNET_ARRAY_ALL;
+        (*entry).value = value;
+        (*entry).priority = 0; // none
+        last = entry;
+        entry = (*entry).next;
+    }
+    if last.is_null() {
+        return false;
+    }
+    (*last).next = ptr::null_mut();
+    true
+}
+
+unsafe extern "C" fn read_property(rpdata: *mut BACNET_READ_PROPERTY_DATA) -> c_int {
+    let rpdata = &mut *rpdata;
+    let value = find(rpdata.object_type, rpdata.object_instance)
+        .map_err(|_| PropertyError::UnknownObject)
+        .and_then(|object| {
+            let object = object.lock().unwrap();
+            match rpdata.object_property {
+                PROP_OBJECT_IDENTIFIER => Ok(BACnetValue::ObjectId {
+                    object_type: rpdata.object_type,
+                    object_instance: rpdata.object_instance,
+                }),
+                PROP_OBJECT_NAME => Ok(BACnetValue::String(object.object_name())),
+                PROP_OBJECT_TYPE => Ok(BACnetValue::Enum(rpdata.object_type, None)),
+                property => object.read_property(property),
+            }
+        })
+        .and_then(|value| at_index(value, rpdata.array_index));
+    encode_property(rpdata, value)
+}
+
+// Put a property value (or the reason there's none) in the answer to a read
+unsafe fn encode_property(
+    rpdata: &mut BACNET_READ_PROPERTY_DATA,
+    value: PropertyResult<BACnetValue>,
+) -> c_int {
+    let encoded = match value.map(|value| encode_application_data(&value)) {
+        Ok(Ok(encoded)) => encoded,
+        Ok(Err(err)) => {
+            warn!(
+                "couldn't encode property {}: {}",
+                rpdata.object_property, err
+            );
+            (rpdata.error_class, rpdata.error_code) =
+                PropertyError::ValueOutOfRange.class_and_code();
+            return BACNET_STATUS_ERROR;
+        }
+        Err(err) => {
+            (rpdata.error_class, rpdata.error_code) = err.class_and_code();
+            return BACNET_STATUS_ERROR;
+        }
+    };
+    if encoded.len() > rpdata.application_data_len.max(0) as usize {
+        rpdata.error_code = BACnet_Error_Code_ERROR_CODE_ABORT_SEGMENTATION_NOT_SUPPORTED;
+        return BACNET_STATUS_ABORT;
+    }
+    std::ptr::copy_nonoverlapping(encoded.as_ptr(), rpdata.application_data, encoded.len());
+    encoded.len() as c_int
+}
+
+// The element of an array value at a BACnet array index (where 0 is the number of elements)
+fn at_index(value: BACnetValue, index: u32) -> PropertyResult<BACnetValue> {
+    match (value, index) {
+        (value, BACNET_ARRAY_ALL) => Ok(value),
+        (BACnetValue::Array(values), 0) => Ok(BACnetValue::Uint(values.len() as u64)),
+        (BACnetValue::Array(mut values), index) if index as usize <= values.len() => {
+            Ok(values.swap_remove(index as usize - 1))
+        }
+        (BACnetValue::Array(_), _) => Err(PropertyError::InvalidArrayIndex),
+        _ => Err(PropertyError::NotAnArray),
+    }
+}
+
+unsafe extern "C" fn write_property(wpdata: *mut BACNET_WRITE_PROPERTY_DATA) -> bool {
+    let wpdata = &mut *wpdata;
+    let len = (wpdata.application_data_len.max(0) as usize).min(wpdata.application_data.len());
+    let result = if wpdata.array_index != BACNET_ARRAY_ALL {
+        Err(PropertyError::NotAnArray)
+    } else {
+        decode_application_data(
+            &wpdata.application_data[..len],
+            wpdata.object_type,
+            wpdata.object_property,
+        )
+        .map_err(|_| PropertyError::InvalidDataType)
+        .and_then(|value| {
+            let object = find(wpdata.object_type, wpdata.object_instance)
+                .map_err(|_| PropertyError::UnknownObject)?;
+            if let PROP_OBJECT_IDENTIFIER | PROP_OBJECT_NAME | PROP_OBJECT_TYPE =
+                wpdata.object_property
+            {
+                return Err(PropertyError::WriteAccessDenied);
+            }
+            let priority = Some(wpdata.priority).filter(|priority| (1..=16).contains(priority));
+            let result =
+                object
+                    .lock()
+                    .unwrap()
+                    .write_property(wpdata.object_property, value, priority);
+            result
+        })
+    };
+    match result {
+        Ok(()) => true,
+        Err(err) => {
+            (wpdata.error_class, wpdata.error_code) = err.class_and_code();
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Nothing the stack would refuse gets as far as starting the network
+    #[test]
+    fn identity_rejected() {
+        let rejected = |builder: LocalDeviceBuilder| match builder.build() {
+            Err(BACnetErr::IdentityRejected { property }) => property,
+            result => panic!("not rejected: {:?}", result.map(|_| ())),
+        };
+        assert_eq!(
+            rejected(LocalDevice::builder().location("x".repeat(65))),
+            "location"
+        );
+        assert_eq!(
+            rejected(LocalDevice::builder().application_software_version("1.0.0-rc.1+20261017")),
+            "application-software-version"
+        );
+        assert_eq!(
+            rejected(LocalDevice::builder().object_name("Gateway\0 1")),
+            "object-name"
+        );
+        assert!(matches!(*IDENTITY.lock().unwrap(), Identity::Pending(None)));
+    }
+
+    #[test]
+    fn unknown_object() {
+        let mut rpdata: BACNET_READ_PROPERTY_DATA = unsafe { std::mem::zeroed() };
+        rpdata.object_type = OBJECT_ANALOG_VALUE;
+        rpdata.object_instance = 4194302;
+        rpdata.object_property = PROP_PRESENT_VALUE;
+        rpdata.array_index = BACNET_ARRAY_ALL;
+        assert_eq!(unsafe { read_property(&mut rpdata) }, BACNET_STATUS_ERROR);
+        assert_eq!(
+            (rpdata.error_class, rpdata.error_code),
+            (ERROR_CLASS_OBJECT, ERROR_CODE_UNKNOWN_OBJECT)
+        );
+    }
+}
@@ -1,209 +1,197 @@
-use crate::{cstr, errors::Result, value::BACnetValue, BACnetErr};
+use crate::{
+    bacnet_error,
+    charset::{decode_character_string, encode_character_string},
+    cstr,
+    enums::enum_name,
+    errors::Result,
+    value::{BACnetValue, CharacterSet, DateTime},
+    BACnetErr, ObjectPropertyId, ObjectType, ReadAccessResult,
+};
 use bacnet_sys::{
-    bacapp_decode_application_data, bactext_application_tag_name,
-    bactext_binary_present_value_name, bactext_engineering_unit_name, bactext_object_type_name,
-    bitstring_bit, bitstring_bits_used, bitstring_init, bitstring_set_bit,
-    BACnetObjectType_OBJECT_PROPRIETARY_MIN, BACNET_APPLICATION_DATA_VALUE, BACNET_BIT_STRING,
-    BACNET_CHARACTER_STRING, BACNET_OCTET_STRING, BACNET_READ_PROPERTY_DATA, BACNET_STATUS_ERROR,
-    MAX_ASHRAE_OBJECT_TYPE,
+    bactext_application_tag_name, bitstring_init, bitstring_set_bit, BACNET_APPLICATION_DATA_VALUE,
+    BACNET_BIT_STRING, BACNET_CHARACTER_STRING, BACNET_DATE_TIME, BACNET_OCTET_STRING,
+    BACNET_READ_PROPERTY_DATA,
 };
+use std::collections::HashMap;
 
 pub fn decode_data(data: BACNET_READ_PROPERTY_DATA) -> Result<BACnetValue> {
-    let mut value = BACNET_APPLICATION_DATA_VALUE::default();
-    let appdata = data.application_data;
-    let appdata_len = data.application_data_len;
+    if data.application_data.is_null() || data.application_data_len <= 0 {
+        return Err(BACnetErr::DecodeFailed);
+    }
+    let appdata = unsafe {
+        std::slice::from_raw_parts(data.application_data, data.application_data_len as usize)
+    };
 
-    let len = unsafe { bacapp_decode_application_data(appdata, appdata_len as u32, &mut value) };
+    decode_application_data(appdata, data.object_type, data.object_property)
+}
 
-    if len == BACNET_STATUS_ERROR {
-        return Err(BACnetErr::DecodeFailed);
+// Properties whose datatype (in clause 12 of ASHRAE 135) is a single SEQUENCE of more than one
+// value, rather than a BACnetARRAY or BACnetLIST: BACnetDateRange, BACnetPrescale,
+// BACnetHostNPort, BACnetLightingCommand and the (device) object property references. A
+// BACnetDateTime is recognized by its values instead (see decode_values()).
+//
+// Only these decode to a `BACnetValue::Sequence`. Any other property with several values decodes
+// to a `BACnetValue::Array`, which is encoded the same way, so a property missing here is still
+// written back as it was read.
+const SEQUENCE_PROPERTIES: &[ObjectPropertyId] = &[
+    bacnet_sys::BACNET_PROPERTY_ID_PROP_EFFECTIVE_PERIOD,
+    bacnet_sys::BACNET_PROPERTY_ID_PROP_PRESCALE,
+    bacnet_sys::BACNET_PROPERTY_ID_PROP_FD_BBMD_ADDRESS,
+    bacnet_sys::BACNET_PROPERTY_ID_PROP_BACNET_IP_GLOBAL_ADDRESS,
+    bacnet_sys::BACNET_PROPERTY_ID_PROP_LIGHTING_COMMAND,
+    bacnet_sys::BACNET_PROPERTY_ID_PROP_LOG_DEVICE_OBJECT_PROPERTY,
+    bacnet_sys::BACNET_PROPERTY_ID_PROP_OBJECT_PROPERTY_REFERENCE,
+    bacnet_sys::BACNET_PROPERTY_ID_PROP_EVENT_ALGORITHM_INHIBIT_REF,
+    bacnet_sys::BACNET_PROPERTY_ID_PROP_SETPOINT_REFERENCE,
+    bacnet_sys::BACNET_PROPERTY_ID_PROP_MANIPULATED_VARIABLE_REFERENCE,
+    bacnet_sys::BACNET_PROPERTY_ID_PROP_CONTROLLED_VARIABLE_REFERENCE,
+    bacnet_sys::BACNET_PROPERTY_ID_PROP_INPUT_REFERENCE,
+];
+
+/// Decode every value in an encoded property value
+///
+/// A single value is returned as is. Several values are returned as a `BACnetValue::Array`, or as
+/// a `BACnetValue::Sequence` for properties holding a single structured value. Context tagged
+/// values (found in structured values) are returned as `BACnetValue::Context` and
+/// `BACnetValue::Constructed`, so they can be written back as they were read.
+pub fn decode_application_data(
+    data: &[u8],
+    object_type: ObjectType,
+    object_property: ObjectPropertyId,
+) -> Result<BACnetValue> {
+    let mut reader = TagReader::new(data);
+    let mut values = decode_values(&mut reader, None, object_type, object_property)?;
+
+    match values.len() {
+        0 => Err(BACnetErr::DecodeFailed),
+        1 => Ok(values.remove(0)),
+        _ if SEQUENCE_PROPERTIES.contains(&object_property) => Ok(BACnetValue::Sequence(values)),
+        _ => Ok(BACnetValue::Array(values)),
     }
+}
 
-    Ok(match value.tag as u32 {
-        bacnet_sys::BACNET_APPLICATION_TAG_BACNET_APPLICATION_TAG_NULL => BACnetValue::Null,
-        bacnet_sys::BACNET_APPLICATION_TAG_BACNET_APPLICATION_TAG_BOOLEAN => {
-            BACnetValue::Bool(unsafe { value.type_.Boolean })
-        }
-        bacnet_sys::BACNET_APPLICATION_TAG_BACNET_APPLICATION_TAG_SIGNED_INT => {
-            BACnetValue::Int(unsafe { value.type_.Signed_Int })
-        }
-        bacnet_sys::BACNET_APPLICATION_TAG_BACNET_APPLICATION_TAG_UNSIGNED_INT => {
-            BACnetValue::Uint(unsafe { value.type_.Unsigned_Int })
-        }
-        bacnet_sys::BACNET_APPLICATION_TAG_BACNET_APPLICATION_TAG_REAL => {
-            BACnetValue::Real(unsafe { value.type_.Real })
-        }
-        bacnet_sys::BACNET_APPLICATION_TAG_BACNET_APPLICATION_TAG_DOUBLE => {
-            BACnetValue::Double(unsafe { value.type_.Double })
-        }
-        bacnet_sys::BACNET_APPLICATION_TAG_BACNET_APPLICATION_TAG_CHARACTER_STRING => {
-            // BACnet string has the following structure
-            // size_t length, uint8_t encoding, char value[MAX_CHARACTER_STRING_BYTES]
-            // For now just assume UTF-8 bytes, but we really should respect encodings...
-            //
-            // FIXME(tj): Look at value.type_.Character_String.encoding
-            let s = cstr(unsafe {
-                value.type_.Character_String.value[0..value.type_.Character_String.length].as_ptr()
-            });
-            BACnetValue::String(s)
-        }
-        bacnet_sys::BACNET_APPLICATION_TAG_BACNET_APPLICATION_TAG_OCTET_STRING => {
-            let v = unsafe { value.type_.Octet_String };
-            BACnetValue::Bytes(v.value[0..v.length].to_vec())
+// Decode values until the end of the data, or until the closing tag matching `closing`. A date
+// directly followed by a time is combined into a `BACnetValue::DateTime` when that's all there is.
+fn decode_values(
+    reader: &mut TagReader,
+    closing: Option<u8>,
+    object_type: ObjectType,
+    object_property: ObjectPropertyId,
+) -> Result<Vec<BACnetValue>> {
+    let mut values = vec![];
+    loop {
+        if reader.is_empty() {
+            if closing.is_some() {
+                return Err(BACnetErr::DecodeFailed);
+            }
+            break;
         }
-        bacnet_sys::BACNET_APPLICATION_TAG_BACNET_APPLICATION_TAG_BIT_STRING => {
-            let nbits = unsafe { bitstring_bits_used(&mut value.type_.Bit_String) };
-            // info!("Number of bits: {}", nbits);
 
-            let mut bits = vec![];
-            for i in 0..nbits {
-                let bit = unsafe { bitstring_bit(&mut value.type_.Bit_String, i) };
-                bits.push(bit);
+        let tag = reader.peek_tag()?;
+        match tag.kind {
+            TagKind::Closing if closing == Some(tag.number) => {
+                reader.read_tag()?;
+                break;
+            }
+            TagKind::Closing => return Err(BACnetErr::DecodeFailed),
+            TagKind::Opening => {
+                reader.read_tag()?;
+                let inner = decode_values(reader, Some(tag.number), object_type, object_property)?;
+                values.push(BACnetValue::Constructed {
+                    tag: tag.number,
+                    values: inner,
+                });
+            }
+            TagKind::Length(_) if tag.context => {
+                let (_, content) = reader.read_primitive()?;
+                values.push(BACnetValue::Context {
+                    tag: tag.number,
+                    data: content.to_vec(),
+                });
             }
+            TagKind::Length(_) => match reader.read_application_value()? {
+                BACnetValue::Enum(enum_val, _) => values.push(BACnetValue::Enum(
+                    enum_val,
+                    enum_name(enum_val, object_type, object_property),
+                )),
+                value => values.push(value),
+            },
+        }
+    }
 
-            BACnetValue::BitString(bits)
-        }
-        bacnet_sys::BACNET_APPLICATION_TAG_BACNET_APPLICATION_TAG_ENUMERATED => {
-            // FIXME(tj): Find the string representation of the enum (if possible).
-            // See bacapp.c:1200
-            // See bactext.c:1266 - bactext_binary_present_value_name()
-            // Try calling:
-            //
-            // int bacapp_snprintf_value(char *str, size_t str_len, BACNET_OBJECT_PROPERTY_VALUE *object_value)
-            //
-            // It should return the numbers of characters written so we can permute it to a String
-            let enum_val = unsafe { value.type_.Enumerated };
-            let s = match data.object_property {
-                bacnet_sys::BACNET_PROPERTY_ID_PROP_UNITS => {
-                    if enum_val < 256 {
-                        Some(cstr(unsafe { bactext_engineering_unit_name(enum_val) }))
-                    } else {
-                        None
-                    }
+    if let [date, time] = values.as_slice() {
+        if let Some(dt) = join_date_time(date, time) {
+            return Ok(vec![BACnetValue::DateTime(dt)]);
+        }
+    }
+    Ok(values)
+}
+
+/// Decode a ReadPropertyMultiple acknowledgement
+///
+/// Every property value is decoded by `decode_application_data()`, a property that failed is
+/// returned as the error reported by the server.
+pub fn decode_read_access_results(data: &[u8]) -> Result<Vec<ReadAccessResult>> {
+    // ReadAccessResult ::= SEQUENCE {
+    //     objectIdentifier [0] BACnetObjectIdentifier,
+    //     listOfResults    [1] SEQUENCE OF SEQUENCE {
+    //         propertyIdentifier [2] BACnetPropertyIdentifier,
+    //         propertyArrayIndex [3] Unsigned OPTIONAL,
+    //         readResult         CHOICE {
+    //             propertyValue       [4] ABSTRACT-SYNTAX.&Type,
+    //             propertyAccessError [5] Error
+    //         }
+    //     } OPTIONAL
+    // }
+    let mut reader = TagReader::new(data);
+    let mut results = vec![];
+    while !reader.is_empty() {
+        let (object_type, object_instance) = decode_object_id(reader.read_context(0)?);
+        let mut properties = HashMap::new();
+        if reader.next_context(1) == Some(TagKind::Opening) {
+            reader.expect_opening(1)?;
+            while reader.next_context(1) != Some(TagKind::Closing) {
+                let property = decode_unsigned(reader.read_context(2)?) as ObjectPropertyId;
+                if matches!(reader.next_context(3), Some(TagKind::Length(_))) {
+                    reader.read_context(3)?;
                 }
-                bacnet_sys::BACNET_PROPERTY_ID_PROP_OBJECT_TYPE => {
-                    if enum_val < MAX_ASHRAE_OBJECT_TYPE {
-                        Some(cstr(unsafe { bactext_object_type_name(enum_val) }))
-                    } else {
-                        None // Either "reserved" or "proprietary"
+                let result = match reader.next_context(4) {
+                    Some(TagKind::Opening) => {
+                        reader.expect_opening(4)?;
+                        decode_application_data(reader.read_constructed(4)?, object_type, property)
                     }
-                }
-                bacnet_sys::BACNET_PROPERTY_ID_PROP_PRESENT_VALUE
-                | bacnet_sys::BACNET_PROPERTY_ID_PROP_RELINQUISH_DEFAULT => {
-                    if data.object_type < BACnetObjectType_OBJECT_PROPRIETARY_MIN {
-                        Some(cstr(unsafe { bactext_binary_present_value_name(enum_val) }))
-                    } else {
-                        None
+                    _ => {
+                        reader.expect_opening(5)?;
+                        let error = decode_error(&mut reader)?;
+                        reader.expect_closing(5)?;
+                        Err(error)
                     }
-                }
-                _ => None,
-            };
-
-            //switch (property) {
-            //    case PROP_PROPERTY_LIST:
-            //        char_str = (char *)bactext_property_name_default(
-            //            value->type.Enumerated, NULL);
-            //        if (char_str) {
-            //            ret_val = snprintf(str, str_len, "%s", char_str);
-            //        } else {
-            //            ret_val = snprintf(str, str_len, "%lu",
-            //                (unsigned long)value->type.Enumerated);
-            //        }
-            //        break;
-            //    case PROP_OBJECT_TYPE:
-            //        if (value->type.Enumerated < MAX_ASHRAE_OBJECT_TYPE) {
-            //            ret_val = snprintf(str, str_len, "%s",
-            //                bactext_object_type_name(
-            //                    value->type.Enumerated));
-            //        } else if (value->type.Enumerated < 128) {
-            //            ret_val = snprintf(str, str_len, "reserved %lu",
-            //                (unsigned long)value->type.Enumerated);
-            //        } else {
-            //            ret_val = snprintf(str, str_len, "proprietary %lu",
-            //                (unsigned long)value->type.Enumerated);
-            //        }
-            //        break;
-            //    case PROP_EVENT_STATE:
-            //        ret_val = snprintf(str, str_len, "%s",
-            //            bactext_event_state_name(value->type.Enumerated));
-            //        break;
-            //    case PROP_UNITS:
-            //        if (value->type.Enumerated < 256) {
-            //            ret_val = snprintf(str, str_len, "%s",
-            //                bactext_engineering_unit_name(
-            //                    value->type.Enumerated));
-            //        } else {
-            //            ret_val = snprintf(str, str_len, "proprietary %lu",
-            //                (unsigned long)value->type.Enumerated);
-            //        }
-            //        break;
-            //    case PROP_POLARITY:
-            //        ret_val = snprintf(str, str_len, "%s",
-            //            bactext_binary_polarity_name(
-            //                value->type.Enumerated));
-            //        break;
-            //    case PROP_PRESENT_VALUE:
-            //    case PROP_RELINQUISH_DEFAULT:
-            //        if (object_type < OBJECT_PROPRIETARY_MIN) {
-            //            ret_val = snprintf(str, str_len, "%s",
-            //                bactext_binary_present_value_name(
-            //                    value->type.Enumerated));
-            //        } else {
-            //            ret_val = snprintf(str, str_len, "%lu",
-            //                (unsigned long)value->type.Enumerated);
-            //        }
-            //        break;
-            //    case PROP_RELIABILITY:
-            //        ret_val = snprintf(str, str_len, "%s",
-            //            bactext_reliability_name(value->type.Enumerated));
-            //        break;
-            //    case PROP_SYSTEM_STATUS:
-            //        ret_val = snprintf(str, str_len, "%s",
-            //            bactext_device_status_name(value->type.Enumerated));
-            //        break;
-            //    case PROP_SEGMENTATION_SUPPORTED:
-            //        ret_val = snprintf(str, str_len, "%s",
-            //            bactext_segmentation_name(value->type.Enumerated));
-            //        break;
-            //    case PROP_NODE_TYPE:
-            //        ret_val = snprintf(str, str_len, "%s",
-            //            bactext_node_type_name(value->type.Enumerated));
-            //        break;
-            //    default:
-            //        ret_val = snprintf(str, str_len, "%lu",
-            //            (unsigned long)value->type.Enumerated);
-            //        break;
-            //}
-
-            BACnetValue::Enum(enum_val, s)
-        }
-        bacnet_sys::BACNET_APPLICATION_TAG_BACNET_APPLICATION_TAG_OBJECT_ID => {
-            // Store the object list, so we can interrogate each object
-
-            let object_type = unsafe { value.type_.Object_Id.type_ };
-            let object_instance = unsafe { value.type_.Object_Id.instance };
-            BACnetValue::ObjectId {
-                object_type,
-                object_instance,
-            }
-        }
-        bacnet_sys::BACNET_APPLICATION_TAG_BACNET_APPLICATION_TAG_DATE => {
-            let date = unsafe { value.type_.Date };
-            BACnetValue::Date {
-                year: date.year,
-                month: date.month,
-                day: date.day,
-                weekday: date.wday,
+                };
+                properties.insert(property, result);
             }
+            reader.expect_closing(1)?;
         }
-        _ => {
-            let tag_name = cstr(unsafe { bactext_application_tag_name(value.tag as u32) });
-            return Err(BACnetErr::UnhandledTag {
-                tag_name,
-                tag: value.tag,
-            });
+
+        results.push(ReadAccessResult {
+            object_type,
+            object_instance,
+            properties,
+        });
+    }
+    Ok(results)
+}
+
+// An Error, an application tagged error class and code
+fn decode_error(reader: &mut TagReader) -> Result<BACnetErr> {
+    match (
+        reader.read_application_value()?,
+        reader.read_application_value()?,
+    ) {
+        (BACnetValue::Enum(error_class, _), BACnetValue::Enum(error_code, _)) => {
+            Ok(bacnet_error(error_class, error_code))
         }
-    })
+        _ => Err(BACnetErr::DecodeFailed),
+    }
 }
 
 pub fn encode_data(value: BACnetValue) -> Result<BACNET_APPLICATION_DATA_VALUE> {
@@ -245,20 +233,26 @@ pub fn encode_data(value: BACnetValue) -> Result<BACNET_APPLICATION_DATA_VALUE>
                 data.type_.Octet_String.value[..s.len()].copy_from_slice(&s);
             }
         }
-        BACnetValue::String(s) => {
+        BACnetValue::String(_) | BACnetValue::EncodedString { .. } => {
+            let (encoding, bytes) = match value {
+                BACnetValue::EncodedString { value, charset } => {
+                    encode_character_string(&value, charset)?
+                }
+                BACnetValue::String(s) => encode_character_string(&s, CharacterSet::Utf8)?,
+                _ => unreachable!(),
+            };
             data.tag =
                 bacnet_sys::BACNET_APPLICATION_TAG_BACNET_APPLICATION_TAG_CHARACTER_STRING as u8;
             data.type_.Character_String = BACNET_CHARACTER_STRING::default();
-            data.type_.Character_String.length = s.len();
-            data.type_.Character_String.value = [0; 1470];
+            data.type_.Character_String.encoding = encoding;
+            data.type_.Character_String.length = bytes.len();
             unsafe {
-                data.type_.Character_String.value[..s.len()].copy_from_slice(
-                    s.as_bytes()
-                        .iter()
-                        .map(|&c| c as _)
-                        .collect::<Vec<_>>()
-                        .as_slice(),
-                );
+                if bytes.len() > data.type_.Character_String.value.len() {
+                    return Err(BACnetErr::EncodeFailed);
+                }
+                for (c, b) in data.type_.Character_String.value.iter_mut().zip(bytes) {
+                    *c = b as _;
+                }
             }
         }
         BACnetValue::BitString(s) => {
@@ -295,8 +289,785 @@ pub fn encode_data(value: BACnetValue) -> Result<BACNET_APPLICATION_DATA_VALUE>
             data.type_.Date.day = day;
             data.type_.Date.wday = weekday;
         }
-        BACnetValue::Array(_) => return Err(BACnetErr::EncodeFailed),
+        BACnetValue::Time {
+            hour,
+            minute,
+            second,
+            hundredths,
+        } => {
+            data.tag = bacnet_sys::BACNET_APPLICATION_TAG_BACNET_APPLICATION_TAG_TIME as u8;
+            data.type_.Time.hour = hour;
+            data.type_.Time.min = minute;
+            data.type_.Time.sec = second;
+            data.type_.Time.hundredths = hundredths;
+        }
+        // These take more than a single application data value, see encode_value_list() and
+        // encode_application_data()
+        BACnetValue::DateTime(_)
+        | BACnetValue::Array(_)
+        | BACnetValue::Sequence(_)
+        | BACnetValue::Constructed { .. }
+        | BACnetValue::Context { .. } => return Err(BACnetErr::EncodeFailed),
     }
 
     Ok(data)
 }
+
+/// Encode a value as a list of application data values, linked together with `link_values()`
+///
+/// A `BACnetValue::DateTime` is encoded as a date followed by a time, everything else as a single
+/// value (see `encode_data()`).
+pub fn encode_value_list(value: BACnetValue) -> Result<Vec<BACNET_APPLICATION_DATA_VALUE>> {
+    match value {
+        BACnetValue::DateTime(dt) => {
+            let (date, time) = split_date_time(dt);
+            Ok(vec![encode_data(date)?, encode_data(time)?])
+        }
+        value => Ok(vec![encode_data(value)?]),
+    }
+}
+
+/// Link the values returned by `encode_value_list()` together
+///
+/// The values must not be moved afterwards, as each one points to the next.
+pub fn link_values(values: &mut [BACNET_APPLICATION_DATA_VALUE]) {
+    for i in 1..values.len() {
+        values[i - 1].next = &mut values[i];
+    }
+}
+
+// A BACnetDateTime is encoded as an application tagged date followed by an application tagged time
+fn split_date_time(dt: DateTime) -> (BACnetValue, BACnetValue) {
+    (
+        BACnetValue::Date {
+            year: dt.year,
+            month: dt.month,
+            day: dt.day,
+            weekday: dt.weekday,
+        },
+        BACnetValue::Time {
+            hour: dt.hour,
+            minute: dt.minute,
+            second: dt.second,
+            hundredths: dt.hundredths,
+        },
+    )
+}
+
+pub(crate) fn join_date_time(date: &BACnetValue, time: &BACnetValue) -> Option<DateTime> {
+    match (date, time) {
+        (
+            &BACnetValue::Date {
+                year,
+                month,
+                day,
+                weekday,
+            },
+            &BACnetValue::Time {
+                hour,
+                minute,
+                second,
+                hundredths,
+            },
+        ) => Some(DateTime {
+            year,
+            month,
+            day,
+            weekday,
+            hour,
+            minute,
+            second,
+            hundredths,
+        }),
+        _ => None,
+    }
+}
+
+impl From<DateTime> for BACNET_DATE_TIME {
+    fn from(dt: DateTime) -> Self {
+        let mut ret = BACNET_DATE_TIME::default();
+        ret.date.year = dt.year;
+        ret.date.month = dt.month;
+        ret.date.day = dt.day;
+        ret.date.wday = dt.weekday;
+        ret.time.hour = dt.hour;
+        ret.time.min = dt.minute;
+        ret.time.sec = dt.second;
+        ret.time.hundredths = dt.hundredths;
+        ret
+    }
+}
+
+// A small reader for BACnet encoded data (ASHRAE 135 clause 20.2), for the data the stack leaves
+// for us to decode, like the item data of a ReadRange acknowledgement.
+
+/// A decoded tag, preceding every encoded value
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Tag {
+    pub number: u8,
+    pub context: bool,
+    pub kind: TagKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum TagKind {
+    Opening,
+    Closing,
+    // Length of the content following the tag (or, for an application tagged boolean, its value)
+    Length(u32),
+}
+
+pub(crate) struct TagReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> TagReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Decode the next tag without consuming it
+    pub fn peek_tag(&self) -> Result<Tag> {
+        self.decode_tag().map(|(tag, _)| tag)
+    }
+
+    /// Consume the next tag (but not the content following it)
+    pub fn read_tag(&mut self) -> Result<Tag> {
+        let (tag, len) = self.decode_tag()?;
+        self.data = &self.data[len..];
+        Ok(tag)
+    }
+
+    /// Consume `len` bytes of content
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.data.len() < len {
+            return Err(BACnetErr::DecodeFailed);
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    /// Consume a primitive value, returning its tag and content
+    pub fn read_primitive(&mut self) -> Result<(Tag, &'a [u8])> {
+        let tag = self.read_tag()?;
+        match tag.kind {
+            // Application tagged booleans keep their value in the tag itself
+            TagKind::Length(_) if !tag.context && tag.number == APPLICATION_TAG_BOOLEAN => {
+                Ok((tag, &[]))
+            }
+            TagKind::Length(len) => Ok((tag, self.read_bytes(len as usize)?)),
+            _ => Err(BACnetErr::DecodeFailed),
+        }
+    }
+
+    /// The kind of the next tag, if it's a context tag with the given number
+    pub fn next_context(&self, number: u8) -> Option<TagKind> {
+        match self.peek_tag() {
+            Ok(tag) if tag.context && tag.number == number => Some(tag.kind),
+            _ => None,
+        }
+    }
+
+    /// Consume a primitive value with the given context tag number, returning its content
+    pub fn read_context(&mut self, number: u8) -> Result<&'a [u8]> {
+        match self.read_primitive()? {
+            (tag, content) if tag.context && tag.number == number => Ok(content),
+            _ => Err(BACnetErr::DecodeFailed),
+        }
+    }
+
+    /// Consume an opening tag with the given context tag number
+    pub fn expect_opening(&mut self, number: u8) -> Result<()> {
+        match self.read_tag()? {
+            Tag {
+                number: n,
+                context: true,
+                kind: TagKind::Opening,
+            } if n == number => Ok(()),
+            _ => Err(BACnetErr::DecodeFailed),
+        }
+    }
+
+    /// Consume a closing tag with the given context tag number
+    pub fn expect_closing(&mut self, number: u8) -> Result<()> {
+        match self.read_tag()? {
+            Tag {
+                number: n,
+                context: true,
+                kind: TagKind::Closing,
+            } if n == number => Ok(()),
+            _ => Err(BACnetErr::DecodeFailed),
+        }
+    }
+
+    /// Consume everything up to (and including) the closing tag matching an opening tag that was
+    /// just read, returning the raw bytes in between
+    pub fn read_constructed(&mut self, number: u8) -> Result<&'a [u8]> {
+        let start = self.data;
+        let mut depth = 0;
+        loop {
+            let consumed = start.len() - self.data.len();
+            let tag = self.peek_tag()?;
+            match tag.kind {
+                TagKind::Opening if tag.context => {
+                    depth += 1;
+                    self.read_tag()?;
+                }
+                TagKind::Closing if tag.context && depth == 0 && tag.number == number => {
+                    self.read_tag()?;
+                    return Ok(&start[..consumed]);
+                }
+                TagKind::Closing if tag.context => {
+                    if depth == 0 {
+                        return Err(BACnetErr::DecodeFailed);
+                    }
+                    depth -= 1;
+                    self.read_tag()?;
+                }
+                _ => {
+                    self.read_primitive()?;
+                }
+            }
+        }
+    }
+
+    /// Consume an application tagged value
+    pub fn read_application_value(&mut self) -> Result<BACnetValue> {
+        let (tag, content) = self.read_primitive()?;
+        if tag.context {
+            return Err(BACnetErr::DecodeFailed);
+        }
+        match tag.kind {
+            TagKind::Length(len) => decode_application_content(tag.number, len, content),
+            _ => Err(BACnetErr::DecodeFailed),
+        }
+    }
+
+    fn decode_tag(&self) -> Result<(Tag, usize)> {
+        let first = *self.data.first().ok_or(BACnetErr::DecodeFailed)?;
+        let mut len = 1;
+        let mut number = first >> 4;
+        if number == 0x0F {
+            number = *self.data.get(len).ok_or(BACnetErr::DecodeFailed)?;
+            len += 1;
+        }
+        let context = first & 0x08 != 0;
+        let kind = match first & 0x07 {
+            6 if context => TagKind::Opening,
+            7 if context => TagKind::Closing,
+            5 => {
+                let extended = *self.data.get(len).ok_or(BACnetErr::DecodeFailed)?;
+                len += 1;
+                let size = match extended {
+                    254 => 2,
+                    255 => 4,
+                    _ => 0,
+                };
+                if size == 0 {
+                    TagKind::Length(extended as u32)
+                } else {
+                    let bytes = self
+                        .data
+                        .get(len..len + size)
+                        .ok_or(BACnetErr::DecodeFailed)?;
+                    len += size;
+                    TagKind::Length(decode_unsigned(bytes) as u32)
+                }
+            }
+            lvt => TagKind::Length(lvt as u32),
+        };
+        Ok((
+            Tag {
+                number,
+                context,
+                kind,
+            },
+            len,
+        ))
+    }
+}
+
+pub(crate) const APPLICATION_TAG_BOOLEAN: u8 = 1;
+
+// Decode the content of an application tagged value, `len` being the length/value/type of the tag
+fn decode_application_content(number: u8, len: u32, content: &[u8]) -> Result<BACnetValue> {
+    Ok(match number {
+        0 => BACnetValue::Null,
+        APPLICATION_TAG_BOOLEAN => BACnetValue::Bool(len != 0),
+        2 => BACnetValue::Uint(decode_unsigned(content)),
+        3 => BACnetValue::Int(decode_signed(content)),
+        4 => BACnetValue::Real(decode_real(content)?),
+        5 => BACnetValue::Double(f64::from_be_bytes(
+            content.try_into().map_err(|_| BACnetErr::DecodeFailed)?,
+        )),
+        6 => BACnetValue::Bytes(content.to_vec()),
+        7 => {
+            let (&charset, bytes) = content.split_first().ok_or(BACnetErr::DecodeFailed)?;
+            decode_character_string(charset, bytes)?
+        }
+        8 => BACnetValue::BitString(decode_bit_string(content)?),
+        9 => BACnetValue::Enum(decode_unsigned(content) as u32, None),
+        10 => {
+            let [year, month, day, weekday]: [u8; 4] =
+                content.try_into().map_err(|_| BACnetErr::DecodeFailed)?;
+            BACnetValue::Date {
+                year: year as u16 + 1900,
+                month,
+                day,
+                weekday,
+            }
+        }
+        11 => {
+            let [hour, minute, second, hundredths]: [u8; 4] =
+                content.try_into().map_err(|_| BACnetErr::DecodeFailed)?;
+            BACnetValue::Time {
+                hour,
+                minute,
+                second,
+                hundredths,
+            }
+        }
+        12 => {
+            let (object_type, object_instance) = decode_object_id(content);
+            BACnetValue::ObjectId {
+                object_type,
+                object_instance,
+            }
+        }
+        _ => {
+            let tag_name = cstr(unsafe { bactext_application_tag_name(number as u32) });
+            return Err(BACnetErr::UnhandledTag {
+                tag_name,
+                tag: number,
+            });
+        }
+    })
+}
+
+// An object identifier is the object type in the upper 10 bits, and the instance in the rest
+pub(crate) fn decode_object_id(content: &[u8]) -> (ObjectType, u32) {
+    let id = decode_unsigned(content) as u32;
+    (id >> 22, id & 0x3F_FFFF)
+}
+
+pub(crate) fn decode_unsigned(content: &[u8]) -> u64 {
+    content.iter().fold(0, |acc, &b| (acc << 8) | b as u64)
+}
+
+pub(crate) fn decode_signed(content: &[u8]) -> i32 {
+    let unsigned = decode_unsigned(content) as u32;
+    // Sign extend from the number of bytes used
+    match content.len() {
+        0 => 0,
+        n @ 1..=3 => {
+            let shift = 32 - 8 * n as u32;
+            ((unsigned << shift) as i32) >> shift
+        }
+        _ => unsigned as i32,
+    }
+}
+
+pub(crate) fn decode_real(content: &[u8]) -> Result<f32> {
+    Ok(f32::from_be_bytes(
+        content.try_into().map_err(|_| BACnetErr::DecodeFailed)?,
+    ))
+}
+
+pub(crate) fn decode_bit_string(content: &[u8]) -> Result<Vec<bool>> {
+    let (&unused, bytes) = content.split_first().ok_or(BACnetErr::DecodeFailed)?;
+    let nbits = (bytes.len() * 8).saturating_sub(unused as usize);
+    Ok((0..nbits)
+        .map(|i| bytes[i / 8] & (0x80 >> (i % 8)) != 0)
+        .collect())
+}
+
+// The encoding counterpart of TagReader, for values the stack can't encode from a
+// BACNET_APPLICATION_DATA_VALUE (like constructed values).
+
+/// Encode a value as it's put on the wire
+///
+/// Arrays and sequences are encoded as their elements one after the other, so this encodes
+/// anything `decode_application_data()` decodes.
+pub fn encode_application_data(value: &BACnetValue) -> Result<Vec<u8>> {
+    let mut buf = vec![];
+    encode_value(&mut buf, value)?;
+    Ok(buf)
+}
+
+fn encode_value(buf: &mut Vec<u8>, value: &BACnetValue) -> Result<()> {
+    match value {
+        BACnetValue::Null => encode_tag(buf, 0, false, 0),
+        // Application tagged booleans keep their value in the tag itself
+        BACnetValue::Bool(b) => encode_tag(buf, APPLICATION_TAG_BOOLEAN, false, *b as u32),
+        BACnetValue::Uint(u) => encode_primitive(buf, 2, &encode_unsigned(*u)),
+        BACnetValue::Int(i) => encode_primitive(buf, 3, &encode_signed(*i)),
+        BACnetValue::Real(f) => encode_primitive(buf, 4, &f.to_be_bytes()),
+        BACnetValue::Double(f) => encode_primitive(buf, 5, &f.to_be_bytes()),
+        BACnetValue::Bytes(b) => encode_primitive(buf, 6, b),
+        BACnetValue::String(_) | BACnetValue::EncodedString { .. } => {
+            let (charset, bytes) = match value {
+                BACnetValue::EncodedString { value, charset } => {
+                    encode_character_string(value, *charset)?
+                }
+                BACnetValue::String(s) => encode_character_string(s, CharacterSet::Utf8)?,
+                _ => unreachable!(),
+            };
+            let content = std::iter::once(charset).chain(bytes).collect::<Vec<_>>();
+            encode_primitive(buf, 7, &content)
+        }
+        BACnetValue::BitString(bits) => encode_primitive(buf, 8, &encode_bit_string(bits)),
+        BACnetValue::Enum(e, _) => encode_primitive(buf, 9, &encode_unsigned(*e as u64)),
+        BACnetValue::Date {
+            year,
+            month,
+            day,
+            weekday,
+        } => {
+            let year = year.checked_sub(1900).ok_or(BACnetErr::EncodeFailed)?;
+            let year = u8::try_from(year).map_err(|_| BACnetErr::EncodeFailed)?;
+            encode_primitive(buf, 10, &[year, *month, *day, *weekday])
+        }
+        BACnetValue::Time {
+            hour,
+            minute,
+            second,
+            hundredths,
+        } => encode_primitive(buf, 11, &[*hour, *minute, *second, *hundredths]),
+        BACnetValue::DateTime(dt) => {
+            let (date, time) = split_date_time(*dt);
+            encode_value(buf, &date)?;
+            encode_value(buf, &time)?;
+        }
+        BACnetValue::ObjectId {
+            object_type,
+            object_instance,
+        } => {
+            if *object_type >= 1 << 10 || *object_instance >= 1 << 22 {
+                return Err(BACnetErr::EncodeFailed);
+            }
+            let id = object_type << 22 | object_instance;
+            encode_primitive(buf, 12, &id.to_be_bytes())
+        }
+        BACnetValue::Array(values) | BACnetValue::Sequence(values) => {
+            for value in values {
+                encode_value(buf, value)?;
+            }
+        }
+        BACnetValue::Constructed { tag, values } => {
+            encode_opening_closing(buf, *tag, 6);
+            for value in values {
+                encode_value(buf, value)?;
+            }
+            encode_opening_closing(buf, *tag, 7);
+        }
+        BACnetValue::Context { tag, data } => {
+            encode_tag(buf, *tag, true, data.len() as u32);
+            buf.extend_from_slice(data);
+        }
+    }
+    Ok(())
+}
+
+fn encode_primitive(buf: &mut Vec<u8>, number: u8, content: &[u8]) {
+    encode_tag(buf, number, false, content.len() as u32);
+    buf.extend_from_slice(content);
+}
+
+fn encode_tag(buf: &mut Vec<u8>, number: u8, context: bool, len: u32) {
+    let class = if context { 0x08 } else { 0 };
+    let lvt = if len < 5 { len as u8 } else { 5 };
+    if number < 15 {
+        buf.push(number << 4 | class | lvt);
+    } else {
+        buf.push(0xF0 | class | lvt);
+        buf.push(number);
+    }
+    if len >= 5 {
+        if len < 254 {
+            buf.push(len as u8);
+        } else if len < 1 << 16 {
+            buf.push(254);
+            buf.extend_from_slice(&(len as u16).to_be_bytes());
+        } else {
+            buf.push(255);
+            buf.extend_from_slice(&len.to_be_bytes());
+        }
+    }
+}
+
+// Opening (lvt 6) and closing (lvt 7) tags are always context tags
+fn encode_opening_closing(buf: &mut Vec<u8>, number: u8, lvt: u8) {
+    if number < 15 {
+        buf.push(number << 4 | 0x08 | lvt);
+    } else {
+        buf.push(0xF8 | lvt);
+        buf.push(number);
+    }
+}
+
+// Unsigned values use as few bytes as possible (but at least one)
+fn encode_unsigned(value: u64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let skip = (value.leading_zeros() / 8).min(7) as usize;
+    bytes[skip..].to_vec()
+}
+
+// Signed values use as few bytes as possible while keeping the sign bit
+fn encode_signed(value: i32) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let mut skip = 0;
+    while skip < 3 {
+        let (first, next) = (bytes[skip], bytes[skip + 1]);
+        if (first == 0 && next & 0x80 == 0) || (first == 0xFF && next & 0x80 != 0) {
+            skip += 1;
+        } else {
+            break;
+        }
+    }
+    bytes[skip..].to_vec()
+}
+
+fn encode_bit_string(bits: &[bool]) -> Vec<u8> {
+    let mut content = vec![((8 - bits.len() % 8) % 8) as u8];
+    for chunk in bits.chunks(8) {
+        content.push(
+            chunk
+                .iter()
+                .enumerate()
+                .fold(0, |acc, (i, &bit)| acc | ((bit as u8) << (7 - i))),
+        );
+    }
+    content
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Properties without an enumeration of their own, so enumerated values aren't named
+    const PROP_ARRAY: ObjectPropertyId = bacnet_sys::BACNET_PROPERTY_ID_PROP_PRIORITY_ARRAY;
+    const OBJECT_TYPE: ObjectType = bacnet_sys::BACnetObjectType_OBJECT_SCHEDULE;
+
+    fn round_trip(value: BACnetValue, property: ObjectPropertyId) -> BACnetValue {
+        let data = encode_application_data(&value).unwrap();
+        decode_application_data(&data, OBJECT_TYPE, property).unwrap()
+    }
+
+    #[test]
+    fn primitive_values() {
+        let values = [
+            BACnetValue::Null,
+            BACnetValue::Bool(true),
+            BACnetValue::Bool(false),
+            BACnetValue::Uint(0),
+            BACnetValue::Uint(u32::MAX as u64 + 1),
+            BACnetValue::Int(-1),
+            BACnetValue::Int(128),
+            BACnetValue::Int(i32::MIN),
+            BACnetValue::Real(21.5),
+            BACnetValue::Double(-0.125),
+            BACnetValue::Bytes(vec![0xC0, 0xA8, 0x01, 0x02, 0xBA, 0xC0]),
+            BACnetValue::String("Zone 1 – süd".to_string()),
+            BACnetValue::BitString(vec![
+                true, false, false, true, false, false, false, false, true,
+            ]),
+            BACnetValue::Enum(3, None),
+            BACnetValue::Date {
+                year: 2024,
+                month: 2,
+                day: 29,
+                weekday: 4,
+            },
+            BACnetValue::Time {
+                hour: 23,
+                minute: 59,
+                second: 0,
+                hundredths: 255,
+            },
+            BACnetValue::ObjectId {
+                object_type: 1023,
+                object_instance: 0x3F_FFFF,
+            },
+        ];
+        for value in values {
+            assert_eq!(round_trip(value.clone(), PROP_ARRAY), value);
+        }
+    }
+
+    #[test]
+    fn long_content() {
+        // Lengths from 5 up take an extra byte, from 254 up two more
+        for len in [4, 5, 253, 254, 300, 70_000] {
+            let value = BACnetValue::Bytes(vec![0x55; len]);
+            assert_eq!(round_trip(value.clone(), PROP_ARRAY), value);
+        }
+    }
+
+    #[test]
+    fn date_time() {
+        let value = BACnetValue::DateTime(DateTime {
+            year: 2023,
+            month: 12,
+            day: 31,
+            weekday: 7,
+            hour: 12,
+            minute: 30,
+            second: 15,
+            hundredths: 0,
+        });
+        assert_eq!(round_trip(value.clone(), PROP_ARRAY), value);
+    }
+
+    #[test]
+    fn arrays_and_sequences() {
+        let reals = BACnetValue::Array(vec![
+            BACnetValue::Null,
+            BACnetValue::Real(20.0),
+            BACnetValue::Null,
+        ]);
+        assert_eq!(round_trip(reals.clone(), PROP_ARRAY), reals);
+
+        let date = |day| BACnetValue::Date {
+            year: 2024,
+            month: 1,
+            day,
+            weekday: 255,
+        };
+        let period = BACnetValue::Sequence(vec![date(1), date(31)]);
+        assert_eq!(
+            round_trip(
+                period.clone(),
+                bacnet_sys::BACNET_PROPERTY_ID_PROP_EFFECTIVE_PERIOD
+            ),
+            period
+        );
+    }
+
+    #[test]
+    fn constructed_values() {
+        // A weekly-schedule: a BACnetDailySchedule per day, each a list of BACnetTimeValues
+        let time = |hour| BACnetValue::Time {
+            hour,
+            minute: 0,
+            second: 0,
+            hundredths: 0,
+        };
+        let day = BACnetValue::Constructed {
+            tag: 0,
+            values: vec![
+                time(7),
+                BACnetValue::Real(21.0),
+                time(18),
+                BACnetValue::Null,
+            ],
+        };
+        let empty_day = BACnetValue::Constructed {
+            tag: 0,
+            values: vec![],
+        };
+        let week = BACnetValue::Array(vec![day.clone(), empty_day.clone(), day]);
+        assert_eq!(
+            round_trip(
+                week.clone(),
+                bacnet_sys::BACNET_PROPERTY_ID_PROP_WEEKLY_SCHEDULE
+            ),
+            week
+        );
+
+        // Context tagged values, nested and with tag numbers that take an extra byte
+        let value = BACnetValue::Array(vec![
+            BACnetValue::Context {
+                tag: 1,
+                data: vec![0x02],
+            },
+            BACnetValue::Constructed {
+                tag: 20,
+                values: vec![
+                    BACnetValue::Context {
+                        tag: 15,
+                        data: vec![1, 2, 3, 4, 5, 6, 7],
+                    },
+                    BACnetValue::Constructed {
+                        tag: 2,
+                        values: vec![BACnetValue::Uint(5)],
+                    },
+                ],
+            },
+        ]);
+        assert_eq!(round_trip(value.clone(), PROP_ARRAY), value);
+    }
+
+    #[test]
+    fn malformed_data() {
+        let decode = |data: &[u8]| decode_application_data(data, OBJECT_TYPE, PROP_ARRAY);
+        // Nothing at all
+        assert!(decode(&[]).is_err());
+        // An opening tag without its closing tag, and the other way around
+        assert!(decode(&[0x0E, 0x21, 0x05]).is_err());
+        assert!(decode(&[0x21, 0x05, 0x0F]).is_err());
+        // Content cut short
+        assert!(decode(&[0x44, 0x41, 0xA8]).is_err());
+    }
+
+    #[test]
+    fn read_access_results() {
+        let mut data = vec![];
+        // analog-value 7
+        encode_tag(&mut data, 0, true, 4);
+        data.extend_from_slice(&(2u32 << 22 | 7).to_be_bytes());
+        encode_opening_closing(&mut data, 1, 6);
+        // present-value: 21.5
+        encode_tag(&mut data, 2, true, 1);
+        data.push(85);
+        encode_opening_closing(&mut data, 4, 6);
+        encode_value(&mut data, &BACnetValue::Real(21.5)).unwrap();
+        encode_opening_closing(&mut data, 4, 7);
+        // priority-array[16]: null
+        encode_tag(&mut data, 2, true, 1);
+        data.push(87);
+        encode_tag(&mut data, 3, true, 1);
+        data.push(16);
+        encode_opening_closing(&mut data, 4, 6);
+        encode_value(&mut data, &BACnetValue::Null).unwrap();
+        encode_opening_closing(&mut data, 4, 7);
+        // description: property / unknown-property
+        encode_tag(&mut data, 2, true, 1);
+        data.push(28);
+        encode_opening_closing(&mut data, 5, 6);
+        encode_value(&mut data, &BACnetValue::Enum(2, None)).unwrap();
+        encode_value(&mut data, &BACnetValue::Enum(32, None)).unwrap();
+        encode_opening_closing(&mut data, 5, 7);
+        encode_opening_closing(&mut data, 1, 7);
+        // device 1, without results
+        encode_tag(&mut data, 0, true, 4);
+        data.extend_from_slice(&(8u32 << 22 | 1).to_be_bytes());
+
+        let results = decode_read_access_results(&data).unwrap();
+        assert_eq!(results.len(), 2);
+        let object = &results[0];
+        assert_eq!((object.object_type, object.object_instance), (2, 7));
+        assert_eq!(
+            object.properties[&85].as_ref().unwrap(),
+            &BACnetValue::Real(21.5)
+        );
+        assert_eq!(object.properties[&87].as_ref().unwrap(), &BACnetValue::Null);
+        assert!(matches!(
+            object.properties[&28],
+            Err(BACnetErr::Error {
+                class: 2,
+                code: 32,
+                ..
+            })
+        ));
+        assert_eq!((results[1].object_type, results[1].object_instance), (8, 1));
+        assert!(results[1].properties.is_empty());
+
+        // Cut off in the middle of a result
+        assert!(decode_read_access_results(&data[..data.len() - 8]).is_err());
+    }
+}
@@ -0,0 +1,232 @@
+// Names of enumerated values
+//
+// The stack knows the names of most enumerations through its bactext_*_name() functions. We use
+// those to name decoded values, and to find the value for a name (which the stack can't do for
+// most enumerations) we walk over every value once and remember the names. A string written to an
+// enumerated property is taken as the name of one of its values.
+//
+// Only enumerated values are named, not the bits of a bit string (like BACnetEventTransitionBits),
+// and only where the property tells which enumeration they belong to, so not inside constructed
+// values (like the operation of a BACnetLightingCommand). Enumerations the stack has no names for,
+// like BACnetAction, BACnetProgramState and BACnetFileAccessMethod, are left unnamed as well.
+
+use crate::{cstr, value::BACnetValue, ObjectPropertyId, ObjectType};
+use bacnet_sys::{
+    bactext_binary_polarity_name, bactext_binary_present_value_name, bactext_device_status_name,
+    bactext_engineering_unit_name, bactext_event_state_name, bactext_event_type_name,
+    bactext_life_safety_mode_name, bactext_life_safety_operation_name,
+    bactext_life_safety_state_name, bactext_lighting_in_progress, bactext_node_type_name,
+    bactext_notify_type_name, bactext_object_type_name, bactext_property_name,
+    bactext_reliability_name, bactext_restart_reason_name, bactext_segmentation_name,
+    MAX_ASHRAE_OBJECT_TYPE,
+};
+use once_cell::sync::OnceCell;
+use std::{collections::HashMap, ops::Range, os::raw::c_char};
+
+type NameFn = unsafe extern "C" fn(u32) -> *const c_char;
+
+// An enumeration the stack has names for
+struct EnumTable {
+    name_fn: NameFn,
+    values: [Range<u32>; 2], // The values that can have a name
+    names: OnceCell<HashMap<String, u32>>,
+}
+
+impl EnumTable {
+    // Values from 0 up to (but not including) `count` can have a name
+    const fn new(name_fn: NameFn, count: u32) -> Self {
+        Self::split(name_fn, 0..count, 0..0)
+    }
+
+    // Values in either range can have a name
+    const fn split(name_fn: NameFn, low: Range<u32>, high: Range<u32>) -> Self {
+        Self {
+            name_fn,
+            values: [low, high],
+            names: OnceCell::new(),
+        }
+    }
+
+    fn name(&self, value: u32) -> Option<String> {
+        if !self.values.iter().any(|values| values.contains(&value)) {
+            return None;
+        }
+        let name = cstr(unsafe { (self.name_fn)(value) });
+        // Values without a name are called "Reserved for Use by ASHRAE" or "Vendor Proprietary
+        // Value" (or are left empty)
+        if name.is_empty() || name.starts_with("Reserved") || name.contains("Proprietary") {
+            None
+        } else {
+            Some(name)
+        }
+    }
+
+    fn value(&self, name: &str) -> Option<u32> {
+        self.names
+            .get_or_init(|| {
+                self.values
+                    .iter()
+                    .cloned()
+                    .flatten()
+                    .filter_map(|value| self.name(value).map(|name| (name, value)))
+                    .collect()
+            })
+            .get(name)
+            .copied()
+    }
+}
+
+static BINARY_PV: EnumTable = EnumTable::new(bactext_binary_present_value_name, 2);
+static POLARITY: EnumTable = EnumTable::new(bactext_binary_polarity_name, 2);
+// The units ASHRAE defines, the others are left to vendors
+static UNITS: EnumTable = EnumTable::split(bactext_engineering_unit_name, 0..256, 47808..50000);
+static EVENT_STATE: EnumTable = EnumTable::new(bactext_event_state_name, 64);
+static EVENT_TYPE: EnumTable = EnumTable::new(bactext_event_type_name, 64);
+static RELIABILITY: EnumTable = EnumTable::new(bactext_reliability_name, 64);
+static DEVICE_STATUS: EnumTable = EnumTable::new(bactext_device_status_name, 64);
+static SEGMENTATION: EnumTable = EnumTable::new(bactext_segmentation_name, 4);
+static NODE_TYPE: EnumTable = EnumTable::new(bactext_node_type_name, 64);
+static NOTIFY_TYPE: EnumTable = EnumTable::new(bactext_notify_type_name, 3);
+static OBJECT_TYPE: EnumTable = EnumTable::new(bactext_object_type_name, MAX_ASHRAE_OBJECT_TYPE);
+static PROPERTY: EnumTable = EnumTable::new(bactext_property_name, 1024);
+static LIFE_SAFETY_STATE: EnumTable = EnumTable::new(bactext_life_safety_state_name, 256);
+static LIFE_SAFETY_MODE: EnumTable = EnumTable::new(bactext_life_safety_mode_name, 256);
+static LIFE_SAFETY_OPERATION: EnumTable = EnumTable::new(bactext_life_safety_operation_name, 64);
+static LIGHTING_IN_PROGRESS: EnumTable = EnumTable::new(bactext_lighting_in_progress, 64);
+static RESTART_REASON: EnumTable = EnumTable::new(bactext_restart_reason_name, 64);
+
+// The enumeration used by a property
+fn table(object_type: ObjectType, property: ObjectPropertyId) -> Option<&'static EnumTable> {
+    Some(match property {
+        bacnet_sys::BACNET_PROPERTY_ID_PROP_PRESENT_VALUE
+        | bacnet_sys::BACNET_PROPERTY_ID_PROP_RELINQUISH_DEFAULT
+        | bacnet_sys::BACNET_PROPERTY_ID_PROP_ALARM_VALUE
+        | bacnet_sys::BACNET_PROPERTY_ID_PROP_FEEDBACK_VALUE
+        | bacnet_sys::BACNET_PROPERTY_ID_PROP_TRACKING_VALUE
+        | bacnet_sys::BACNET_PROPERTY_ID_PROP_ALARM_VALUES
+        | bacnet_sys::BACNET_PROPERTY_ID_PROP_FAULT_VALUES
+        | bacnet_sys::BACNET_PROPERTY_ID_PROP_LIFE_SAFETY_ALARM_VALUES => match object_type {
+            bacnet_sys::BACnetObjectType_OBJECT_BINARY_INPUT
+            | bacnet_sys::BACnetObjectType_OBJECT_BINARY_OUTPUT
+            | bacnet_sys::BACnetObjectType_OBJECT_BINARY_VALUE => &BINARY_PV,
+            bacnet_sys::BACnetObjectType_OBJECT_LIFE_SAFETY_POINT
+            | bacnet_sys::BACnetObjectType_OBJECT_LIFE_SAFETY_ZONE => &LIFE_SAFETY_STATE,
+            _ => return None,
+        },
+        bacnet_sys::BACNET_PROPERTY_ID_PROP_MODE
+        | bacnet_sys::BACNET_PROPERTY_ID_PROP_ACCEPTED_MODES => &LIFE_SAFETY_MODE,
+        bacnet_sys::BACNET_PROPERTY_ID_PROP_OPERATION_EXPECTED => &LIFE_SAFETY_OPERATION,
+        bacnet_sys::BACNET_PROPERTY_ID_PROP_IN_PROGRESS => &LIGHTING_IN_PROGRESS,
+        bacnet_sys::BACNET_PROPERTY_ID_PROP_LAST_RESTART_REASON => &RESTART_REASON,
+        bacnet_sys::BACNET_PROPERTY_ID_PROP_POLARITY => &POLARITY,
+        bacnet_sys::BACNET_PROPERTY_ID_PROP_UNITS => &UNITS,
+        bacnet_sys::BACNET_PROPERTY_ID_PROP_EVENT_STATE => &EVENT_STATE,
+        bacnet_sys::BACNET_PROPERTY_ID_PROP_EVENT_TYPE => &EVENT_TYPE,
+        bacnet_sys::BACNET_PROPERTY_ID_PROP_RELIABILITY => &RELIABILITY,
+        bacnet_sys::BACNET_PROPERTY_ID_PROP_SYSTEM_STATUS => &DEVICE_STATUS,
+        bacnet_sys::BACNET_PROPERTY_ID_PROP_SEGMENTATION_SUPPORTED => &SEGMENTATION,
+        bacnet_sys::BACNET_PROPERTY_ID_PROP_NODE_TYPE => &NODE_TYPE,
+        bacnet_sys::BACNET_PROPERTY_ID_PROP_NOTIFY_TYPE => &NOTIFY_TYPE,
+        bacnet_sys::BACNET_PROPERTY_ID_PROP_OBJECT_TYPE => &OBJECT_TYPE,
+        bacnet_sys::BACNET_PROPERTY_ID_PROP_PROPERTY_LIST => &PROPERTY,
+        _ => return None,
+    })
+}
+
+/// The name of an enumerated value of the given property (if we know it)
+pub(crate) fn enum_name(
+    value: u32,
+    object_type: ObjectType,
+    property: ObjectPropertyId,
+) -> Option<String> {
+    table(object_type, property)?.name(value)
+}
+
+/// A value to write to the given property, with strings naming a value of its enumeration (like
+/// "active" or "degrees-celsius") turned into that value. Anything else is left as it is.
+pub(crate) fn resolve_names(
+    value: BACnetValue,
+    object_type: ObjectType,
+    property: ObjectPropertyId,
+) -> BACnetValue {
+    let Some(table) = table(object_type, property) else {
+        return value;
+    };
+    match value {
+        BACnetValue::String(name) => match table.value(&name) {
+            Some(value) => BACnetValue::Enum(value, Some(name)),
+            None => BACnetValue::String(name),
+        },
+        // Like the alarm-values of a life safety point
+        BACnetValue::Array(values) => BACnetValue::Array(
+            values
+                .into_iter()
+                .map(|value| resolve_names(value, object_type, property))
+                .collect(),
+        ),
+        value => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bacnet_sys::{
+        BACnetObjectType_OBJECT_ANALOG_INPUT as OBJECT_ANALOG_INPUT,
+        BACnetObjectType_OBJECT_BINARY_INPUT as OBJECT_BINARY_INPUT,
+        BACNET_PROPERTY_ID_PROP_ALARM_VALUES as PROP_ALARM_VALUES,
+        BACNET_PROPERTY_ID_PROP_PRESENT_VALUE as PROP_PRESENT_VALUE,
+        BACNET_PROPERTY_ID_PROP_UNITS as PROP_UNITS,
+    };
+
+    fn named(value: u32, name: &str) -> BACnetValue {
+        BACnetValue::Enum(value, Some(name.to_string()))
+    }
+
+    #[test]
+    fn names_written() {
+        let written = |value: &str, object_type, property| {
+            resolve_names(BACnetValue::from(value.to_string()), object_type, property)
+        };
+        assert_eq!(
+            written("active", OBJECT_BINARY_INPUT, PROP_PRESENT_VALUE),
+            named(1, "active")
+        );
+        assert_eq!(
+            written("degrees-celsius", OBJECT_ANALOG_INPUT, PROP_UNITS),
+            named(62, "degrees-celsius")
+        );
+        assert_eq!(
+            written(
+                "standard-cubic-feet-per-day",
+                OBJECT_ANALOG_INPUT,
+                PROP_UNITS
+            ),
+            named(47808, "standard-cubic-feet-per-day")
+        );
+        assert_eq!(
+            resolve_names(
+                BACnetValue::Array(vec![BACnetValue::from("inactive".to_string())]),
+                OBJECT_BINARY_INPUT,
+                PROP_ALARM_VALUES
+            ),
+            BACnetValue::Array(vec![named(0, "inactive")])
+        );
+
+        // Strings that don't name a value of the property's enumeration stay strings
+        for (value, object_type, property) in [
+            ("warm", OBJECT_BINARY_INPUT, PROP_PRESENT_VALUE),
+            ("active", OBJECT_ANALOG_INPUT, PROP_PRESENT_VALUE),
+            ("Vendor Proprietary Value", OBJECT_ANALOG_INPUT, PROP_UNITS),
+        ] {
+            assert_eq!(
+                written(value, object_type, property),
+                BACnetValue::String(value.to_string())
+            );
+        }
+        assert_eq!(
+            written("1", OBJECT_BINARY_INPUT, PROP_PRESENT_VALUE),
+            BACnetValue::Uint(1)
+        );
+    }
+}
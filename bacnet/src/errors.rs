@@ -8,6 +8,9 @@ pub enum BACnetErr {
     #[error("Aborted: {text} (code {code})")]
     Aborted { text: String, code: u8 },
 
+    #[error("BVLC NAK: {text} (code {code})")]
+    BvlcNak { text: String, code: u16 },
+
     #[error("Error: class={class_text} ({class}) {text} ({code})")]
     Error {
         class_text: String,
@@ -16,6 +19,20 @@ pub enum BACnetErr {
         code: u32,
     },
 
+    #[error(
+        "WritePropertyMultiple failed at object {object_type}:{object_instance} property \
+         {property}: class={class_text} ({class}) {text} ({code})"
+    )]
+    WritePropertyMultiple {
+        object_type: u32,
+        object_instance: u32,
+        property: u32,
+        class_text: String,
+        class: u32,
+        text: String,
+        code: u32,
+    },
+
     #[error("Request is still ongoing")]
     RequestOngoing,
 
@@ -25,14 +42,38 @@ pub enum BACnetErr {
     #[error("Invalid value was extracted")]
     InvalidValue,
 
+    #[error("Invalid priority {priority}, must be between 1 and 16")]
+    InvalidPriority { priority: u8 },
+
     #[error("Not connected to server with Device ID {device_id}")]
     NotConnected { device_id: u32 },
 
-    #[error("TSM Timeout")]
-    TsmTimeout,
+    #[error("No router to network {network} is known")]
+    NoRoute { network: u16 },
+
+    #[error("A MAC address is at most 7 bytes, not {len}")]
+    InvalidAddress { len: usize },
+
+    #[error("No invoke ID became free in time to send the request")]
+    NoInvokeId,
+
+    #[error("No response, after {retries} retries")]
+    NoResponse { retries: u8 },
 
-    #[error("APDU Timeout")]
-    ApduTimeout,
+    #[error("The request couldn't be sent")]
+    SendFailed,
+
+    #[error("The network driver has stopped")]
+    DriverStopped,
+
+    #[error("The network has already been started")]
+    AlreadyStarted,
+
+    #[error("The stack didn't take the device's {property}")]
+    IdentityRejected { property: String },
+
+    #[error("Couldn't initialize the datalink: {reason}")]
+    DatalinkInitFailed { reason: String },
 
     #[error("Decoding failed")]
     DecodeFailed,
@@ -43,6 +84,27 @@ pub enum BACnetErr {
     #[error("Unhandled type tag {tag_name} ({tag:?})")]
     UnhandledTag { tag_name: String, tag: u8 },
 
+    #[error("Unsupported DBCS code page {code_page}")]
+    UnsupportedCodePage { code_page: u16 },
+
+    #[error("Object type {object_type} isn't supported")]
+    UnsupportedObjectType { object_type: u32 },
+
+    #[error("Object {object_type}:{object_instance} already exists")]
+    ObjectExists {
+        object_type: u32,
+        object_instance: u32,
+    },
+
+    #[error("No object {object_type}:{object_instance}")]
+    UnknownObject {
+        object_type: u32,
+        object_instance: u32,
+    },
+
+    #[error("The table has room for {max} entries")]
+    TableFull { max: usize },
+
     #[error("Couldn't get lock")]
     CouldntGetLock,
 }
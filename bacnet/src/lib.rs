@@ -1,74 +1,93 @@
-use crate::encoding::encode_data;
+use crate::{
+    encoding::{
+        decode_read_access_results, encode_application_data, encode_value_list, link_values,
+    },
+    enums::resolve_names,
+};
+#[cfg(feature = "async")]
+pub use async_client::AsyncBACnetServer;
 use bacnet_sys::{
-    address_add, address_bind_request, address_init, address_remove_device, apdu_set_abort_handler,
-    apdu_set_confirmed_ack_handler, apdu_set_confirmed_handler,
+    address_add, address_bind_request, address_remove_device, apdu_set_abort_handler,
+    apdu_set_complex_error_handler, apdu_set_confirmed_ack_handler, apdu_set_confirmed_handler,
     apdu_set_confirmed_simple_ack_handler, apdu_set_error_handler, apdu_set_reject_handler,
     apdu_set_unconfirmed_handler, apdu_set_unrecognized_service_handler_handler,
-    bacnet_address_same, bactext_abort_reason_name, bactext_error_class_name,
-    bactext_error_code_name, bactext_property_name, bip_cleanup, bip_receive, dlenv_init,
-    handler_read_property, handler_unrecognized_service, handler_who_is, npdu_handler,
-    property_list_special, rp_ack_decode_service_request, special_property_list_t,
-    tsm_invoke_id_failed, tsm_invoke_id_free, BACnetObjectType_OBJECT_DEVICE,
+    bacerror_decode_error_class_and_code, bactext_abort_reason_name, bactext_error_class_name,
+    bactext_error_code_name, bactext_property_name, handler_cov_init, handler_cov_subscribe,
+    handler_read_property, handler_read_property_multiple, handler_unrecognized_service,
+    handler_who_has, handler_who_is, handler_write_property, handler_write_property_multiple,
+    property_list_special, rp_ack_decode_service_request, rr_ack_decode_service_request,
+    special_property_list_t, wpm_error_ack_decode_apdu, BACnetObjectType_OBJECT_DEVICE,
+    BACnet_Confirmed_Service_Choice_SERVICE_CONFIRMED_COV_NOTIFICATION,
     BACnet_Confirmed_Service_Choice_SERVICE_CONFIRMED_READ_PROPERTY,
+    BACnet_Confirmed_Service_Choice_SERVICE_CONFIRMED_READ_PROP_MULTIPLE,
+    BACnet_Confirmed_Service_Choice_SERVICE_CONFIRMED_READ_RANGE,
+    BACnet_Confirmed_Service_Choice_SERVICE_CONFIRMED_SUBSCRIBE_COV,
+    BACnet_Confirmed_Service_Choice_SERVICE_CONFIRMED_SUBSCRIBE_COV_PROPERTY,
     BACnet_Confirmed_Service_Choice_SERVICE_CONFIRMED_WRITE_PROPERTY,
+    BACnet_Confirmed_Service_Choice_SERVICE_CONFIRMED_WRITE_PROP_MULTIPLE,
+    BACnet_Unconfirmed_Service_Choice_SERVICE_UNCONFIRMED_COV_NOTIFICATION,
     BACnet_Unconfirmed_Service_Choice_SERVICE_UNCONFIRMED_I_AM,
     BACnet_Unconfirmed_Service_Choice_SERVICE_UNCONFIRMED_I_HAVE,
-    BACnet_Unconfirmed_Service_Choice_SERVICE_UNCONFIRMED_WHO_IS, Device_Init,
-    Send_Read_Property_Request, Send_Write_Property_Request, BACNET_ADDRESS, BACNET_ARRAY_ALL,
+    BACnet_Unconfirmed_Service_Choice_SERVICE_UNCONFIRMED_WHO_HAS,
+    BACnet_Unconfirmed_Service_Choice_SERVICE_UNCONFIRMED_WHO_IS, Send_ReadRange_Request,
+    Send_Read_Property_Multiple_Request, Send_Read_Property_Request,
+    Send_Write_Property_Multiple_Request, Send_Write_Property_Request,
+    Send_Write_Property_Request_Data, BACNET_ADDRESS, BACNET_ARRAY_ALL,
     BACNET_CONFIRMED_SERVICE_ACK_DATA, BACNET_ERROR_CLASS, BACNET_ERROR_CODE, BACNET_OBJECT_TYPE,
     BACNET_PROPERTY_ID, BACNET_PROPERTY_ID_PROP_OBJECT_LIST, BACNET_PROPERTY_ID_PROP_PRESENT_VALUE,
-    BACNET_READ_PROPERTY_DATA, MAX_APDU, MAX_MPDU,
+    BACNET_PROPERTY_REFERENCE, BACNET_PROPERTY_VALUE, BACNET_READ_ACCESS_DATA,
+    BACNET_READ_PROPERTY_DATA, BACNET_READ_RANGE_DATA, BACNET_WRITE_ACCESS_DATA,
+    BACNET_WRITE_PROPERTY_DATA, MAX_APDU, MAX_MPDU,
 };
+use cov::{my_ccov_notification_handler, my_ucov_notification_handler, CovSubscription};
+pub use datalink::DatalinkConfig;
+pub use device::{BACnetObject, LocalDevice, LocalObject};
 use encoding::decode_data;
 pub use epics::Epics;
 use errors::{BACnetErr, Result};
 use log::{debug, error, info, log_enabled, trace, warn};
-use once_cell::sync::Lazy;
+use network::complete_request;
+pub use network::{BACnetNetwork, RequestOptions};
+use read_range::{decode_read_range_ack, ReadRange, ReadRangeResult};
+pub use router::MacAddress;
+#[cfg(feature = "bip6")]
+use std::net::{Ipv6Addr, SocketAddrV6};
 use std::{
-    cmp::min,
-    collections::HashMap,
-    ffi::CStr,
-    net::Ipv4Addr,
-    os::raw::c_char,
-    sync::{Mutex, Once},
+    cmp::min, collections::HashMap, ffi::CStr, net::Ipv4Addr, os::raw::c_char, sync::Arc,
+    time::Duration,
 };
 use value::BACnetValue;
 use whohas::i_have_handler;
 use whois::i_am_handler;
 
+#[cfg(feature = "async")]
+pub mod async_client;
+pub mod bbmd;
+mod charset;
+pub mod cov;
+pub mod datalink;
+pub mod device;
 mod encoding;
+mod enums;
 mod epics;
 pub mod errors;
+pub mod network;
+pub mod read_range;
+pub mod router;
 pub mod value;
 pub mod whohas;
 pub mod whois;
 
-static BACNET_STACK_INIT: Once = Once::new();
-
 type RequestInvokeId = u8;
 type DeviceId = u32;
 
-// We need a global structure here for collecting "target addresses"
-static TARGET_ADDRESSES: Lazy<Mutex<HashMap<DeviceId, TargetServer>>> =
-    Lazy::new(|| Mutex::new(HashMap::new()));
-
-// Status of a request
-enum RequestStatus {
-    Ongoing,          // No reply has been received yet
-    Done,             // Successfully completed
-    Error(BACnetErr), // Request failed
-}
-
-// A structure for tracking
-//
-// FIXME(tj): This is a really poor hand-off mechanism. When making a request, we set the
-// request_invoke_id so the response can be matched properly, then we set the decoded value inside
-// an Option and read_prop() fishes it out. This means that read_prop() needs to acquire the mutex
-// twice for each data extraction, which seems like a really poor design.
-struct TargetServer {
-    addr: BACNET_ADDRESS,
-    request: Option<(RequestInvokeId, RequestStatus)>, // For tracking on-going an ongoing request
-    value: Option<Result<BACnetValue>>,                // TODO Build this into the 'request status'
+// The response to a confirmed request, as decoded by the handlers
+pub(crate) enum Response {
+    Ack,                                       // SimpleACK
+    Value(Result<BACnetValue>),                // ReadProperty
+    ReadAccess(Result<Vec<ReadAccessResult>>), // ReadPropertyMultiple
+    ReadRange(Result<ReadRangeResult>),        // ReadRange
+    Error(BACnetErr),                          // Error, Reject or Abort
 }
 
 // As I understand the BACnet stack, it works by acting as another BACnet device on the network.
@@ -83,47 +102,75 @@ pub struct BACnetServer {
     pub device_id: u32,
     max_apdu: u32,
     addr: BACNET_ADDRESS,
+    options: RequestOptions,
+    binding: Arc<Binding>,
+}
+
+// How to reach a server. Removes the server from the address cache once the last handle on it is
+// gone
+#[derive(Debug)]
+struct Binding {
+    device_id: DeviceId,
+    // Remote network to find the router to when connecting
+    network: Option<u16>,
+    #[cfg(feature = "bip6")]
+    ipv6: Option<SocketAddrV6>,
+}
+
+impl Binding {
+    // Add the server at `addr` to the address cache and bind it. Must run on the driver thread.
+    fn bind_request(&self, mut addr: BACNET_ADDRESS) -> (bool, u32) {
+        #[cfg(feature = "bip6")]
+        if let Some(ipv6) = self.ipv6 {
+            datalink::add_vmac(&addr, ipv6);
+        }
+        unsafe {
+            address_add(self.device_id, MAX_APDU, &mut addr);
+            let mut max_apdu = 0;
+            let mut target_addr = BACNET_ADDRESS::default();
+            // FIXME(tj): Wait until server is bound, or timeout
+            let found = address_bind_request(self.device_id, &mut max_apdu, &mut target_addr);
+            (found, max_apdu)
+        }
+    }
+}
+
+impl Drop for Binding {
+    fn drop(&mut self) {
+        disconnect(self.device_id);
+    }
 }
 
 pub type ObjectType = BACNET_OBJECT_TYPE;
 pub type ObjectPropertyId = BACNET_PROPERTY_ID;
 
+/// The values (or errors) read from a single object by a ReadPropertyMultiple request
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadAccessResult {
+    pub object_type: ObjectType,
+    pub object_instance: u32,
+    pub properties: HashMap<ObjectPropertyId, Result<BACnetValue>>,
+}
+
 impl BACnetServer {
     pub fn builder() -> BACnetServerBuilder {
         BACnetServerBuilder::default()
     }
 
     pub fn connect(&mut self) -> Result<()> {
-        BACNET_STACK_INIT.call_once(|| unsafe {
-            bip_cleanup();
-            init_service_handlers();
-            address_init();
-            dlenv_init();
-        });
-        // Add address
-        unsafe {
-            address_add(self.device_id, MAX_APDU, &mut self.addr);
+        if let Some(network) = self.binding.network {
+            router::resolve(network)?.set_mac(&mut self.addr);
         }
-        let mut target_addr = BACNET_ADDRESS::default();
-        // FIXME(tj): Wait until server is bound, or timeout
-        let found =
-            unsafe { address_bind_request(self.device_id, &mut self.max_apdu, &mut target_addr) };
+        let device_id = self.device_id;
+        let addr = self.addr;
+        let binding = Arc::clone(&self.binding);
+        let (found, max_apdu) = BACnetNetwork::get()?.run(move || binding.bind_request(addr))?;
         debug!("found = {}", found);
         if found {
-            let mut lock = TARGET_ADDRESSES.lock().unwrap();
-            lock.insert(
-                self.device_id,
-                TargetServer {
-                    addr: target_addr,
-                    request: None,
-                    value: None,
-                },
-            );
+            self.max_apdu = max_apdu;
             Ok(())
         } else {
-            Err(BACnetErr::NotConnected {
-                device_id: self.device_id,
-            })
+            Err(BACnetErr::NotConnected { device_id })
         }
     }
 
@@ -165,76 +212,141 @@ impl BACnetServer {
         index: u32,
     ) -> Result<BACnetValue> {
         let init = std::time::Instant::now();
-        const TIMEOUT: u32 = 100;
-        let request_invoke_id =
-            if let Some(h) = TARGET_ADDRESSES.lock().unwrap().get_mut(&self.device_id) {
-                let request_invoke_id = unsafe {
-                    Send_Read_Property_Request(
-                        self.device_id,
-                        object_type,
-                        object_instance,
-                        property_id,
-                        index,
-                    )
-                };
-                h.request = Some((request_invoke_id, RequestStatus::Ongoing));
-                request_invoke_id
-            } else {
-                return Err(BACnetErr::NotConnected {
-                    device_id: self.device_id,
-                });
-            };
+        let response = self.request(move |device_id| {
+            Ok(unsafe {
+                Send_Read_Property_Request(
+                    device_id,
+                    object_type,
+                    object_instance,
+                    property_id,
+                    index,
+                )
+            })
+        });
+        let ret = match response? {
+            Response::Value(value) => value,
+            _ => Err(BACnetErr::NoValue),
+        };
 
-        let mut src = BACNET_ADDRESS::default();
-        let mut rx_buf = [0u8; MAX_MPDU as usize];
-        let start = std::time::Instant::now();
-        loop {
-            // TODO(tj): Consider pulling the "driving forward the internal state machine" stuff
-            // into an inner method here. We need it for EPICS as well.
-            let pdu_len =
-                unsafe { bip_receive(&mut src, &mut rx_buf as *mut _, MAX_MPDU as u16, TIMEOUT) };
-            if pdu_len > 0 {
-                unsafe { npdu_handler(&mut src, &mut rx_buf as *mut _, pdu_len) }
-            }
+        trace!("read_prop_at() finished in {:?}", init.elapsed());
+        ret
+    }
 
-            if unsafe { tsm_invoke_id_free(request_invoke_id) } {
-                break;
-            }
-            if unsafe { tsm_invoke_id_failed(request_invoke_id) } {
-                return Err(BACnetErr::TsmTimeout);
-            }
+    /// Reads several properties from several objects in a single request
+    ///
+    /// We call Send_Read_Property_Multiple_Request, and wait for a result. Every property is read
+    /// with BACNET_ARRAY_ALL, and a property the server couldn't read is reported as an error in
+    /// the matching `ReadAccessResult` rather than failing the whole request.
+    pub fn read_prop_multiple(
+        &self,
+        objects: &[(ObjectType, u32, Vec<ObjectPropertyId>)],
+    ) -> Result<Vec<ReadAccessResult>> {
+        let init = std::time::Instant::now();
+        let objects = objects.to_vec();
+        let ret =
+            match self.request(move |device_id| send_read_prop_multiple(device_id, &objects))? {
+                Response::ReadAccess(results) => results,
+                _ => Err(BACnetErr::NoValue),
+            };
 
-            if start.elapsed().as_secs() > 3 {
-                return Err(BACnetErr::ApduTimeout);
-            }
-        }
+        trace!("read_prop_multiple() finished in {:?}", init.elapsed());
+        ret
+    }
 
-        let ret = {
-            let mut lock = TARGET_ADDRESSES.lock().unwrap();
+    /// Reads a range of items from a list property, like the log-buffer of a Trend Log
+    ///
+    /// We call Send_ReadRange_Request, and wait for a result. The returned items are decoded as
+    /// log records.
+    pub fn read_range(
+        &self,
+        object_type: ObjectType,
+        object_instance: u32,
+        property_id: ObjectPropertyId,
+        range: ReadRange,
+    ) -> Result<ReadRangeResult> {
+        let init = std::time::Instant::now();
+        let response = self.request(move |device_id| {
+            Ok(send_read_range(
+                device_id,
+                object_type,
+                object_instance,
+                property_id,
+                &range,
+            ))
+        });
+        let ret = match response? {
+            Response::ReadRange(result) => result,
+            _ => Err(BACnetErr::NoValue),
+        };
 
-            let h = lock.get_mut(&self.device_id);
-            if h.is_none() {
-                return Err(BACnetErr::NotConnected {
-                    device_id: self.device_id,
-                });
-            }
-            let h = h.unwrap();
+        trace!("read_range() finished in {:?}", init.elapsed());
+        ret
+    }
 
-            let request_status = h.request.take();
-            if request_status.is_none() {
-                return Err(BACnetErr::NoValue);
-            }
-            let request_status = request_status.unwrap();
+    /// Subscribes to changes of value of an object
+    ///
+    /// The subscription lasts for `lifetime` (in whole seconds, zero meaning indefinitely) and is
+    /// renewed automatically until the returned `CovSubscription` is dropped, which cancels it. A
+    /// lifetime under a second or over `u32::MAX` seconds fails with `InvalidValue`.
+    /// With `confirmed` the server sends ConfirmedCOVNotifications, which we acknowledge.
+    pub fn subscribe_cov(
+        &self,
+        object_type: ObjectType,
+        object_instance: u32,
+        confirmed: bool,
+        lifetime: Duration,
+    ) -> Result<CovSubscription<'_>> {
+        CovSubscription::new(
+            self,
+            object_type,
+            object_instance,
+            None,
+            confirmed,
+            lifetime,
+        )
+    }
 
-            match request_status.1 {
-                RequestStatus::Done => h.value.take().unwrap_or(Err(BACnetErr::NoValue)),
-                RequestStatus::Ongoing => Err(BACnetErr::RequestOngoing),
-                RequestStatus::Error(err) => Err(err),
-            }
-        };
+    /// Subscribes to changes of value of a single property of an object
+    ///
+    /// Like `subscribe_cov()`, but only `property` is monitored. With a `cov_increment` the
+    /// server only notifies us once the (numeric) value has changed by at least that much,
+    /// instead of using the increment configured on the object.
+    pub fn subscribe_cov_property(
+        &self,
+        object_type: ObjectType,
+        object_instance: u32,
+        property: ObjectPropertyId,
+        cov_increment: Option<f32>,
+        confirmed: bool,
+        lifetime: Duration,
+    ) -> Result<CovSubscription<'_>> {
+        CovSubscription::new(
+            self,
+            object_type,
+            object_instance,
+            Some((property, cov_increment)),
+            confirmed,
+            lifetime,
+        )
+    }
 
-        trace!("read_prop_at() finished in {:?}", init.elapsed());
-        ret
+    /// A handle on the same server, sending its requests with different timeouts and retries
+    ///
+    /// Whatever isn't set in `options` is taken from this server. This is meant for a single call
+    /// (or a few), like `server.with_options(options).read_prop(..)`.
+    pub fn with_options(&self, options: RequestOptions) -> Self {
+        Self {
+            options: options.or(self.options),
+            ..self.clone()
+        }
+    }
+
+    // Have the network send a request to this server, and wait for the response
+    pub(crate) fn request(
+        &self,
+        send: impl FnMut(DeviceId) -> Result<RequestInvokeId> + Send + 'static,
+    ) -> Result<Response> {
+        BACnetNetwork::get()?.request(self.device_id, self.options, send)
     }
 
     /// Read all required properties for a given object-type and object-instance
@@ -280,8 +392,8 @@ impl BACnetServer {
                         } => {
                             debug!("{}", bacnet_err);
                         }
-                        // If we get a timeout, we'll just return the error
-                        BACnetErr::TsmTimeout | BACnetErr::ApduTimeout => {
+                        // If the request couldn't be sent or went unanswered, we'll just return the error
+                        BACnetErr::NoInvokeId | BACnetErr::NoResponse { .. } => {
                             return Err(bacnet_err);
                         }
                         _ => {
@@ -329,8 +441,8 @@ impl BACnetServer {
                                 ret.insert(prop, BACnetValue::Array(ary));
                             }
                         }
-                        BACnetErr::TsmTimeout | BACnetErr::ApduTimeout => {
-                            // If we get a timeout, we'll just return the error
+                        BACnetErr::NoInvokeId | BACnetErr::NoResponse { .. } => {
+                            // If the request couldn't be sent or went unanswered, we'll just return the error
                             return Err(bacnet_err);
                         }
                         // If we get another error on a optional property, we'll just ignore it
@@ -376,12 +488,54 @@ impl BACnetServer {
             value,
             property_id,
             BACNET_ARRAY_ALL,
+            None,
         )
     }
 
-    /// Writes a property at a specific index
+    /// Writes a property at the given priority (1-16)
     ///
     /// We call Send_Write_Property_Request, and wait for a result.
+    pub fn write_prop_with_priority(
+        &self,
+        object_type: ObjectType,
+        object_instance: u32,
+        value: BACnetValue,
+        property_id: ObjectPropertyId,
+        priority: u8,
+    ) -> Result<()> {
+        self.write_prop_at(
+            object_type,
+            object_instance,
+            value,
+            property_id,
+            BACNET_ARRAY_ALL,
+            Some(priority),
+        )
+    }
+
+    /// Relinquishes the present value at the given priority (1-16)
+    ///
+    /// This writes `BACnetValue::Null` to the present value, releasing the command at that slot of
+    /// the priority array.
+    pub fn relinquish(
+        &self,
+        object_type: ObjectType,
+        object_instance: u32,
+        priority: u8,
+    ) -> Result<()> {
+        self.write_prop_with_priority(
+            object_type,
+            object_instance,
+            BACnetValue::Null,
+            BACNET_PROPERTY_ID_PROP_PRESENT_VALUE,
+            priority,
+        )
+    }
+
+    /// Writes a property at a specific index
+    ///
+    /// The priority must be between 1 and 16, `None` writes without a priority. We call
+    /// Send_Write_Property_Request, and wait for a result.
     pub fn write_prop_at(
         &self,
         object_type: ObjectType,
@@ -389,81 +543,45 @@ impl BACnetServer {
         value: BACnetValue,
         property_id: ObjectPropertyId,
         index: u32,
+        priority: Option<u8>,
     ) -> Result<()> {
         let init = std::time::Instant::now();
-        const TIMEOUT: u32 = 100;
-        let request_invoke_id =
-            if let Some(h) = TARGET_ADDRESSES.lock().unwrap().get_mut(&self.device_id) {
-                let request_invoke_id = unsafe {
-                    let mut object_value = encode_data(value)?;
-
-                    Send_Write_Property_Request(
-                        self.device_id,
-                        object_type,
-                        object_instance,
-                        property_id,
-                        &mut object_value,
-                        0,
-                        index,
-                    )
-                };
-                h.request = Some((request_invoke_id, RequestStatus::Ongoing));
-                request_invoke_id
-            } else {
-                return Err(BACnetErr::NotConnected {
-                    device_id: self.device_id,
-                });
-            };
-
-        let mut src = BACNET_ADDRESS::default();
-        let mut rx_buf = [0u8; MAX_MPDU as usize];
-        let start = std::time::Instant::now();
-        loop {
-            // TODO(tj): Consider pulling the "driving forward the internal state machine" stuff
-            // into an inner method here. We need it for EPICS as well.
-            let pdu_len =
-                unsafe { bip_receive(&mut src, &mut rx_buf as *mut _, MAX_MPDU as u16, TIMEOUT) };
-            if pdu_len > 0 {
-                unsafe { npdu_handler(&mut src, &mut rx_buf as *mut _, pdu_len) }
-            }
-
-            if unsafe { tsm_invoke_id_free(request_invoke_id) } {
-                break;
-            }
-            if unsafe { tsm_invoke_id_failed(request_invoke_id) } {
-                return Err(BACnetErr::TsmTimeout);
-            }
-
-            if start.elapsed().as_secs() > 3 {
-                return Err(BACnetErr::ApduTimeout);
-            }
-        }
-
-        let ret = {
-            let mut lock = TARGET_ADDRESSES.lock().unwrap();
-
-            let h = lock.get_mut(&self.device_id);
-            if h.is_none() {
-                return Err(BACnetErr::NotConnected {
-                    device_id: self.device_id,
-                });
-            }
-            let h = h.unwrap();
+        let ret = self
+            .request(move |device_id| {
+                send_write_prop(
+                    device_id,
+                    object_type,
+                    object_instance,
+                    &value,
+                    property_id,
+                    index,
+                    priority,
+                )
+            })
+            .map(|_| ());
 
-            let request_status = h.request.take();
-            if request_status.is_none() {
-                return Err(BACnetErr::NoValue);
-            }
-            let request_status = request_status.unwrap();
+        trace!("write_prop_at() finished in {:?}", init.elapsed());
+        ret
+    }
 
-            match request_status.1 {
-                RequestStatus::Done => Ok(()),
-                RequestStatus::Ongoing => Err(BACnetErr::RequestOngoing),
-                RequestStatus::Error(err) => Err(err),
-            }
-        };
+    /// Writes several properties on several objects in a single request
+    ///
+    /// Each write is given as (object-type, object-instance, property, value, priority), with the
+    /// priority being between 1 and 16 or `None` for no priority. Consecutive writes to the same
+    /// object are grouped together.
+    /// We call Send_Write_Property_Multiple_Request, and wait for a result. If the server rejects
+    /// one of the writes, `BACnetErr::WritePropertyMultiple` tells which one it was.
+    pub fn write_prop_multiple(
+        &self,
+        writes: &[(ObjectType, u32, ObjectPropertyId, BACnetValue, Option<u8>)],
+    ) -> Result<()> {
+        let init = std::time::Instant::now();
+        let writes = writes.to_vec();
+        let ret = self
+            .request(move |device_id| send_write_prop_multiple(device_id, &writes))
+            .map(|_| ());
 
-        trace!("write_prop_at() finished in {:?}", init.elapsed());
+        trace!("write_prop_multiple() finished in {:?}", init.elapsed());
         ret
     }
 
@@ -532,52 +650,80 @@ impl BACnetServer {
         })
     }
 
+    /// Remove the server from the address cache
+    ///
+    /// This happens by itself once the server (and every handle from `with_options()`) is dropped.
     pub fn disconnect(&self) {
-        info!("disconnecting");
-        unsafe { address_remove_device(self.device_id) };
+        disconnect(self.device_id);
     }
 }
 
-impl Drop for BACnetServer {
-    fn drop(&mut self) {
-        self.disconnect();
+pub(crate) fn disconnect(device_id: DeviceId) {
+    info!("disconnecting");
+    if let Err(err) = BACnetNetwork::get().and_then(|network| {
+        network.run_detached(move || unsafe { address_remove_device(device_id) })
+    }) {
+        warn!("failed to disconnect: {}", err);
     }
 }
 
 #[derive(Debug)]
 pub struct BACnetServerBuilder {
-    ip: Ipv4Addr,
+    ip: Option<Ipv4Addr>,
     dnet: u16,
-    dadr: u8,
+    dadr: MacAddress,
     port: u16,
     device_id: u32,
+    options: RequestOptions,
+    #[cfg(feature = "bip6")]
+    ipv6: Option<Ipv6Addr>,
+    #[cfg(feature = "bip6")]
+    vmac: Option<u32>,
+    #[cfg(feature = "mstp")]
+    mstp: Option<u8>,
+    #[cfg(feature = "ethernet")]
+    ethernet: Option<[u8; 6]>,
 }
 
 impl Default for BACnetServerBuilder {
     fn default() -> Self {
         Self {
-            ip: Ipv4Addr::LOCALHOST,
+            ip: None,
             dnet: 0,
-            dadr: 0,
+            dadr: MacAddress::from(0),
             port: 0xBAC0,
             device_id: 0,
+            options: RequestOptions::default(),
+            #[cfg(feature = "bip6")]
+            ipv6: None,
+            #[cfg(feature = "bip6")]
+            vmac: None,
+            #[cfg(feature = "mstp")]
+            mstp: None,
+            #[cfg(feature = "ethernet")]
+            ethernet: None,
         }
     }
 }
 
 impl BACnetServerBuilder {
+    /// Set the server's address, or the address of the router to it if it's on a remote network.
+    /// Default: localhost
     pub fn ip(mut self, ip: Ipv4Addr) -> Self {
-        self.ip = ip;
+        self.ip = Some(ip);
         self
     }
 
+    /// Reach the server on a remote network. Without an address of its own (`ip` or the like)
+    /// the router to that network is looked up when connecting, see the `router` module
     pub fn dnet(mut self, dnet: u16) -> Self {
         self.dnet = dnet;
         self
     }
 
-    pub fn dadr(mut self, dadr: u8) -> Self {
-        self.dadr = dadr;
+    /// Set the server's address on its remote network, up to 7 bytes
+    pub fn dadr(mut self, dadr: impl Into<MacAddress>) -> Self {
+        self.dadr = dadr.into();
         self
     }
 
@@ -591,29 +737,321 @@ impl BACnetServerBuilder {
         self
     }
 
+    /// Reach the server over BACnet/IPv6, at this address instead of `ip`. Needs the network to
+    /// be started with `DatalinkConfig::bip6()`
+    #[cfg(feature = "bip6")]
+    pub fn ipv6(mut self, ipv6: Ipv6Addr) -> Self {
+        self.ipv6 = Some(ipv6);
+        self
+    }
+
+    /// Set the server's VMAC (a 3 byte virtual MAC address) on BACnet/IPv6. Default: the device
+    /// id, which is what bacnet-stack based servers use. Without `ipv6` the server is reached
+    /// through the VMAC alone, which works for devices that have talked to us, e.g. in a Who-Is
+    #[cfg(feature = "bip6")]
+    pub fn vmac(mut self, vmac: u32) -> Self {
+        self.vmac = Some(vmac);
+        self
+    }
+
+    /// Reach the server over MS/TP, at this MAC address instead of `ip`. Needs the network to be
+    /// started with `DatalinkConfig::mstp()`
+    #[cfg(feature = "mstp")]
+    pub fn mstp(mut self, mac: u8) -> Self {
+        self.mstp = Some(mac);
+        self
+    }
+
+    /// Reach the server over BACnet/Ethernet, at this MAC address instead of `ip`. Needs the
+    /// network to be started with `DatalinkConfig::ethernet()`
+    #[cfg(feature = "ethernet")]
+    pub fn ethernet(mut self, mac: [u8; 6]) -> Self {
+        self.ethernet = Some(mac);
+        self
+    }
+
+    /// Set how long to wait for a response before sending a request again. Default: the stack's
+    /// APDU timeout, see `RequestOptions`
+    pub fn apdu_timeout(mut self, timeout: Duration) -> Self {
+        self.options = self.options.apdu_timeout(timeout);
+        self
+    }
+
+    /// Set how many times a request is sent again if no response comes in. Default: the stack's
+    /// number of APDU retries, see `RequestOptions`
+    pub fn retries(mut self, retries: u8) -> Self {
+        self.options = self.options.retries(retries);
+        self
+    }
+
     pub fn build(self) -> BACnetServer {
-        let BACnetServerBuilder {
-            ip,
-            dnet,
-            dadr,
-            port,
-            device_id,
-        } = self;
+        BACnetServer {
+            device_id: self.device_id,
+            max_apdu: 0,
+            addr: self.address(),
+            options: self.options,
+            binding: self.binding(),
+        }
+    }
+
+    /// Build a server for the async client, see the `async_client` module
+    #[cfg(feature = "async")]
+    pub fn build_async(self) -> AsyncBACnetServer {
+        AsyncBACnetServer::new(self.device_id, self.address(), self.options, self.binding())
+    }
+
+    fn binding(&self) -> Arc<Binding> {
+        Arc::new(Binding {
+            device_id: self.device_id,
+            network: (self.dnet != 0 && !self.has_address()).then_some(self.dnet),
+            #[cfg(feature = "bip6")]
+            ipv6: self
+                .ipv6
+                .map(|ipv6| SocketAddrV6::new(ipv6, self.port, 0, 0)),
+        })
+    }
+
+    // Whether we were told where to send to, rather than having to find a router
+    fn has_address(&self) -> bool {
+        #[cfg(feature = "bip6")]
+        if self.ipv6.is_some() || self.vmac.is_some() {
+            return true;
+        }
+        #[cfg(feature = "mstp")]
+        if self.mstp.is_some() {
+            return true;
+        }
+        #[cfg(feature = "ethernet")]
+        if self.ethernet.is_some() {
+            return true;
+        }
+        self.ip.is_some()
+    }
+
+    fn address(&self) -> BACNET_ADDRESS {
         let mut addr = BACNET_ADDRESS::default();
+        let ip = self.ip.unwrap_or(Ipv4Addr::LOCALHOST);
         addr.mac[..4].copy_from_slice(&ip.octets());
-        addr.mac[4] = (port >> 8) as u8;
-        addr.mac[5] = (port & 0xff) as u8;
+        addr.mac[4] = (self.port >> 8) as u8;
+        addr.mac[5] = (self.port & 0xff) as u8;
         addr.mac_len = 6;
-        addr.net = dnet;
-        addr.adr[0] = dadr;
-        addr.len = 1;
+        // On BACnet/IPv6 devices are addressed by their VMAC
+        #[cfg(feature = "bip6")]
+        if self.ipv6.is_some() || self.vmac.is_some() {
+            let vmac = self.vmac.unwrap_or(self.device_id).to_be_bytes();
+            addr.mac = Default::default();
+            addr.mac[..3].copy_from_slice(&vmac[1..]);
+            addr.mac_len = 3;
+        }
+        #[cfg(feature = "mstp")]
+        if let Some(mac) = self.mstp {
+            addr.mac = Default::default();
+            addr.mac[0] = mac;
+            addr.mac_len = 1;
+        }
+        #[cfg(feature = "ethernet")]
+        if let Some(mac) = self.ethernet {
+            addr.mac = Default::default();
+            addr.mac[..6].copy_from_slice(&mac);
+            addr.mac_len = 6;
+        }
+        addr.net = self.dnet;
+        self.dadr.set_adr(&mut addr);
+        addr
+    }
+}
 
-        BACnetServer {
+// The sending half of the requests, shared by the blocking and the async client. Each returns the
+// invoke ID of the request, which is 0 if it couldn't be sent.
+
+// Send a ReadPropertyMultiple request, see `BACnetServer::read_prop_multiple()`
+pub(crate) fn send_read_prop_multiple(
+    device_id: DeviceId,
+    objects: &[(ObjectType, u32, Vec<ObjectPropertyId>)],
+) -> Result<RequestInvokeId> {
+    if objects.is_empty() || objects.iter().any(|(_, _, props)| props.is_empty()) {
+        return Err(BACnetErr::EncodeFailed);
+    }
+
+    // The request is a linked list of objects, each holding a linked list of properties. We
+    // build every node up front so the vectors never reallocate once they're linked.
+    let mut property_lists = objects
+        .iter()
+        .map(|(_, _, props)| {
+            props
+                .iter()
+                .map(|&prop| BACNET_PROPERTY_REFERENCE {
+                    propertyIdentifier: prop,
+                    propertyArrayIndex: BACNET_ARRAY_ALL,
+                    ..Default::default()
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    for list in property_lists.iter_mut() {
+        for i in 1..list.len() {
+            list[i - 1].next = &mut list[i];
+        }
+    }
+    let mut read_access_data = objects
+        .iter()
+        .zip(property_lists.iter_mut())
+        .map(
+            |((object_type, object_instance, _), list)| BACNET_READ_ACCESS_DATA {
+                object_type: *object_type,
+                object_instance: *object_instance,
+                listOfProperties: list.as_mut_ptr(),
+                ..Default::default()
+            },
+        )
+        .collect::<Vec<_>>();
+    for i in 1..read_access_data.len() {
+        read_access_data[i - 1].next = &mut read_access_data[i];
+    }
+
+    let mut pdu = [0u8; MAX_MPDU as usize];
+    Ok(unsafe {
+        Send_Read_Property_Multiple_Request(
+            pdu.as_mut_ptr(),
+            pdu.len(),
             device_id,
-            max_apdu: 0,
-            addr,
+            read_access_data.as_mut_ptr(),
+        )
+    })
+}
+
+// Send a ReadRange request, see `BACnetServer::read_range()`
+pub(crate) fn send_read_range(
+    device_id: DeviceId,
+    object_type: ObjectType,
+    object_instance: u32,
+    property_id: ObjectPropertyId,
+    range: &ReadRange,
+) -> RequestInvokeId {
+    let mut rr_data = BACNET_READ_RANGE_DATA {
+        object_type,
+        object_instance,
+        object_property: property_id,
+        array_index: BACNET_ARRAY_ALL,
+        ..Default::default()
+    };
+    range.apply(&mut rr_data);
+
+    unsafe { Send_ReadRange_Request(device_id, &mut rr_data) }
+}
+
+// Send a WriteProperty request, see `BACnetServer::write_prop_at()`
+pub(crate) fn send_write_prop(
+    device_id: DeviceId,
+    object_type: ObjectType,
+    object_instance: u32,
+    value: &BACnetValue,
+    property_id: ObjectPropertyId,
+    index: u32,
+    priority: Option<u8>,
+) -> Result<RequestInvokeId> {
+    let priority = validate_priority(priority)?;
+    let value = &resolve_names(value.clone(), object_type, property_id);
+    Ok(unsafe {
+        match value {
+            // The stack can't encode these, so we hand it the encoded data instead
+            BACnetValue::Array(_)
+            | BACnetValue::Sequence(_)
+            | BACnetValue::Constructed { .. }
+            | BACnetValue::Context { .. } => {
+                let mut data = encode_application_data(value)?;
+                Send_Write_Property_Request_Data(
+                    device_id,
+                    object_type,
+                    object_instance,
+                    property_id,
+                    data.as_mut_ptr(),
+                    data.len() as i32,
+                    priority,
+                    index,
+                )
+            }
+            _ => {
+                let mut object_values = encode_value_list(value.clone())?;
+                link_values(&mut object_values);
+
+                Send_Write_Property_Request(
+                    device_id,
+                    object_type,
+                    object_instance,
+                    property_id,
+                    object_values.as_mut_ptr(),
+                    priority,
+                    index,
+                )
+            }
+        }
+    })
+}
+
+// Send a WritePropertyMultiple request, see `BACnetServer::write_prop_multiple()`
+pub(crate) fn send_write_prop_multiple(
+    device_id: DeviceId,
+    writes: &[(ObjectType, u32, ObjectPropertyId, BACnetValue, Option<u8>)],
+) -> Result<RequestInvokeId> {
+    if writes.is_empty() {
+        return Err(BACnetErr::EncodeFailed);
+    }
+
+    // Group the writes per object, then link everything together like for
+    // read_prop_multiple().
+    // Values taking more than one application data value (see `encode_value_list()`) keep the
+    // rest of their list in `encoded_values`.
+    let mut objects: Vec<(ObjectType, u32, Vec<BACNET_PROPERTY_VALUE>)> = vec![];
+    let mut encoded_values = vec![];
+    for (object_type, object_instance, property_id, value, priority) in writes {
+        let mut values =
+            encode_value_list(resolve_names(value.clone(), *object_type, *property_id))?;
+        link_values(&mut values);
+        let property_value = BACNET_PROPERTY_VALUE {
+            propertyIdentifier: *property_id,
+            propertyArrayIndex: BACNET_ARRAY_ALL,
+            value: values[0],
+            priority: validate_priority(*priority)?,
+            ..Default::default()
+        };
+        match objects.last_mut() {
+            Some((t, i, list)) if t == object_type && i == object_instance => {
+                list.push(property_value)
+            }
+            _ => objects.push((*object_type, *object_instance, vec![property_value])),
+        }
+        encoded_values.push(values);
+    }
+    for (_, _, list) in objects.iter_mut() {
+        for i in 1..list.len() {
+            list[i - 1].next = &mut list[i];
         }
     }
+    let mut write_access_data = objects
+        .iter_mut()
+        .map(
+            |(object_type, object_instance, list)| BACNET_WRITE_ACCESS_DATA {
+                object_type: *object_type,
+                object_instance: *object_instance,
+                listOfProperties: list.as_mut_ptr(),
+                ..Default::default()
+            },
+        )
+        .collect::<Vec<_>>();
+    for i in 1..write_access_data.len() {
+        write_access_data[i - 1].next = &mut write_access_data[i];
+    }
+
+    let mut pdu = [0u8; MAX_MPDU as usize];
+    Ok(unsafe {
+        Send_Write_Property_Multiple_Request(
+            pdu.as_mut_ptr(),
+            pdu.len(),
+            device_id,
+            write_access_data.as_mut_ptr(),
+        )
+    })
 }
 
 #[no_mangle]
@@ -626,41 +1064,64 @@ extern "C" fn my_readprop_ack_handler(
     let mut data: BACNET_READ_PROPERTY_DATA = BACNET_READ_PROPERTY_DATA::default();
 
     let invoke_id = unsafe { (*service_data).invoke_id };
-    let mut lock = TARGET_ADDRESSES.lock().unwrap();
-    if let Some(target) = find_matching_server(&mut lock, src, invoke_id) {
-        // Decode the data
-        let len = unsafe {
-            rp_ack_decode_service_request(service_request, service_len.into(), &mut data as *mut _)
-        };
-        if len >= 0 {
-            // XXX Consider moving data decoding out. We should probably just stick to getting
-            // the raw data, putting it somewhere and let someone else decode it.
-            let decoded = decode_data(data);
-            target.value = Some(decoded);
-        } else {
-            error!("<decode failed>");
-            target.value = Some(Err(BACnetErr::DecodeFailed));
-        }
-        target.request = Some((invoke_id, RequestStatus::Done));
-    }
+    // Decode the data
+    let len = unsafe {
+        rp_ack_decode_service_request(service_request, service_len.into(), &mut data as *mut _)
+    };
+    let value = if len >= 0 {
+        // XXX Consider moving data decoding out. We should probably just stick to getting
+        // the raw data, putting it somewhere and let someone else decode it.
+        decode_data(data)
+    } else {
+        error!("<decode failed>");
+        Err(BACnetErr::DecodeFailed)
+    };
+    complete_request(src, invoke_id, Response::Value(value));
 }
 
 #[no_mangle]
 extern "C" fn my_property_simple_ack_handler(src: *mut BACNET_ADDRESS, invoke_id: u8) {
-    let mut lock = TARGET_ADDRESSES.lock().unwrap();
-    if let Some(target) = find_matching_server(&mut lock, src, invoke_id) {
-        target.request = Some((invoke_id, RequestStatus::Done));
-    }
+    complete_request(src, invoke_id, Response::Ack);
 }
 
 #[no_mangle]
 extern "C" fn my_readpropmultiple_ack_handler(
-    _: u16,
-    _: *mut BACNET_ADDRESS,
-    _: *mut BACNET_CONFIRMED_SERVICE_ACK_DATA,
+    service_request: *mut u8,
+    service_len: u16,
+    src: *mut BACNET_ADDRESS,
+    service_data: *mut BACNET_CONFIRMED_SERVICE_ACK_DATA,
+) {
+    let invoke_id = unsafe { (*service_data).invoke_id };
+    // Decoded here rather than by the stack, which only keeps a single value per property and
+    // can't decode constructed values
+    let apdu = unsafe { std::slice::from_raw_parts(service_request, service_len as usize) };
+    let results = decode_read_access_results(apdu);
+    if results.is_err() {
+        error!("<decode failed>");
+    }
+    complete_request(src, invoke_id, Response::ReadAccess(results));
+}
+
+#[no_mangle]
+extern "C" fn my_readrange_ack_handler(
+    service_request: *mut u8,
+    service_len: u16,
+    src: *mut BACNET_ADDRESS,
+    service_data: *mut BACNET_CONFIRMED_SERVICE_ACK_DATA,
 ) {
-    // TODO
-    unimplemented!();
+    let invoke_id = unsafe { (*service_data).invoke_id };
+    // The decoded item data points into `service_request`, so it has to be decoded right away
+    let mut rr_data = BACNET_READ_RANGE_DATA::default();
+    let len =
+        unsafe { rr_ack_decode_service_request(service_request, service_len.into(), &mut rr_data) };
+    let result = if len > 0 {
+        let apdu = unsafe { std::slice::from_raw_parts(service_request, service_len as usize) };
+        decode_read_range_ack(apdu, &mut rr_data)
+    } else {
+        error!("<decode failed>");
+        Err(BACnetErr::DecodeFailed)
+    };
+    complete_request(src, invoke_id, Response::ReadRange(result));
 }
 
 #[no_mangle]
@@ -670,18 +1131,49 @@ extern "C" fn my_error_handler(
     error_class: BACNET_ERROR_CLASS,
     error_code: BACNET_ERROR_CODE,
 ) {
-    let mut lock = TARGET_ADDRESSES.lock().unwrap();
-    if let Some(target) = find_matching_server(&mut lock, src, invoke_id) {
-        let error_class_str = cstr(unsafe { bactext_error_class_name(error_class) });
-        let error_code_str = cstr(unsafe { bactext_error_code_name(error_code) });
-        let err = BACnetErr::Error {
-            class_text: error_class_str,
-            class: error_class,
-            text: error_code_str,
-            code: error_code,
-        };
-        target.request = Some((invoke_id, RequestStatus::Error(err)));
-    }
+    let err = bacnet_error(error_class, error_code);
+    complete_request(src, invoke_id, Response::Error(err));
+}
+
+#[no_mangle]
+extern "C" fn my_writepropmultiple_error_handler(
+    src: *mut BACNET_ADDRESS,
+    invoke_id: u8,
+    _service_choice: u8,
+    service_request: *mut u8,
+    service_len: u16,
+) {
+    // The error carries the first write that failed in addition to the error class and code
+    let mut wp_data = BACNET_WRITE_PROPERTY_DATA::default();
+    let len = unsafe { wpm_error_ack_decode_apdu(service_request, service_len, &mut wp_data) };
+    let mut error_class = BACNET_ERROR_CLASS::default();
+    let mut error_code = BACNET_ERROR_CODE::default();
+    let err = if len > 0 {
+        BACnetErr::WritePropertyMultiple {
+            object_type: wp_data.object_type,
+            object_instance: wp_data.object_instance,
+            property: wp_data.object_property,
+            class_text: cstr(unsafe { bactext_error_class_name(wp_data.error_class) }),
+            class: wp_data.error_class,
+            text: cstr(unsafe { bactext_error_code_name(wp_data.error_code) }),
+            code: wp_data.error_code,
+        }
+    } else if unsafe {
+        // Some devices answer with a plain error, without the write that failed
+        bacerror_decode_error_class_and_code(
+            service_request,
+            service_len.into(),
+            &mut error_class,
+            &mut error_code,
+        )
+    } > 0
+    {
+        bacnet_error(error_class, error_code)
+    } else {
+        error!("<decode failed>");
+        BACnetErr::DecodeFailed
+    };
+    complete_request(src, invoke_id, Response::Error(err));
 }
 
 #[no_mangle]
@@ -692,31 +1184,23 @@ extern "C" fn my_abort_handler(
     server: bool,
 ) {
     let _ = server;
-    let _ = src;
-    let mut lock = TARGET_ADDRESSES.lock().unwrap();
-    if let Some(target) = find_matching_server(&mut lock, src, invoke_id) {
-        let abort_text = cstr(unsafe { bactext_abort_reason_name(abort_reason as u32) });
-        let err_abort = BACnetErr::Aborted {
-            text: abort_text,
-            code: abort_reason,
-        };
-        target.request = Some((invoke_id, RequestStatus::Error(err_abort)));
-    }
+    let abort_text = cstr(unsafe { bactext_abort_reason_name(abort_reason as u32) });
+    let err_abort = BACnetErr::Aborted {
+        text: abort_text,
+        code: abort_reason,
+    };
+    complete_request(src, invoke_id, Response::Error(err_abort));
 }
 
 #[no_mangle]
 extern "C" fn my_reject_handler(src: *mut BACNET_ADDRESS, invoke_id: u8, reject_reason: u8) {
-    let _ = src;
-
-    let mut lock = TARGET_ADDRESSES.lock().unwrap();
-    if let Some(target) = find_matching_server(&mut lock, src, invoke_id) {
-        target.request = Some((
-            invoke_id,
-            RequestStatus::Error(BACnetErr::Rejected {
-                code: reject_reason,
-            }),
-        ));
-    }
+    complete_request(
+        src,
+        invoke_id,
+        Response::Error(BACnetErr::Rejected {
+            code: reject_reason,
+        }),
+    );
 }
 
 fn cstr(ptr: *const c_char) -> String {
@@ -725,26 +1209,24 @@ fn cstr(ptr: *const c_char) -> String {
         .into_owned()
 }
 
-// Holding the lock on the global map of servers, find a server that matches `src` and the given
-// RequestInvokeId.
-//
-// This function _should_ return something.
-fn find_matching_server<'a>(
-    guard: &'a mut std::sync::MutexGuard<'_, HashMap<u32, TargetServer>>,
-    src: *mut BACNET_ADDRESS,
-    invoke_id: RequestInvokeId,
-) -> Option<&'a mut TargetServer> {
-    for target in guard.values_mut() {
-        let is_addr_match = unsafe { bacnet_address_same(&mut target.addr, src) };
-        if let Some((request_invoke_id, _)) = &target.request {
-            let is_request_invoke_id = invoke_id == *request_invoke_id;
-            if is_addr_match && is_request_invoke_id {
-                return Some(target);
-            }
-        }
+// Build a `BACnetErr::Error` with the textual representation of the error class and code.
+fn bacnet_error(error_class: BACNET_ERROR_CLASS, error_code: BACNET_ERROR_CODE) -> BACnetErr {
+    BACnetErr::Error {
+        class_text: cstr(unsafe { bactext_error_class_name(error_class) }),
+        class: error_class,
+        text: cstr(unsafe { bactext_error_code_name(error_code) }),
+        code: error_code,
+    }
+}
+
+// Check that a write priority is within 1..=16, and turn it into what the stack expects (where 0
+// means no priority).
+fn validate_priority(priority: Option<u8>) -> Result<u8> {
+    match priority {
+        None => Ok(0),
+        Some(priority @ 1..=16) => Ok(priority),
+        Some(priority) => Err(BACnetErr::InvalidPriority { priority }),
     }
-    error!("Server wasn't matched! {:?}", src);
-    None
 }
 
 /// # Safety
@@ -752,7 +1234,7 @@ fn find_matching_server<'a>(
 /// We have to declare this function as unsafe but it's actually safe. The reason is that the
 /// whole function is a callback from the C library and we have to declare it as unsafe.
 pub unsafe fn init_service_handlers() {
-    Device_Init(std::ptr::null_mut());
+    device::init();
     apdu_set_unconfirmed_handler(
         BACnet_Unconfirmed_Service_Choice_SERVICE_UNCONFIRMED_WHO_IS,
         Some(handler_who_is),
@@ -765,24 +1247,102 @@ pub unsafe fn init_service_handlers() {
         BACnet_Unconfirmed_Service_Choice_SERVICE_UNCONFIRMED_I_HAVE,
         Some(i_have_handler),
     );
+    // Answered from the object table, so our own objects can be found by name or identifier
+    apdu_set_unconfirmed_handler(
+        BACnet_Unconfirmed_Service_Choice_SERVICE_UNCONFIRMED_WHO_HAS,
+        Some(handler_who_has),
+    );
     apdu_set_unrecognized_service_handler_handler(Some(handler_unrecognized_service));
     apdu_set_confirmed_handler(
         BACnet_Confirmed_Service_Choice_SERVICE_CONFIRMED_READ_PROPERTY,
         Some(handler_read_property),
     );
+    apdu_set_confirmed_handler(
+        BACnet_Confirmed_Service_Choice_SERVICE_CONFIRMED_READ_PROP_MULTIPLE,
+        Some(handler_read_property_multiple),
+    );
+    apdu_set_confirmed_handler(
+        BACnet_Confirmed_Service_Choice_SERVICE_CONFIRMED_WRITE_PROPERTY,
+        Some(handler_write_property),
+    );
+    apdu_set_confirmed_handler(
+        BACnet_Confirmed_Service_Choice_SERVICE_CONFIRMED_WRITE_PROP_MULTIPLE,
+        Some(handler_write_property_multiple),
+    );
     apdu_set_confirmed_ack_handler(
         BACnet_Confirmed_Service_Choice_SERVICE_CONFIRMED_READ_PROPERTY,
         Some(my_readprop_ack_handler),
     );
+    apdu_set_confirmed_ack_handler(
+        BACnet_Confirmed_Service_Choice_SERVICE_CONFIRMED_READ_PROP_MULTIPLE,
+        Some(my_readpropmultiple_ack_handler),
+    );
+    apdu_set_confirmed_ack_handler(
+        BACnet_Confirmed_Service_Choice_SERVICE_CONFIRMED_READ_RANGE,
+        Some(my_readrange_ack_handler),
+    );
     apdu_set_confirmed_simple_ack_handler(
         BACnet_Confirmed_Service_Choice_SERVICE_CONFIRMED_WRITE_PROPERTY,
         Some(my_property_simple_ack_handler),
     );
+    apdu_set_confirmed_simple_ack_handler(
+        BACnet_Confirmed_Service_Choice_SERVICE_CONFIRMED_WRITE_PROP_MULTIPLE,
+        Some(my_property_simple_ack_handler),
+    );
 
     apdu_set_error_handler(
         BACnet_Confirmed_Service_Choice_SERVICE_CONFIRMED_READ_PROPERTY,
         Some(my_error_handler),
     );
+    apdu_set_error_handler(
+        BACnet_Confirmed_Service_Choice_SERVICE_CONFIRMED_READ_PROP_MULTIPLE,
+        Some(my_error_handler),
+    );
+    apdu_set_error_handler(
+        BACnet_Confirmed_Service_Choice_SERVICE_CONFIRMED_READ_RANGE,
+        Some(my_error_handler),
+    );
+    apdu_set_error_handler(
+        BACnet_Confirmed_Service_Choice_SERVICE_CONFIRMED_WRITE_PROPERTY,
+        Some(my_error_handler),
+    );
+    apdu_set_confirmed_simple_ack_handler(
+        BACnet_Confirmed_Service_Choice_SERVICE_CONFIRMED_SUBSCRIBE_COV,
+        Some(my_property_simple_ack_handler),
+    );
+    // Subscriptions to our own objects, kept (and notified) by the stack
+    handler_cov_init();
+    apdu_set_confirmed_handler(
+        BACnet_Confirmed_Service_Choice_SERVICE_CONFIRMED_SUBSCRIBE_COV,
+        Some(handler_cov_subscribe),
+    );
+    apdu_set_error_handler(
+        BACnet_Confirmed_Service_Choice_SERVICE_CONFIRMED_SUBSCRIBE_COV,
+        Some(my_error_handler),
+    );
+    apdu_set_confirmed_simple_ack_handler(
+        BACnet_Confirmed_Service_Choice_SERVICE_CONFIRMED_SUBSCRIBE_COV_PROPERTY,
+        Some(my_property_simple_ack_handler),
+    );
+    apdu_set_error_handler(
+        BACnet_Confirmed_Service_Choice_SERVICE_CONFIRMED_SUBSCRIBE_COV_PROPERTY,
+        Some(my_error_handler),
+    );
+
+    // Incoming COV notifications are decoded (and acknowledged) by the COV module
+    apdu_set_confirmed_handler(
+        BACnet_Confirmed_Service_Choice_SERVICE_CONFIRMED_COV_NOTIFICATION,
+        Some(my_ccov_notification_handler),
+    );
+    apdu_set_unconfirmed_handler(
+        BACnet_Unconfirmed_Service_Choice_SERVICE_UNCONFIRMED_COV_NOTIFICATION,
+        Some(my_ucov_notification_handler),
+    );
+
+    apdu_set_complex_error_handler(
+        BACnet_Confirmed_Service_Choice_SERVICE_CONFIRMED_WRITE_PROP_MULTIPLE,
+        Some(my_writepropmultiple_error_handler),
+    );
     apdu_set_abort_handler(Some(my_abort_handler));
     apdu_set_reject_handler(Some(my_reject_handler));
 }
@@ -0,0 +1,500 @@
+//! The BACnet network, driven by a single background thread
+//!
+//! The C stack isn't thread safe and has to be driven forward continually, so one thread owns it:
+//! it initializes the stack, sends the requests, receives every PDU and runs the timers of the
+//! transaction state machine (TSM), which times requests out. Every other part of this library
+//! hands its work to that thread.
+//!
+//! Responses are matched to their request by invoke ID, and passed on to whoever is waiting for
+//! them. Requests that time out are resent from here rather than by the TSM, so every request can
+//! have its own APDU timeout and number of retries (see `RequestOptions`). Unsolicited messages
+//! (I-Am, I-Have and COV notifications nobody subscribed to) go to everyone listening through
+//! `BACnetNetwork::subscribe()`, so they're never lost because some request happened to be ongoing.
+
+use crate::{
+    bbmd,
+    cov::{self, CovNotification},
+    datalink::{self, DatalinkConfig},
+    device,
+    errors::Result,
+    init_service_handlers,
+    router::{self, Route},
+    whohas::IHaveData,
+    whois::IAmDevice,
+    BACnetErr, DeviceId, RequestInvokeId, Response,
+};
+use bacnet_sys::{
+    address_get_by_device, address_init, apdu_retries, apdu_retries_set, apdu_timeout,
+    apdu_timeout_set, bacnet_address_same, bip_cleanup, dlenv_init, npdu_handler,
+    tsm_free_invoke_id, tsm_invoke_id_failed, tsm_invoke_id_free, tsm_timer_milliseconds,
+    tsm_transaction_available, BACNET_ADDRESS, MAX_MPDU,
+};
+use log::{debug, error, trace};
+use once_cell::sync::{Lazy, OnceCell};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+// How long (in milliseconds) the driver waits for a PDU before looking for new work
+const RECEIVE_TIMEOUT: u32 = 10;
+
+// Sends a request, it's called again for every retry
+type SendFn = Box<dyn FnMut(DeviceId) -> Result<RequestInvokeId> + Send>;
+type ReplyFn = Box<dyn FnOnce(Response) + Send>;
+
+// What the driver thread is asked to do
+enum Command {
+    // Send a confirmed request, and pass on the response once it arrives
+    Request {
+        device_id: DeviceId,
+        options: RequestOptions,
+        submitted: Instant,
+        send: SendFn,
+        reply: ReplyFn,
+    },
+    // Run something that uses the stack
+    Run(Box<dyn FnOnce() + Send>),
+}
+
+// A request waiting for its response
+struct Pending {
+    device_id: DeviceId,
+    addr: BACNET_ADDRESS,
+    send: SendFn,
+    reply: ReplyFn,
+    limits: Limits,
+    attempt: u8,
+}
+
+// The APDU timeout (in milliseconds) and number of retries of a request, as the stack takes them
+#[derive(Debug, Clone, Copy)]
+struct Limits {
+    timeout: u16,
+    retries: u8,
+}
+
+impl Limits {
+    // How long the request may take, over all its attempts
+    fn total(&self) -> Duration {
+        Duration::from_millis(self.timeout as u64 * (self.retries as u64 + 1))
+    }
+}
+
+static PENDING: Lazy<Mutex<HashMap<RequestInvokeId, Pending>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+static SUBSCRIBERS: Lazy<Mutex<Vec<Sender<UnsolicitedMessage>>>> = Lazy::new(|| Mutex::new(vec![]));
+
+static NETWORK: OnceCell<BACnetNetwork> = OnceCell::new();
+
+/// A message we received without asking for it (or at least, without waiting for it)
+#[derive(Debug, Clone)]
+pub enum UnsolicitedMessage {
+    IAm(IAmDevice),
+    IHave(IHaveData),
+    /// The networks a router reaches, from an I-Am-Router-To-Network
+    IAmRouter(Vec<Route>),
+    /// A COV notification for a subscription we don't know of
+    CovNotification(CovNotification),
+}
+
+/// How long to wait for the response to a request, and how often to send it before giving up
+///
+/// Whatever isn't set is taken from the server the request is sent to, and otherwise from the
+/// stack's defaults (the `BACNET_APDU_TIMEOUT` and `BACNET_APDU_RETRIES` environment variables,
+/// or 3 seconds and 3 retries).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RequestOptions {
+    apdu_timeout: Option<Duration>,
+    retries: Option<u8>,
+}
+
+impl RequestOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set how long to wait for a response before sending the request again. The stack counts in
+    /// milliseconds, up to a little over a minute.
+    pub fn apdu_timeout(mut self, timeout: Duration) -> Self {
+        self.apdu_timeout = Some(timeout);
+        self
+    }
+
+    /// Set how many times the request is sent again if no response comes in
+    pub fn retries(mut self, retries: u8) -> Self {
+        self.retries = Some(retries);
+        self
+    }
+
+    // Fill in whatever isn't set from `defaults`
+    pub(crate) fn or(self, defaults: RequestOptions) -> Self {
+        Self {
+            apdu_timeout: self.apdu_timeout.or(defaults.apdu_timeout),
+            retries: self.retries.or(defaults.retries),
+        }
+    }
+
+    fn limits(&self, defaults: Limits) -> Limits {
+        Limits {
+            timeout: self.apdu_timeout.map_or(defaults.timeout, |timeout| {
+                timeout.as_millis().min(u16::MAX as u128) as u16
+            }),
+            retries: self.retries.unwrap_or(defaults.retries),
+        }
+    }
+}
+
+/// A handle on the network
+///
+/// There's a single network per process, as the stack has a single socket and keeps its state in
+/// globals. The network (along with the stack) is started by `start()`, or on first use with the
+/// datalink configured from the `BACNET_*` environment variables.
+#[derive(Debug)]
+pub struct BACnetNetwork {
+    commands: Mutex<Sender<Command>>,
+}
+
+impl BACnetNetwork {
+    /// Start the network on a datalink configured by `config`
+    ///
+    /// This has to happen before anything else uses the network, otherwise it has already been
+    /// started and `BACnetErr::AlreadyStarted` is returned.
+    pub fn start(config: DatalinkConfig) -> Result<&'static BACnetNetwork> {
+        let mut started = false;
+        let network = NETWORK.get_or_try_init(|| {
+            started = true;
+            Self::spawn(Some(config))
+        })?;
+        if started {
+            Ok(network)
+        } else {
+            Err(BACnetErr::AlreadyStarted)
+        }
+    }
+
+    /// The network, starting it if that hasn't been done yet. Fails if it can't be started, the
+    /// datalink or the local device's identity not being taken, say.
+    pub fn get() -> Result<&'static BACnetNetwork> {
+        NETWORK.get_or_try_init(|| Self::spawn(None))
+    }
+
+    // Start the driver thread, and wait for it to initialize the stack
+    fn spawn(config: Option<DatalinkConfig>) -> Result<Self> {
+        let (tx, rx) = mpsc::channel();
+        let (ready_tx, ready_rx) = mpsc::channel();
+        thread::Builder::new()
+            .name("bacnet-driver".to_string())
+            .spawn(move || drive(config, rx, ready_tx))
+            .map_err(|_| BACnetErr::DriverStopped)?;
+        ready_rx.recv().map_err(|_| BACnetErr::DriverStopped)??;
+        Ok(BACnetNetwork {
+            commands: Mutex::new(tx),
+        })
+    }
+
+    /// Receive every unsolicited message that comes in from now on
+    ///
+    /// Messages are kept until they're read, so the receiver should be read from (or dropped).
+    pub fn subscribe(&self) -> Receiver<UnsolicitedMessage> {
+        let (tx, rx) = mpsc::channel();
+        SUBSCRIBERS.lock().unwrap().push(tx);
+        rx
+    }
+
+    // Run `f` on the driver thread and wait for its result
+    pub(crate) fn run<T: Send + 'static>(
+        &self,
+        f: impl FnOnce() -> T + Send + 'static,
+    ) -> Result<T> {
+        let (tx, rx) = mpsc::channel();
+        self.submit(Command::Run(Box::new(move || {
+            let _ = tx.send(f());
+        })))?;
+        rx.recv().map_err(|_| BACnetErr::DriverStopped)
+    }
+
+    // Run `f` on the driver thread, without waiting for it
+    pub(crate) fn run_detached(&self, f: impl FnOnce() + Send + 'static) -> Result<()> {
+        self.submit(Command::Run(Box::new(f)))
+    }
+
+    // Have the driver send a request, and wait for the response
+    pub(crate) fn request(
+        &self,
+        device_id: DeviceId,
+        options: RequestOptions,
+        send: impl FnMut(DeviceId) -> Result<RequestInvokeId> + Send + 'static,
+    ) -> Result<Response> {
+        let (tx, rx) = mpsc::channel();
+        self.request_detached(device_id, options, send, move |response| {
+            let _ = tx.send(response);
+        })?;
+        match rx.recv().map_err(|_| BACnetErr::DriverStopped)? {
+            Response::Error(err) => Err(err),
+            response => Ok(response),
+        }
+    }
+
+    // Like `run()`, without blocking the calling thread
+    #[cfg(feature = "async")]
+    pub(crate) async fn run_async<T: Send + 'static>(
+        &self,
+        f: impl FnOnce() -> T + Send + 'static,
+    ) -> Result<T> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.submit(Command::Run(Box::new(move || {
+            let _ = tx.send(f());
+        })))?;
+        rx.await.map_err(|_| BACnetErr::DriverStopped)
+    }
+
+    // Like `request()`, without blocking the calling thread
+    #[cfg(feature = "async")]
+    pub(crate) async fn request_async(
+        &self,
+        device_id: DeviceId,
+        options: RequestOptions,
+        send: impl FnMut(DeviceId) -> Result<RequestInvokeId> + Send + 'static,
+    ) -> Result<Response> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.request_detached(device_id, options, send, move |response| {
+            let _ = tx.send(response);
+        })?;
+        match rx.await.map_err(|_| BACnetErr::DriverStopped)? {
+            Response::Error(err) => Err(err),
+            response => Ok(response),
+        }
+    }
+
+    // Have the driver send a request without waiting for it, `reply` is called on the driver
+    // thread once the response arrives. Can be used from the driver thread itself.
+    pub(crate) fn request_detached(
+        &self,
+        device_id: DeviceId,
+        options: RequestOptions,
+        send: impl FnMut(DeviceId) -> Result<RequestInvokeId> + Send + 'static,
+        reply: impl FnOnce(Response) + Send + 'static,
+    ) -> Result<()> {
+        self.submit(Command::Request {
+            device_id,
+            options,
+            submitted: Instant::now(),
+            send: Box::new(send),
+            reply: Box::new(reply),
+        })
+    }
+
+    fn submit(&self, command: Command) -> Result<()> {
+        self.commands
+            .lock()
+            .map_err(|_| BACnetErr::CouldntGetLock)?
+            .send(command)
+            .map_err(|_| BACnetErr::DriverStopped)
+    }
+}
+
+// The driver thread
+fn drive(config: Option<DatalinkConfig>, commands: Receiver<Command>, ready: Sender<Result<()>>) {
+    unsafe {
+        bip_cleanup();
+        init_service_handlers();
+        address_init();
+    }
+    let init = device::apply_identity().and_then(|()| match config {
+        Some(config) => config.init(),
+        None => {
+            unsafe { dlenv_init() };
+            Ok(())
+        }
+    });
+    let failed = init.is_err();
+    let _ = ready.send(init);
+    if failed {
+        return;
+    }
+
+    // Requests take their limits from the datalink configuration unless told otherwise. The TSM
+    // itself never retries, that's left to finish_requests(). This also goes for the confirmed
+    // requests the stack sends on its own (confirmed COV notifications), which are sent once.
+    let defaults = unsafe {
+        let defaults = Limits {
+            timeout: apdu_timeout(),
+            retries: apdu_retries(),
+        };
+        apdu_retries_set(0);
+        defaults
+    };
+
+    let mut queue = VecDeque::new();
+    let mut last_tick = Instant::now();
+    loop {
+        queue.extend(commands.try_iter());
+        while let Some(command) = queue.pop_front() {
+            match command {
+                Command::Run(f) => f(),
+                Command::Request {
+                    device_id,
+                    options,
+                    submitted,
+                    send,
+                    reply,
+                } => {
+                    let limits = options.limits(defaults);
+                    // Every invoke ID is in use, so wait for one of the requests to finish
+                    if !unsafe { tsm_transaction_available() } {
+                        if submitted.elapsed() > limits.total() {
+                            reply(Response::Error(BACnetErr::NoInvokeId));
+                            continue;
+                        }
+                        queue.push_front(Command::Request {
+                            device_id,
+                            options,
+                            submitted,
+                            send,
+                            reply,
+                        });
+                        break;
+                    }
+                    start_request(device_id, limits, send, reply);
+                }
+            }
+        }
+
+        receive_pdu(RECEIVE_TIMEOUT);
+
+        // The TSM fails requests that weren't answered in time
+        let elapsed = last_tick.elapsed().as_millis().min(u16::MAX as u128) as u16;
+        last_tick += Duration::from_millis(elapsed as u64);
+        unsafe { tsm_timer_milliseconds(elapsed) };
+        finish_requests();
+        bbmd::maintain();
+        cov::maintain();
+        device::maintain();
+    }
+}
+
+// Wait up to `timeout` milliseconds for a PDU, and hand it to the stack if one arrives.
+fn receive_pdu(timeout: u32) {
+    let mut src = BACNET_ADDRESS::default();
+    let mut rx_buf = [0u8; MAX_MPDU as usize];
+    let pdu_len = datalink::receive(&mut src, &mut rx_buf, timeout);
+    if pdu_len > 0 {
+        router::receive(&src, &mut rx_buf[..pdu_len as usize]);
+        unsafe { npdu_handler(&mut src, &mut rx_buf as *mut _, pdu_len) }
+    }
+}
+
+fn start_request(device_id: DeviceId, limits: Limits, send: SendFn, reply: ReplyFn) {
+    let mut max_apdu = 0;
+    let mut addr = BACNET_ADDRESS::default();
+    if !unsafe { address_get_by_device(device_id, &mut max_apdu, &mut addr) } {
+        reply(Response::Error(BACnetErr::NotConnected { device_id }));
+        return;
+    }
+
+    send_request(Pending {
+        device_id,
+        addr,
+        send,
+        reply,
+        limits,
+        attempt: 0,
+    });
+}
+
+// Send a request (again), the TSM times it out using the APDU timeout at the time it's sent.
+// Everything else the stack sends keeps the default.
+fn send_request(mut request: Pending) {
+    let default_timeout = unsafe { apdu_timeout() };
+    unsafe { apdu_timeout_set(request.limits.timeout) };
+    let sent = (request.send)(request.device_id);
+    unsafe { apdu_timeout_set(default_timeout) };
+    match sent {
+        Ok(0) => (request.reply)(Response::Error(BACnetErr::SendFailed)),
+        Ok(invoke_id) => {
+            trace!(
+                "sent request {} to device {} (attempt {})",
+                invoke_id,
+                request.device_id,
+                request.attempt + 1
+            );
+            PENDING.lock().unwrap().insert(invoke_id, request);
+        }
+        Err(err) => (request.reply)(Response::Error(err)),
+    }
+}
+
+// Retry (or fail) the requests the TSM timed out, and fail those that were finished without a
+// response we understood (like a segmented one)
+fn finish_requests() {
+    let finished = {
+        let mut pending = PENDING.lock().unwrap();
+        let invoke_ids = pending
+            .keys()
+            .copied()
+            .filter(|&invoke_id| unsafe {
+                tsm_invoke_id_failed(invoke_id) || tsm_invoke_id_free(invoke_id)
+            })
+            .collect::<Vec<_>>();
+        invoke_ids
+            .into_iter()
+            .map(|invoke_id| (invoke_id, pending.remove(&invoke_id).unwrap()))
+            .collect::<Vec<_>>()
+    };
+
+    for (invoke_id, mut request) in finished {
+        let err = if unsafe { tsm_invoke_id_failed(invoke_id) } {
+            unsafe { tsm_free_invoke_id(invoke_id) };
+            // A retry is a new transaction, so it gets a new invoke ID
+            if request.attempt < request.limits.retries {
+                debug!("request {} timed out, retrying", invoke_id);
+                request.attempt += 1;
+                send_request(request);
+                continue;
+            }
+            BACnetErr::NoResponse {
+                retries: request.limits.retries,
+            }
+        } else {
+            BACnetErr::NoValue
+        };
+        debug!("request {} failed: {}", invoke_id, err);
+        (request.reply)(Response::Error(err));
+    }
+}
+
+// Pass the response to a request on to whoever made the request
+pub(crate) fn complete_request(
+    src: *mut BACNET_ADDRESS,
+    invoke_id: RequestInvokeId,
+    response: Response,
+) {
+    let mut pending = PENDING.lock().unwrap();
+    let is_match = match pending.get_mut(&invoke_id) {
+        Some(request) => unsafe { bacnet_address_same(&mut request.addr, src) },
+        None => false,
+    };
+    if !is_match {
+        error!("Request wasn't matched! {:?} ({})", src, invoke_id);
+        return;
+    }
+
+    let request = pending.remove(&invoke_id).unwrap();
+    (request.reply)(response);
+}
+
+// Pass an unsolicited message on to everyone listening
+pub(crate) fn publish(message: UnsolicitedMessage) {
+    // Subscribers that are gone are dropped along the way
+    SUBSCRIBERS
+        .lock()
+        .unwrap()
+        .retain(|subscriber| subscriber.send(message.clone()).is_ok());
+}
@@ -0,0 +1,358 @@
+//! ReadRange, for reading (a part of) a list property, like the log-buffer of a Trend Log
+//!
+//! The stack decodes the acknowledgement up to the item data, which holds the encoded
+//! BACnetLogRecords. Those we decode ourselves.
+
+use crate::{
+    encoding::{
+        decode_bit_string, decode_real, decode_signed, decode_unsigned, join_date_time, Tag,
+        TagKind, TagReader,
+    },
+    errors::Result,
+    value::{BACnetValue, DateTime},
+    BACnetErr,
+};
+use bacnet_sys::{
+    bitstring_bit, BACNET_READ_RANGE_DATA, RR_BY_POSITION, RR_BY_SEQUENCE, RR_BY_TIME, RR_READ_ALL,
+};
+
+// Bits of the result flags
+const RESULT_FIRST_ITEM: u8 = 0;
+const RESULT_LAST_ITEM: u8 = 1;
+const RESULT_MORE_ITEMS: u8 = 2;
+
+/// The range of items to read
+///
+/// A negative `count` reads backwards from the reference item.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReadRange {
+    /// Items by their position in the list, starting at 1
+    ByPosition { index: u32, count: i32 },
+    /// Items by their sequence number
+    BySequence { sequence: u32, count: i32 },
+    /// Items logged after (or before, with a negative count) the given time
+    ByTime { time: DateTime, count: i32 },
+    /// The whole list
+    All,
+}
+
+/// The datum of a log record
+#[derive(Debug, Clone, PartialEq)]
+pub enum LogDatum {
+    /// A change in the status of the log: log-disabled, buffer-purged and log-interrupted
+    LogStatus(Vec<bool>),
+    /// A logged value (boolean, real, enumerated, unsigned, signed, bitstring or null)
+    Value(BACnetValue),
+    /// Reading the monitored property failed
+    Failure { error_class: u32, error_code: u32 },
+    /// The clock was changed, by this many seconds
+    TimeChange(f32),
+    /// A value of any other type, as encoded on the wire
+    Any(Vec<u8>),
+}
+
+/// A single record from the log-buffer of a Trend Log
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogRecord {
+    pub timestamp: DateTime,
+    pub datum: LogDatum,
+    pub status_flags: Option<Vec<bool>>,
+}
+
+/// The result of a ReadRange request
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadRangeResult {
+    /// The first item of the list is included
+    pub first_item: bool,
+    /// The last item of the list is included
+    pub last_item: bool,
+    /// More items matched the range than could be returned
+    pub more_items: bool,
+    pub item_count: u32,
+    /// The sequence number of the first record (not returned when reading by position)
+    pub first_sequence: Option<u32>,
+    pub records: Vec<LogRecord>,
+}
+
+impl ReadRange {
+    // Fill in the range of the request
+    pub(crate) fn apply(&self, data: &mut BACNET_READ_RANGE_DATA) {
+        match *self {
+            ReadRange::ByPosition { index, count } => {
+                data.RequestType = RR_BY_POSITION as i32;
+                data.Range.RefIndex = index;
+                data.Count = count;
+            }
+            ReadRange::BySequence { sequence, count } => {
+                data.RequestType = RR_BY_SEQUENCE as i32;
+                data.Range.RefSeqNum = sequence;
+                data.Count = count;
+            }
+            ReadRange::ByTime { time, count } => {
+                data.RequestType = RR_BY_TIME as i32;
+                data.Range.RefTime = time.into();
+                data.Count = count;
+            }
+            ReadRange::All => {
+                data.RequestType = RR_READ_ALL as i32;
+            }
+        }
+    }
+}
+
+/// Decode a ReadRange acknowledgement `apdu`, as decoded by `rr_ack_decode_service_request`
+pub(crate) fn decode_read_range_ack(
+    apdu: &[u8],
+    data: &mut BACNET_READ_RANGE_DATA,
+) -> Result<ReadRangeResult> {
+    let item_data = if data.application_data.is_null() || data.application_data_len <= 0 {
+        &[][..]
+    } else {
+        unsafe {
+            std::slice::from_raw_parts(data.application_data, data.application_data_len as usize)
+        }
+    };
+
+    Ok(ReadRangeResult {
+        first_item: unsafe { bitstring_bit(&mut data.ResultFlags, RESULT_FIRST_ITEM) },
+        last_item: unsafe { bitstring_bit(&mut data.ResultFlags, RESULT_LAST_ITEM) },
+        more_items: unsafe { bitstring_bit(&mut data.ResultFlags, RESULT_MORE_ITEMS) },
+        item_count: data.ItemCount,
+        first_sequence: if has_first_sequence(apdu)? {
+            Some(data.FirstSequence)
+        } else {
+            None
+        },
+        records: decode_log_records(item_data)?,
+    })
+}
+
+// The first sequence number [6] follows the item data [5], but only when reading by sequence
+// number or time. The stack doesn't tell whether it found one, so we look for it ourselves.
+fn has_first_sequence(apdu: &[u8]) -> Result<bool> {
+    let mut reader = TagReader::new(apdu);
+    // Everything before the item data is primitive
+    while reader.peek_tag()?.kind != TagKind::Opening {
+        reader.read_primitive()?;
+    }
+    reader.expect_opening(5)?;
+    reader.read_constructed(5)?;
+    Ok(matches!(
+        reader.peek_tag(),
+        Ok(Tag {
+            number: 6,
+            context: true,
+            kind: TagKind::Length(_),
+        })
+    ))
+}
+
+// BACnetLogRecord ::= SEQUENCE {
+//     timestamp    [0] BACnetDateTime,
+//     logDatum     [1] CHOICE { ... },
+//     statusFlags  [2] BACnetStatusFlags OPTIONAL
+// }
+fn decode_log_records(data: &[u8]) -> Result<Vec<LogRecord>> {
+    let mut reader = TagReader::new(data);
+    let mut records = vec![];
+    while !reader.is_empty() {
+        reader.expect_opening(0)?;
+        let timestamp = decode_timestamp(&mut reader)?;
+        reader.expect_closing(0)?;
+
+        reader.expect_opening(1)?;
+        let datum = decode_log_datum(&mut reader)?;
+        reader.expect_closing(1)?;
+
+        let status_flags = match reader.peek_tag() {
+            Ok(tag) if tag.context && tag.number == 2 && tag.kind != TagKind::Opening => {
+                let (_, content) = reader.read_primitive()?;
+                Some(decode_bit_string(content)?)
+            }
+            _ => None,
+        };
+
+        records.push(LogRecord {
+            timestamp,
+            datum,
+            status_flags,
+        });
+    }
+    Ok(records)
+}
+
+// BACnetDateTime is an application tagged date followed by an application tagged time
+fn decode_timestamp(reader: &mut TagReader) -> Result<DateTime> {
+    let date = reader.read_application_value()?;
+    let time = reader.read_application_value()?;
+    join_date_time(&date, &time).ok_or(BACnetErr::DecodeFailed)
+}
+
+fn decode_log_datum(reader: &mut TagReader) -> Result<LogDatum> {
+    let tag = reader.peek_tag()?;
+    if !tag.context {
+        return Err(BACnetErr::DecodeFailed);
+    }
+
+    // The constructed choices
+    if tag.kind == TagKind::Opening {
+        reader.read_tag()?;
+        return match tag.number {
+            // failure [8] BACnetError, an application tagged error class and code
+            8 => {
+                let error_class = reader.read_application_value()?;
+                let error_code = reader.read_application_value()?;
+                reader.expect_closing(8)?;
+                match (error_class, error_code) {
+                    (BACnetValue::Enum(error_class, _), BACnetValue::Enum(error_code, _)) => {
+                        Ok(LogDatum::Failure {
+                            error_class,
+                            error_code,
+                        })
+                    }
+                    _ => Err(BACnetErr::DecodeFailed),
+                }
+            }
+            // any-value [10] ABSTRACT-SYNTAX.&Type
+            10 => Ok(LogDatum::Any(reader.read_constructed(10)?.to_vec())),
+            _ => Err(BACnetErr::DecodeFailed),
+        };
+    }
+
+    let (tag, content) = reader.read_primitive()?;
+    Ok(match tag.number {
+        0 => LogDatum::LogStatus(decode_bit_string(content)?),
+        1 => LogDatum::Value(BACnetValue::Bool(decode_unsigned(content) != 0)),
+        2 => LogDatum::Value(BACnetValue::Real(decode_real(content)?)),
+        3 => LogDatum::Value(BACnetValue::Enum(decode_unsigned(content) as u32, None)),
+        4 => LogDatum::Value(BACnetValue::Uint(decode_unsigned(content))),
+        5 => LogDatum::Value(BACnetValue::Int(decode_signed(content))),
+        6 => LogDatum::Value(BACnetValue::BitString(decode_bit_string(content)?)),
+        7 => LogDatum::Value(BACnetValue::Null),
+        9 => LogDatum::TimeChange(decode_real(content)?),
+        _ => return Err(BACnetErr::DecodeFailed),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2024-02-29 (a Thursday) 23:59:00.00
+    const TIMESTAMP: [u8; 12] = [
+        0x0E, 0xA4, 0x7C, 0x02, 0x1D, 0x04, 0xB4, 0x17, 0x3B, 0x00, 0x00, 0x0F,
+    ];
+
+    fn timestamp() -> DateTime {
+        DateTime {
+            year: 2024,
+            month: 2,
+            day: 29,
+            weekday: 4,
+            hour: 23,
+            minute: 59,
+            second: 0,
+            hundredths: 0,
+        }
+    }
+
+    fn record(datum: &[u8], status_flags: &[u8]) -> Vec<u8> {
+        [&TIMESTAMP[..], &[0x1E], datum, &[0x1F], status_flags].concat()
+    }
+
+    // A ReadRange-ACK of a Trend Log's log-buffer holding `item_data`
+    fn ack(item_data: &[u8], first_sequence: &[u8]) -> Vec<u8> {
+        [
+            &[
+                0x0C, 0x05, 0x00, 0x00, 0x01, 0x19, 0x83, 0x3A, 0x05, 0xC0, 0x49, 0x01, 0x5E,
+            ][..],
+            item_data,
+            &[0x5F],
+            first_sequence,
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn value_with_status_flags() {
+        // real 21.5, in alarm
+        let data = record(&[0x2C, 0x41, 0xAC, 0x00, 0x00], &[0x2A, 0x04, 0x80]);
+        assert_eq!(
+            decode_log_records(&data).unwrap(),
+            vec![LogRecord {
+                timestamp: timestamp(),
+                datum: LogDatum::Value(BACnetValue::Real(21.5)),
+                status_flags: Some(vec![true, false, false, false]),
+            }]
+        );
+    }
+
+    #[test]
+    fn log_status() {
+        // buffer-purged
+        let data = record(&[0x0A, 0x05, 0x40], &[]);
+        assert_eq!(
+            decode_log_records(&data).unwrap(),
+            vec![LogRecord {
+                timestamp: timestamp(),
+                datum: LogDatum::LogStatus(vec![false, true, false]),
+                status_flags: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn failure() {
+        // property, unknown-property
+        let data = record(&[0x8E, 0x91, 0x02, 0x91, 0x20, 0x8F], &[]);
+        assert_eq!(
+            decode_log_records(&data).unwrap(),
+            vec![LogRecord {
+                timestamp: timestamp(),
+                datum: LogDatum::Failure {
+                    error_class: 2,
+                    error_code: 32,
+                },
+                status_flags: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn truncated_records() {
+        let data = record(&[0x2C, 0x41, 0xAC, 0x00, 0x00], &[0x2A, 0x04, 0x80]);
+        // Cut right before the status flags, the record is complete without them
+        let complete = data.len() - 3;
+        for len in (1..data.len()).filter(|&len| len != complete) {
+            assert!(
+                matches!(
+                    decode_log_records(&data[..len]),
+                    Err(BACnetErr::DecodeFailed)
+                ),
+                "{} bytes",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn first_sequence() {
+        let item_data = record(&[0x0A, 0x05, 0x40], &[]);
+        assert!(has_first_sequence(&ack(&item_data, &[0x69, 0x07])).unwrap());
+        assert!(!has_first_sequence(&ack(&item_data, &[])).unwrap());
+        assert!(!has_first_sequence(&ack(&[], &[])).unwrap());
+
+        // Cut anywhere before the end of the item data
+        let data = ack(&item_data, &[]);
+        for len in 0..data.len() {
+            assert!(
+                matches!(
+                    has_first_sequence(&data[..len]),
+                    Err(BACnetErr::DecodeFailed)
+                ),
+                "{} bytes",
+                len
+            );
+        }
+    }
+}
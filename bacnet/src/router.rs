@@ -0,0 +1,312 @@
+//! Network layer routing: finding the routers to remote networks
+//!
+//! Routers answer a Who-Is-Router-To-Network with I-Am-Router-To-Network, listing the networks
+//! they can reach. Every I-Am-Router-To-Network that comes in, asked for or not, ends up in the
+//! routing table. A server built with a `dnet` but without an address of its own is reached
+//! through the router to that network, looked up when it's connected.
+
+// The stack doesn't handle network layer messages unless it's built as a router (BAC_ROUTING),
+// so we send Who-Is-Router-To-Network ourselves and pick I-Am-Router-To-Network out of the PDUs
+// before they're handed to the stack, which drops them.
+
+use crate::{
+    datalink,
+    errors::Result,
+    network::{publish, UnsolicitedMessage},
+    BACnetErr, BACnetNetwork,
+};
+use bacnet_sys::{
+    npdu_decode, npdu_encode_npdu_data, npdu_encode_pdu, BACNET_ADDRESS,
+    BACNET_MESSAGE_PRIORITY_MESSAGE_PRIORITY_NORMAL,
+    BACNET_NETWORK_MESSAGE_TYPE_NETWORK_MESSAGE_I_AM_ROUTER_TO_NETWORK,
+    BACNET_NETWORK_MESSAGE_TYPE_NETWORK_MESSAGE_WHO_IS_ROUTER_TO_NETWORK, BACNET_NPDU_DATA,
+    MAX_MAC_LEN, MAX_MPDU,
+};
+use log::{debug, trace, warn};
+use once_cell::sync::Lazy;
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+// How long to wait for a router when connecting to a server on a network we know no route to
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(3);
+
+// Network number -> the router to it
+static ROUTES: Lazy<Mutex<HashMap<u16, MacAddress>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// A MAC address of any datalink, up to 7 bytes: 6 for BACnet/IP and Ethernet, 3 for a
+/// BACnet/IPv6 VMAC, 1 for MS/TP
+///
+/// Also used for the address of a device on a remote network (DADR), which is a MAC address on
+/// that network.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct MacAddress {
+    len: u8,
+    mac: [u8; MAX_MAC_LEN as usize],
+}
+
+impl MacAddress {
+    pub fn new(mac: &[u8]) -> Result<Self> {
+        if mac.len() > MAX_MAC_LEN as usize {
+            return Err(BACnetErr::InvalidAddress { len: mac.len() });
+        }
+        let mut address = Self {
+            len: mac.len() as u8,
+            ..Self::default()
+        };
+        address.mac[..mac.len()].copy_from_slice(mac);
+        Ok(address)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.mac[..self.len as usize]
+    }
+
+    // The MAC address `addr` was received from, on our own network
+    pub(crate) fn from_mac(addr: &BACNET_ADDRESS) -> Self {
+        Self {
+            len: addr.mac_len.min(MAX_MAC_LEN as u8),
+            mac: addr.mac,
+        }
+    }
+
+    // The address of a device on a remote network `addr` was received from (empty for our own
+    // network)
+    pub(crate) fn from_adr(addr: &BACNET_ADDRESS) -> Self {
+        Self {
+            len: addr.len.min(MAX_MAC_LEN as u8),
+            mac: addr.adr,
+        }
+    }
+
+    pub(crate) fn set_mac(&self, addr: &mut BACNET_ADDRESS) {
+        addr.mac = self.mac;
+        addr.mac_len = self.len;
+    }
+
+    pub(crate) fn set_adr(&self, addr: &mut BACNET_ADDRESS) {
+        addr.adr = self.mac;
+        addr.len = self.len;
+    }
+}
+
+impl From<u8> for MacAddress {
+    fn from(mac: u8) -> Self {
+        Self::new(&[mac]).unwrap()
+    }
+}
+
+impl From<[u8; 6]> for MacAddress {
+    fn from(mac: [u8; 6]) -> Self {
+        Self::new(&mac).unwrap()
+    }
+}
+
+impl fmt::Display for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, byte) in self.as_bytes().iter().enumerate() {
+            if i > 0 {
+                write!(f, ":")?;
+            }
+            write!(f, "{:02X}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// A remote network, and the router on our network to reach it through
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Route {
+    pub network: u16,
+    pub router: MacAddress,
+}
+
+pub struct WhoIsRouter {
+    /// How long to wait for I-Am-Router-To-Network answers
+    timeout: Duration,
+
+    /// Only ask for the router to this network, default is `None` which asks every router for
+    /// all the networks it can reach
+    network: Option<u16>,
+}
+
+// WhoIsRouter::new().network(5).execute()
+impl WhoIsRouter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the amount of time to wait for routers to answer. Default: 3 seconds
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn network<N>(mut self, network: N) -> Self
+    where
+        N: Into<Option<u16>>,
+    {
+        self.network = network.into();
+        self
+    }
+
+    /// Asks the routers on our network, and returns the routes they answered with. These are
+    /// added to the routing table as well.
+    pub fn execute(self) -> Result<Vec<Route>> {
+        let network = BACnetNetwork::get()?;
+        // Listen before asking, so no answer is missed
+        let messages = network.subscribe();
+        let asked_for = self.network;
+        network.run(move || send_who_is_router(asked_for))?;
+
+        let start = Instant::now();
+        let mut routes = vec![];
+        while let Some(time_left) = self.timeout.checked_sub(start.elapsed()) {
+            match messages.recv_timeout(time_left) {
+                Ok(UnsolicitedMessage::IAmRouter(announced)) => routes.extend(
+                    announced
+                        .into_iter()
+                        .filter(|route| asked_for.is_none_or(|net| net == route.network)),
+                ),
+                Ok(_) => {}
+                Err(_) => break,
+            }
+        }
+        trace!("Got {} routes", routes.len());
+        Ok(routes)
+    }
+}
+
+impl Default for WhoIsRouter {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(3),
+            network: None,
+        }
+    }
+}
+
+/// The routes learned so far, ordered by network number
+pub fn routing_table() -> Vec<Route> {
+    let mut routes: Vec<_> = ROUTES
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(&network, &router)| Route { network, router })
+        .collect();
+    routes.sort_by_key(|route| route.network);
+    routes
+}
+
+// The router to `network`, from the routing table
+pub(crate) fn lookup(network: u16) -> Result<MacAddress> {
+    ROUTES
+        .lock()
+        .unwrap()
+        .get(&network)
+        .copied()
+        .ok_or(BACnetErr::NoRoute { network })
+}
+
+// The router to `network`, asking the routers on our network if it isn't known yet
+pub(crate) fn resolve(network: u16) -> Result<MacAddress> {
+    if let Ok(router) = lookup(network) {
+        return Ok(router);
+    }
+    let driver = BACnetNetwork::get()?;
+    let messages = driver.subscribe();
+    driver.run(move || send_who_is_router(Some(network)))?;
+
+    let start = Instant::now();
+    while let Some(time_left) = DISCOVERY_TIMEOUT.checked_sub(start.elapsed()) {
+        match messages.recv_timeout(time_left) {
+            Ok(UnsolicitedMessage::IAmRouter(routes)) => {
+                if let Some(route) = routes.iter().find(|route| route.network == network) {
+                    return Ok(route.router);
+                }
+            }
+            Ok(_) => {}
+            Err(_) => break,
+        }
+    }
+    Err(BACnetErr::NoRoute { network })
+}
+
+// Broadcast a Who-Is-Router-To-Network on our own network. Must run on the driver thread.
+fn send_who_is_router(network: Option<u16>) {
+    let mut dest = BACNET_ADDRESS::default();
+    datalink::get_broadcast_address(&mut dest);
+    // Only the routers on our network should answer
+    dest.net = 0;
+    dest.len = 0;
+    let mut my_address = BACNET_ADDRESS::default();
+    datalink::get_my_address(&mut my_address);
+
+    let mut npdu_data = BACNET_NPDU_DATA::default();
+    unsafe {
+        npdu_encode_npdu_data(
+            &mut npdu_data,
+            false,
+            BACNET_MESSAGE_PRIORITY_MESSAGE_PRIORITY_NORMAL,
+        )
+    };
+    npdu_data.network_layer_message = true;
+    npdu_data.network_message_type =
+        BACNET_NETWORK_MESSAGE_TYPE_NETWORK_MESSAGE_WHO_IS_ROUTER_TO_NETWORK;
+
+    let mut pdu = [0u8; MAX_MPDU as usize];
+    let mut pdu_len =
+        unsafe { npdu_encode_pdu(pdu.as_mut_ptr(), &mut dest, &mut my_address, &mut npdu_data) }
+            as usize;
+    if let Some(network) = network {
+        pdu[pdu_len..pdu_len + 2].copy_from_slice(&network.to_be_bytes());
+        pdu_len += 2;
+    }
+    if datalink::send_pdu(&mut dest, &mut npdu_data, &mut pdu[..pdu_len]) <= 0 {
+        warn!("failed to send Who-Is-Router-To-Network");
+    }
+}
+
+// Look at a PDU before the stack gets it, to learn the routes from I-Am-Router-To-Network
+pub(crate) fn receive(src: &BACNET_ADDRESS, pdu: &mut [u8]) {
+    let mut npdu_dest = BACNET_ADDRESS::default();
+    let mut npdu_src = BACNET_ADDRESS::default();
+    let mut npdu_data = BACNET_NPDU_DATA::default();
+    let len = unsafe {
+        npdu_decode(
+            pdu.as_mut_ptr(),
+            &mut npdu_dest,
+            &mut npdu_src,
+            &mut npdu_data,
+        )
+    };
+    if len <= 0
+        || !npdu_data.network_layer_message
+        || npdu_data.network_message_type
+            != BACNET_NETWORK_MESSAGE_TYPE_NETWORK_MESSAGE_I_AM_ROUTER_TO_NETWORK
+    {
+        return;
+    }
+
+    let router = MacAddress::from_mac(src);
+    let routes: Vec<_> = pdu
+        .get(len as usize..)
+        .unwrap_or_default()
+        .chunks_exact(2)
+        .map(|network| Route {
+            network: u16::from_be_bytes([network[0], network[1]]),
+            router,
+        })
+        .collect();
+    debug!("router {} reaches {:?}", router, routes);
+
+    let mut table = ROUTES.lock().unwrap();
+    for route in &routes {
+        table.insert(route.network, route.router);
+    }
+    drop(table);
+    publish(UnsolicitedMessage::IAmRouter(routes));
+}
@@ -11,6 +11,12 @@ pub enum BACnetValue {
     Real(f32),
     Double(f64),
     String(String), // BACNET_CHARACTER_STRING
+    // A BACNET_CHARACTER_STRING in a character set other than UTF-8, which is kept so the string
+    // is written back the way it was read
+    EncodedString {
+        value: String,
+        charset: CharacterSet,
+    },
     Bytes(Vec<u8>), // BACNET_OCTET_STRING
     BitString(Vec<bool>),
     Date {
@@ -19,6 +25,13 @@ pub enum BACnetValue {
         day: u8,
         weekday: u8,
     },
+    Time {
+        hour: u8,
+        minute: u8,
+        second: u8,
+        hundredths: u8,
+    },
+    DateTime(DateTime), // A date directly followed by a time (BACnetDateTime)
     Enum(u32, Option<String>), // Enumerated values also have string representations...
     // A reference to an object, used during interrogation of the device (object-list)
     ObjectId {
@@ -26,6 +39,92 @@ pub enum BACnetValue {
         object_instance: u32,
     },
     Array(Vec<BACnetValue>),
+    // The elements of a single structured value, in order
+    Sequence(Vec<BACnetValue>),
+    // A context tagged constructed value (the values between an opening and a closing tag)
+    Constructed {
+        tag: u8,
+        values: Vec<BACnetValue>,
+    },
+    // A context tagged primitive value. Its type depends on where it's used, so we keep the raw
+    // content.
+    Context {
+        tag: u8,
+        data: Vec<u8>,
+    },
+}
+
+/// The character sets of BACnet character strings
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CharacterSet {
+    Utf8,                    // ANSI X3.4, which has been UTF-8 since BACnet 2008
+    Dbcs { code_page: u16 }, // IBM/Microsoft DBCS, with the code page (932, 936, 949 or 950)
+    Jis,                     // JIS X 0208
+    Ucs4,                    // ISO 10646 UCS-4
+    Ucs2,                    // ISO 10646 UCS-2
+    Latin1,                  // ISO 8859-1
+}
+
+/// Marks a field of a date or time as unspecified, matching any value
+pub const WILDCARD: u8 = 0xFF;
+
+/// The year of a date with an unspecified year (1900 + `WILDCARD`)
+pub const YEAR_WILDCARD: u16 = 1900 + WILDCARD as u16;
+
+/// A date and time (BACnetDateTime)
+///
+/// Like `BACnetValue::Date` and `BACnetValue::Time`, any field can be a wildcard. Next to that a
+/// month can be 13 (odd months) or 14 (even months), and a day can be 32 (the last day of the
+/// month), 33 (odd days) or 34 (even days).
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub weekday: u8, // 1 is Monday, 7 is Sunday
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub hundredths: u8,
+}
+
+impl DateTime {
+    /// Whether any of the fields is unspecified (or one of the special months and days)
+    pub fn is_wildcard(&self) -> bool {
+        is_date_wildcard(self.year, self.month, self.day, self.weekday)
+            || is_time_wildcard(self.hour, self.minute, self.second, self.hundredths)
+    }
+}
+
+impl BACnetValue {
+    /// Whether this is a date and/or time with unspecified fields (or one of the special months
+    /// and days), which can't be taken as a single point in time
+    pub fn is_wildcard(&self) -> bool {
+        match *self {
+            BACnetValue::Date {
+                year,
+                month,
+                day,
+                weekday,
+            } => is_date_wildcard(year, month, day, weekday),
+            BACnetValue::Time {
+                hour,
+                minute,
+                second,
+                hundredths,
+            } => is_time_wildcard(hour, minute, second, hundredths),
+            BACnetValue::DateTime(dt) => dt.is_wildcard(),
+            _ => false,
+        }
+    }
+}
+
+fn is_date_wildcard(year: u16, month: u8, day: u8, weekday: u8) -> bool {
+    year == YEAR_WILDCARD || month > 12 || day > 31 || weekday == WILDCARD
+}
+
+fn is_time_wildcard(hour: u8, minute: u8, second: u8, hundredths: u8) -> bool {
+    [hour, minute, second, hundredths].contains(&WILDCARD)
 }
 
 impl TryInto<String> for BACnetValue {
@@ -33,6 +132,7 @@ impl TryInto<String> for BACnetValue {
     fn try_into(self) -> Result<String, Self::Error> {
         Ok(match self {
             BACnetValue::String(s) => s,
+            BACnetValue::EncodedString { value, .. } => value,
             BACnetValue::Enum(_, Some(s)) => s,
             BACnetValue::Enum(i, None) => format!("{}", i),
             _ => return Err(BACnetErr::EncodeFailed),
@@ -50,6 +150,8 @@ impl TryInto<u64> for BACnetValue {
     }
 }
 
+/// Parses booleans and numbers, anything else is a string. Writing a string to an enumerated
+/// property writes the value it names, so "active" or "degrees-celsius" can be written as well.
 impl From<String> for BACnetValue {
     fn from(raw: String) -> Self {
         if let Ok(value) = raw.parse::<bool>() {
@@ -75,3 +177,119 @@ impl From<String> for BACnetValue {
         BACnetValue::String(raw)
     }
 }
+
+// Conversions to and from chrono, which fail for dates and times with wildcards
+#[cfg(feature = "chrono")]
+mod chrono_conversions {
+    use super::{BACnetValue, DateTime};
+    use crate::errors::BACnetErr;
+    use chrono::{Datelike, NaiveDate, NaiveDateTime, NaiveTime, Timelike};
+
+    fn to_date(year: u16, month: u8, day: u8) -> Result<NaiveDate, BACnetErr> {
+        NaiveDate::from_ymd_opt(year as i32, month as u32, day as u32)
+            .ok_or(BACnetErr::InvalidValue)
+    }
+
+    fn to_time(hour: u8, minute: u8, second: u8, hundredths: u8) -> Result<NaiveTime, BACnetErr> {
+        NaiveTime::from_hms_milli_opt(
+            hour as u32,
+            minute as u32,
+            second as u32,
+            hundredths as u32 * 10,
+        )
+        .ok_or(BACnetErr::InvalidValue)
+    }
+
+    impl TryFrom<DateTime> for NaiveDateTime {
+        type Error = BACnetErr;
+        fn try_from(dt: DateTime) -> Result<Self, Self::Error> {
+            if dt.is_wildcard() {
+                return Err(BACnetErr::InvalidValue);
+            }
+            Ok(NaiveDateTime::new(
+                to_date(dt.year, dt.month, dt.day)?,
+                to_time(dt.hour, dt.minute, dt.second, dt.hundredths)?,
+            ))
+        }
+    }
+
+    impl From<NaiveDateTime> for DateTime {
+        fn from(dt: NaiveDateTime) -> Self {
+            DateTime {
+                year: dt.year() as u16,
+                month: dt.month() as u8,
+                day: dt.day() as u8,
+                weekday: dt.weekday().number_from_monday() as u8,
+                hour: dt.hour() as u8,
+                minute: dt.minute() as u8,
+                second: dt.second() as u8,
+                hundredths: (dt.nanosecond() % 1_000_000_000 / 10_000_000) as u8,
+            }
+        }
+    }
+
+    impl TryFrom<BACnetValue> for NaiveDate {
+        type Error = BACnetErr;
+        fn try_from(value: BACnetValue) -> Result<Self, Self::Error> {
+            match value {
+                BACnetValue::Date {
+                    year, month, day, ..
+                } if !value.is_wildcard() => to_date(year, month, day),
+                _ => Err(BACnetErr::InvalidValue),
+            }
+        }
+    }
+
+    impl TryFrom<BACnetValue> for NaiveTime {
+        type Error = BACnetErr;
+        fn try_from(value: BACnetValue) -> Result<Self, Self::Error> {
+            match value {
+                BACnetValue::Time {
+                    hour,
+                    minute,
+                    second,
+                    hundredths,
+                } if !value.is_wildcard() => to_time(hour, minute, second, hundredths),
+                _ => Err(BACnetErr::InvalidValue),
+            }
+        }
+    }
+
+    impl TryFrom<BACnetValue> for NaiveDateTime {
+        type Error = BACnetErr;
+        fn try_from(value: BACnetValue) -> Result<Self, Self::Error> {
+            match value {
+                BACnetValue::DateTime(dt) => NaiveDateTime::try_from(dt),
+                _ => Err(BACnetErr::InvalidValue),
+            }
+        }
+    }
+
+    impl From<NaiveDate> for BACnetValue {
+        fn from(date: NaiveDate) -> Self {
+            BACnetValue::Date {
+                year: date.year() as u16,
+                month: date.month() as u8,
+                day: date.day() as u8,
+                weekday: date.weekday().number_from_monday() as u8,
+            }
+        }
+    }
+
+    impl From<NaiveTime> for BACnetValue {
+        fn from(time: NaiveTime) -> Self {
+            BACnetValue::Time {
+                hour: time.hour() as u8,
+                minute: time.minute() as u8,
+                second: time.second() as u8,
+                hundredths: (time.nanosecond() % 1_000_000_000 / 10_000_000) as u8,
+            }
+        }
+    }
+
+    impl From<NaiveDateTime> for BACnetValue {
+        fn from(dt: NaiveDateTime) -> Self {
+            BACnetValue::DateTime(dt.into())
+        }
+    }
+}
@@ -2,39 +2,30 @@
 //!
 //! Design is like a builder with different parameters and returns
 
-// So the design of the BACnet stack is a little annoying in that we have to drive the subsystem
-// forward, continually called bip_receive(). Each device that's discovered is processed by the
-// my_i_have_handler, and we need to a global list of discovered features.
-//
-// In effect, this library is not thread-safe, so we need to make sure that only one WhoHas client
-// is running at a time.
+// Each object that's found is processed by the i_have_handler, which hands it to the network as
+// an unsolicited message. We listen for those for as long as the timeout lasts.
 
 use crate::{
-    cstr,
-    errors::{BACnetErr, Result},
-    init_service_handlers, ObjectType, BACNET_STACK_INIT,
+    cstr, datalink,
+    errors::Result,
+    network::{publish, UnsolicitedMessage},
+    BACnetNetwork, ObjectType,
 };
 use bacnet_sys::{
-    address_init, bactext_object_type_name, bip_cleanup, bip_get_broadcast_address, bip_receive,
-    characterstring_value, dlenv_init, ihave_decode_service_request, npdu_handler,
+    bactext_object_type_name, characterstring_value, ihave_decode_service_request,
     BACnetObjectType_OBJECT_DEVICE, Send_WhoHas_Object, BACNET_ADDRESS, BACNET_I_HAVE_DATA,
-    MAX_MPDU,
 };
 use log::{debug, error, trace};
-use once_cell::sync::Lazy;
-use std::{
-    sync::Mutex,
-    time::{Duration, Instant},
-};
-
-static DISCOVERED_DEVICES: Lazy<Mutex<Vec<IHaveData>>> = Lazy::new(|| Mutex::new(vec![]));
+use std::time::{Duration, Instant};
 
+#[derive(Debug, Clone)]
 pub struct ObjectId {
     pub object_type: ObjectType,
     pub object_instance: u32,
 }
 
 /// A BACnet device that responded with I-Am in response to the Who-Has we sent out.
+#[derive(Debug, Clone)]
 pub struct IHaveData {
     pub device_id: ObjectId,
     pub object_id: ObjectId,
@@ -114,16 +105,7 @@ impl WhoHas {
             subnet,
         } = self;
 
-        // create an object with a Drop impl that calls bip_cleanup
-        whohas(object_type, object_instance, timeout, subnet);
-
-        let devices = if let Ok(mut lock) = DISCOVERED_DEVICES.lock() {
-            lock.drain(..).collect()
-        } else {
-            return Err(BACnetErr::CouldntGetLock);
-        };
-
-        Ok(devices)
+        whohas(object_type, object_instance, timeout, subnet)
     }
 }
 
@@ -164,60 +146,48 @@ pub unsafe extern "C" fn i_have_handler(
         );
     }
 
-    if let Ok(mut lock) = DISCOVERED_DEVICES.lock() {
-        lock.push(data.into());
-    }
+    publish(UnsolicitedMessage::IHave(data.into()));
 }
 
-fn whohas(object_type: ObjectType, object_instance: u32, timeout: Duration, subnet: Option<u16>) {
-    let mut dest = BACNET_ADDRESS::default();
-    let target_object_instance_min = -1i32; // TODO(tj): parameterize?
-    let target_object_instance_max = -1i32; // TODO(tj): parameterize?
+fn whohas(
+    object_type: ObjectType,
+    object_instance: u32,
+    timeout: Duration,
+    subnet: Option<u16>,
+) -> Result<Vec<IHaveData>> {
+    let network = BACnetNetwork::get()?;
+    // Listen before asking, so no answer is missed
+    let messages = network.subscribe();
+    network.run(move || {
+        let mut dest = BACNET_ADDRESS::default();
+        let target_object_instance_min = -1i32; // TODO(tj): parameterize?
+        let target_object_instance_max = -1i32; // TODO(tj): parameterize?
+
+        if let Some(subnet) = subnet {
+            dest.net = subnet;
+        } else {
+            datalink::get_broadcast_address(&mut dest);
+        }
 
-    if let Some(subnet) = subnet {
-        dest.net = subnet;
-    } else {
         unsafe {
-            bip_get_broadcast_address(&mut dest as *mut _);
+            Send_WhoHas_Object(
+                target_object_instance_min,
+                target_object_instance_max,
+                object_type,
+                object_instance,
+            );
         }
-    }
+    })?;
 
-    BACNET_STACK_INIT.call_once(|| unsafe {
-        bip_cleanup();
-        init_service_handlers();
-        address_init();
-        dlenv_init();
-    });
-
-    let mut src = BACNET_ADDRESS::default();
-    let mut rx_buf = [0u8; MAX_MPDU as usize];
-    let bip_timeout = 100; // ms
-    unsafe {
-        Send_WhoHas_Object(
-            target_object_instance_min,
-            target_object_instance_max,
-            object_type,
-            object_instance,
-        );
-    }
     let start = Instant::now();
-    let mut i = 0;
-    while start.elapsed() < timeout {
-        let pdu_len = unsafe {
-            bip_receive(
-                &mut src as *mut _,
-                &mut rx_buf as *mut _,
-                MAX_MPDU as u16,
-                bip_timeout,
-            )
-        };
-        if pdu_len > 0 {
-            unsafe {
-                npdu_handler(&mut src as *mut _, &mut rx_buf as *mut _, pdu_len);
-            }
+    let mut devices = vec![];
+    while let Some(time_left) = timeout.checked_sub(start.elapsed()) {
+        match messages.recv_timeout(time_left) {
+            Ok(UnsolicitedMessage::IHave(data)) => devices.push(data),
+            Ok(_) => {}
+            Err(_) => break,
         }
-
-        i += 1;
     }
-    trace!("Looped {} times", i);
+    trace!("Got {} I-Have responses", devices.len());
+    Ok(devices)
 }
@@ -2,38 +2,30 @@
 //!
 //! Design is like a builder with different parameters and returns
 
-// So the design of the BACnet stack is a little annoying in that we have to drive the subsystem
-// forward, continually called bip_receive(). Each device that's discovered is processed by the
-// my_i_am_handler, and we need to a global list of discovered devices.
-//
-// In effect, this library is not thread-safe, so we need to make sure that only one WhoIs client
-// is running at a time.
+// Each device that's discovered is processed by the i_am_handler, which hands it to the network
+// as an unsolicited message. We listen for those for as long as the timeout lasts.
 
 use crate::{
-    errors::{BACnetErr, Result},
-    init_service_handlers, BACNET_STACK_INIT,
-};
-use bacnet_sys::{
-    address_init, bip_cleanup, bip_get_broadcast_address, bip_receive, dlenv_init,
-    iam_decode_service_request, npdu_handler, Send_WhoIs_To_Network, BACNET_ADDRESS, MAX_MPDU,
+    datalink,
+    errors::Result,
+    network::{publish, UnsolicitedMessage},
+    BACnetNetwork, MacAddress,
 };
+use bacnet_sys::{iam_decode_service_request, Send_WhoIs_To_Network, BACNET_ADDRESS};
 use log::{debug, error, trace};
-use once_cell::sync::Lazy;
-use std::{
-    sync::Mutex,
-    time::{Duration, Instant},
-};
-
-static DISCOVERED_DEVICES: Lazy<Mutex<Vec<IAmDevice>>> = Lazy::new(|| Mutex::new(vec![]));
+use std::time::{Duration, Instant};
 
 /// A BACnet device that responded with I-Am in response to the Who-Is we sent out.
+#[derive(Debug, Clone)]
 pub struct IAmDevice {
     pub device_id: u32,
     pub max_apdu: u32,
     pub vendor_id: u16,
-    pub mac_addr: [u8; 6],
+    /// The MAC address the I-Am came from: the device itself, or the router to its network
+    pub mac_addr: MacAddress,
     pub network_number: u16,
-    pub addr: [u8; 6],
+    /// The address of the device on its network, empty for a device on our own network
+    pub addr: MacAddress,
 }
 
 pub struct WhoIs {
@@ -66,17 +58,7 @@ impl WhoIs {
 
     pub fn execute(self) -> Result<Vec<IAmDevice>> {
         let WhoIs { timeout, subnet } = self;
-
-        // create an object with a Drop impl that calls bip_cleanup
-        whois(timeout, subnet);
-
-        let devices = if let Ok(mut lock) = DISCOVERED_DEVICES.lock() {
-            lock.drain(..).collect()
-        } else {
-            return Err(BACnetErr::CouldntGetLock);
-        };
-
-        Ok(devices)
+        whois(timeout, subnet)
     }
 }
 
@@ -120,80 +102,61 @@ pub unsafe extern "C" fn i_am_handler(
         "device_id = {} max_apdu = {} vendor_id = {}",
         device_id, max_apdu, vendor_id
     );
-    let mac_len = unsafe { (*src).mac_len } as usize;
-    let mut mac_addr = [0u8; 6];
-    mac_addr[..mac_len].copy_from_slice(unsafe { &(*src).mac[..mac_len] });
-    let network_number = unsafe { (*src).net };
-
-    let mut addr = [0u8; 6];
-    if network_number > 0 {
-        let adr_len = unsafe { (*src).len } as usize;
-        addr[..adr_len].copy_from_slice(unsafe { &(*src).adr[..adr_len] });
-    }
+    let src = unsafe { &*src };
+    let mac_addr = MacAddress::from_mac(src);
+    let network_number = src.net;
+    let addr = if network_number > 0 {
+        MacAddress::from_adr(src)
+    } else {
+        MacAddress::default()
+    };
 
-    debug!("MAC = {:02X?}", mac_addr);
-    if let Ok(mut lock) = DISCOVERED_DEVICES.lock() {
-        lock.push(IAmDevice {
-            device_id,
-            max_apdu,
-            vendor_id,
-            mac_addr,
-            network_number,
-            addr,
-        });
-    }
+    debug!("MAC = {}", mac_addr);
+    publish(UnsolicitedMessage::IAm(IAmDevice {
+        device_id,
+        max_apdu,
+        vendor_id,
+        mac_addr,
+        network_number,
+        addr,
+    }));
 }
 
 // TODO(tj): Handle duplicates. A duplicate is pretty much a device ID we've already seen, from
 // what I understand.
-fn whois(timeout: Duration, subnet: Option<u16>) {
-    let mut dest = BACNET_ADDRESS::default();
-    let target_object_instance_min = -1i32; // TODO(tj): parameterize?
-    let target_object_instance_max = -1i32; // TODO(tj): parameterize?
+fn whois(timeout: Duration, subnet: Option<u16>) -> Result<Vec<IAmDevice>> {
+    let network = BACnetNetwork::get()?;
+    // Listen before asking, so no answer is missed
+    let messages = network.subscribe();
+    network.run(move || {
+        let mut dest = BACNET_ADDRESS::default();
+        let target_object_instance_min = -1i32; // TODO(tj): parameterize?
+        let target_object_instance_max = -1i32; // TODO(tj): parameterize?
+
+        if let Some(subnet) = subnet {
+            dest.net = subnet;
+        } else {
+            datalink::get_broadcast_address(&mut dest);
+        }
 
-    if let Some(subnet) = subnet {
-        dest.net = subnet;
-    } else {
         unsafe {
-            bip_get_broadcast_address(&mut dest as *mut _);
+            Send_WhoIs_To_Network(
+                &mut dest as *mut _,
+                target_object_instance_min,
+                target_object_instance_max,
+            );
         }
-    }
+    })?;
 
-    BACNET_STACK_INIT.call_once(|| unsafe {
-        bip_cleanup();
-        init_service_handlers();
-        address_init();
-        dlenv_init();
-    });
-
-    let mut src = BACNET_ADDRESS::default();
-    let mut rx_buf = [0u8; MAX_MPDU as usize];
-    let bip_timeout = 100; // ms
-    unsafe {
-        Send_WhoIs_To_Network(
-            &mut dest as *mut _,
-            target_object_instance_min,
-            target_object_instance_max,
-        );
-    }
     let start = Instant::now();
-    let mut i = 0;
-    while start.elapsed() < timeout {
-        let pdu_len = unsafe {
-            bip_receive(
-                &mut src as *mut _,
-                &mut rx_buf as *mut _,
-                MAX_MPDU as u16,
-                bip_timeout,
-            )
-        };
-        if pdu_len > 0 {
-            unsafe {
-                npdu_handler(&mut src as *mut _, &mut rx_buf as *mut _, pdu_len);
-            }
+    let mut devices = vec![];
+    while let Some(time_left) = timeout.checked_sub(start.elapsed()) {
+        match messages.recv_timeout(time_left) {
+            Ok(UnsolicitedMessage::IAm(device)) => devices.push(device),
+            Ok(_) => {}
+            Err(_) => break,
         }
-
-        i += 1;
     }
-    trace!("Looped {} times", i);
+    trace!("Got {} I-Am responses", devices.len());
+    Ok(devices)
 }
@@ -0,0 +1,82 @@
+// Foreign device registration, against a stand-in for a BBMD on the loopback interface
+//
+// There's a single network per process, so everything is checked in one test.
+
+use bacnet::{errors::BACnetErr, BACnetNetwork, DatalinkConfig};
+use std::{
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+const BVLC_RESULT: u8 = 0x00;
+const BVLC_REGISTER_FOREIGN_DEVICE: u8 = 0x05;
+const TTL: Duration = Duration::from_secs(60);
+
+// Wait for a registration, and answer it with a BVLC-Result carrying `code` (if any)
+fn answer(bbmd: &UdpSocket, code: Option<u16>) -> JoinHandle<()> {
+    let bbmd = bbmd.try_clone().unwrap();
+    thread::spawn(move || {
+        let mut buf = [0; 64];
+        let (len, src) = bbmd.recv_from(&mut buf).unwrap();
+        assert_eq!(len, 6);
+        assert_eq!(buf[1], BVLC_REGISTER_FOREIGN_DEVICE);
+        assert_eq!(u16::from_be_bytes([buf[4], buf[5]]), TTL.as_secs() as u16);
+        if let Some(code) = code {
+            let [high, low] = code.to_be_bytes();
+            bbmd.send_to(&[0x81, BVLC_RESULT, 0, 6, high, low], src)
+                .unwrap();
+        }
+    })
+}
+
+fn address(bbmd: &UdpSocket) -> SocketAddrV4 {
+    match bbmd.local_addr().unwrap() {
+        SocketAddr::V4(address) => address,
+        address => panic!("not an IPv4 address: {}", address),
+    }
+}
+
+fn register(network: &BACnetNetwork, bbmd: &UdpSocket, code: Option<u16>) -> Result<(), BACnetErr> {
+    let standin = answer(bbmd, code);
+    let result = network.register_foreign_device(address(bbmd), TTL);
+    standin.join().unwrap();
+    result
+}
+
+#[test]
+fn register_foreign_device() {
+    let network = BACnetNetwork::start(
+        DatalinkConfig::new()
+            .interface("lo")
+            .address(Ipv4Addr::LOCALHOST)
+            .port(47899),
+    )
+    .unwrap();
+    let bbmd = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0)).unwrap();
+    bbmd.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+    // Accepted
+    assert!(register(network, &bbmd, Some(0x0000)).is_ok());
+    assert_eq!(network.foreign_device_bbmd(), Some(address(&bbmd)));
+
+    // Registering again, with a BBMD that doesn't answer this time. The answer to the first
+    // registration is still the last result the stack got.
+    assert!(matches!(
+        register(network, &bbmd, None),
+        Err(BACnetErr::NoResponse { .. })
+    ));
+
+    // A result that isn't about the registration is no answer either
+    assert!(matches!(
+        register(network, &bbmd, Some(0x0060)),
+        Err(BACnetErr::NoResponse { .. })
+    ));
+
+    // Refused
+    assert!(matches!(
+        register(network, &bbmd, Some(0x0030)),
+        Err(BACnetErr::BvlcNak { code: 0x0030, .. })
+    ));
+    assert_eq!(network.foreign_device_bbmd(), None);
+}